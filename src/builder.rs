@@ -1,40 +1,106 @@
 #[cfg(unix)]
 mod os {
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileExt;
+
+    use crate::posix;
+
+    pub use crate::posix::FileMapping;
+
     pub const NULL_DEVICE: &str = "/dev/null";
     pub const SHELL: [&str; 2] = ["sh", "-c"];
+
+    pub fn os_str_bytes(s: &OsStr) -> Vec<u8> {
+        s.as_bytes().to_vec()
+    }
+
+    pub fn env_key_eq(a: &OsStr, b: &OsStr) -> bool {
+        a == b
+    }
+
+    pub fn map_file(file: &File, offset: u64, len: usize) -> io::Result<FileMapping> {
+        posix::mmap_file(file, offset, len)
+    }
+
+    pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        file.read_at(buf, offset)
+    }
 }
 
 #[cfg(windows)]
 mod os {
+    use std::cmp::Ordering;
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::FileExt;
+
+    use crate::win32;
+
+    pub use crate::win32::FileMapping;
+
     pub const NULL_DEVICE: &str = "nul";
     pub const SHELL: [&str; 2] = ["cmd.exe", "/c"];
+
+    pub fn os_str_bytes(s: &OsStr) -> Vec<u8> {
+        s.encode_wide().flat_map(u16::to_le_bytes).collect()
+    }
+
+    // Environment variable names are case-insensitive on Windows, in
+    // all of Unicode, not just ASCII.
+    pub fn env_key_eq(a: &OsStr, b: &OsStr) -> bool {
+        win32::compare_env_keys(a, b) == Ordering::Equal
+    }
+
+    pub fn map_file(file: &File, offset: u64, len: usize) -> io::Result<FileMapping> {
+        win32::MapViewOfFile(file, offset, len)
+    }
+
+    pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        file.seek_read(buf, offset)
+    }
 }
 
-pub use self::exec::{CaptureData, Exec, NullFile};
-pub use self::os::*;
+pub use self::exec::{
+    CaptureData, Encoding, Exec, ExecPlan, MmapFile, NullFile, SpooledCaptureData,
+};
 pub use self::pipeline::Pipeline;
 
 #[cfg(unix)]
 pub use exec::unix;
+#[cfg(windows)]
+pub use exec::windows;
 
 mod exec {
     use std::borrow::Cow;
-    use std::collections::HashMap;
-    use std::ffi::{OsStr, OsString};
+    use std::collections::{BTreeMap, HashMap, HashSet};
     use std::env;
+    use std::ffi::{OsStr, OsString};
     use std::fmt;
     use std::fs::{File, OpenOptions};
-    use std::io::{self, Read, Write};
+    use std::io::{self, BufRead, BufReader, Read, Write};
     use std::ops::BitOr;
-    use std::path::Path;
-
-    use crate::communicate::Communicator;
-    use crate::os_common::ExitStatus;
-    use crate::popen::{Popen, PopenConfig, Redirection, Result as PopenResult};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::communicate::{CaptureSink, Communicator, TempFile};
+    use crate::os_common::{ExitStatus, StandardStream};
+    use crate::popen::{
+        format_response_file, is_terminal, Popen, PopenConfig, PopenError, Redirection,
+        Result as PopenResult,
+    };
 
     use super::os::*;
     use super::Pipeline;
 
+    type ChunkIter = Box<dyn Iterator<Item = Vec<u8>> + Send>;
+
     /// A builder for [`Popen`] instances, providing control and
     /// convenience methods.
     ///
@@ -121,13 +187,24 @@ mod exec {
     ///
     /// [`Popen`]: struct.Popen.html
     /// [`Popen::create`]: struct.Popen.html#method.create
-
     #[must_use]
     pub struct Exec {
         command: OsString,
         args: Vec<OsString>,
+        file_args: Vec<OsString>,
         config: PopenConfig,
+        // the environment of the current process that config.env was
+        // initialized from, unless it was cleared
+        inherited_env: Option<Vec<(OsString, OsString)>>,
         stdin_data: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+        stderr_callback: Option<Arc<Mutex<dyn FnMut(&[u8]) + Send>>>,
+        stdout_shared: Option<Arc<Mutex<Vec<u8>>>>,
+        stdin_iter: Option<Arc<Mutex<Option<ChunkIter>>>>,
+        stdin_mmap: Option<Arc<File>>,
+        output_prefix: Option<String>,
+        retry: Option<(u32, Duration)>,
+        open_error: Option<io::Error>,
     }
 
     impl Exec {
@@ -145,8 +222,18 @@ mod exec {
             Exec {
                 command: command.as_ref().to_owned(),
                 args: vec![],
+                file_args: vec![],
                 config: PopenConfig::default(),
+                inherited_env: None,
                 stdin_data: None,
+                timeout: None,
+                stderr_callback: None,
+                stdout_shared: None,
+                stdin_iter: None,
+                stdin_mmap: None,
+                output_prefix: None,
+                retry: None,
+                open_error: None,
             }
         }
 
@@ -184,6 +271,61 @@ mod exec {
             self
         }
 
+        /// Passes `args` to the program through a response file.
+        ///
+        /// When the process is started, `args` are written to a
+        /// temporary file, and a single `@path` argument naming the file
+        /// is passed instead of them.  This works around the limit on
+        /// the total size of the command line (`ARG_MAX` on Unix, 32K
+        /// characters on Windows) when passing a large number of
+        /// arguments, such as file names.
+        ///
+        /// This only works with programs that understand the `@file`
+        /// convention, such as GCC, Clang, `javac`, and the MSVC
+        /// toolchain; other programs will receive the `@path` argument
+        /// as-is.  The arguments are written one per line, quoted so
+        /// that whitespace, quotes, and backslashes survive: with
+        /// backslash escapes on Unix, and with the command-line quoting
+        /// rules on Windows.
+        ///
+        /// The `@path` argument is placed after all arguments given with
+        /// [`arg`] and [`args`].  Calling this method more than once
+        /// adds to the same file.  The file is removed after the process
+        /// has finished, when it is waited for or when the `Popen` is
+        /// dropped.  On Unix-like systems it is created with mode 0600,
+        /// so that other users cannot read the arguments.
+        ///
+        /// [`arg`]: struct.Exec.html#method.arg
+        /// [`args`]: struct.Exec.html#method.args
+        pub fn args_via_file(mut self, args: &[impl AsRef<OsStr>]) -> Exec {
+            self.file_args
+                .extend(args.iter().map(|x| x.as_ref().to_owned()));
+            self
+        }
+
+        /// Runs the command through a wrapper program.
+        ///
+        /// `wrapper` is the argument vector of the wrapper, which is
+        /// prepended to the command and its arguments.  For example,
+        /// `Exec::cmd("make").wrap_with(&["nice", "-n", "10"])` runs
+        /// `nice -n 10 make`.  Calling this with an empty `wrapper` has
+        /// no effect.
+        ///
+        /// Other settings, such as the environment and the working
+        /// directory, apply to the wrapper, which typically passes them
+        /// on to the wrapped command.
+        pub fn wrap_with(mut self, wrapper: &[impl AsRef<OsStr>]) -> Exec {
+            if let Some((wrapper_cmd, wrapper_args)) = wrapper.split_first() {
+                let mut args: Vec<OsString> =
+                    wrapper_args.iter().map(|x| x.as_ref().to_owned()).collect();
+                args.push(self.command);
+                args.append(&mut self.args);
+                self.command = wrapper_cmd.as_ref().to_owned();
+                self.args = args;
+            }
+            self
+        }
+
         /// Specifies that the process is initially detached.
         ///
         /// A detached process means that we will not wait for the
@@ -194,18 +336,69 @@ mod exec {
             self
         }
 
+        /// Limits the time the terminal operations may take.
+        ///
+        /// With a timeout set, [`join`] and [`capture`] kill the process
+        /// and wait for it to exit if it doesn't finish within `dur`,
+        /// and then return an error of kind `ErrorKind::TimedOut`.
+        /// [`communicate`] applies the timeout as the default time limit
+        /// of the returned `Communicator`; a time limit set explicitly
+        /// with [`Communicator::limit_time`] takes precedence over it.
+        ///
+        /// [`join`]: struct.Exec.html#method.join
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`communicate`]: struct.Exec.html#method.communicate
+        /// [`Communicator::limit_time`]: struct.Communicator.html#method.limit_time
+        pub fn timeout(mut self, dur: Duration) -> Exec {
+            self.timeout = Some(dur);
+            self
+        }
+
+        /// Runs the command again if it fails.
+        ///
+        /// If the command can't be started, times out, or exits with a
+        /// non-zero status, [`join`] and [`capture`] run it again, up to
+        /// `attempts` more times, sleeping for `backoff` before each
+        /// retry.  They return the result of the first successful
+        /// attempt, or of the last attempt if none succeeded; the output
+        /// of failed attempts is discarded.  This is useful for commands
+        /// that fail intermittently, such as network fetches.
+        ///
+        /// Each attempt runs a fresh copy of the command, including the
+        /// input data given with [`stdin`].  Standard input redirected
+        /// from a `File` can't be replayed, so combining it with `retry`
+        /// makes `join` and `capture` fail with
+        /// `PopenError::LogicError`.  Output redirected to a `File` is
+        /// shared by all attempts.
+        ///
+        /// [`join`]: struct.Exec.html#method.join
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        pub fn retry(mut self, attempts: u32, backoff: Duration) -> Exec {
+            self.retry = Some((attempts, backoff));
+            self
+        }
+
         fn ensure_env(&mut self) {
             if self.config.env.is_none() {
-                self.config.env = Some(PopenConfig::current_env());
+                let env = PopenConfig::current_env();
+                self.inherited_env = Some(env.clone());
+                self.config.env = Some(env);
             }
         }
 
         /// Clears the environment of the subprocess.
         ///
         /// When this is invoked, the subprocess will not inherit the
-        /// environment of this process.
+        /// environment of this process.  Variables set by subsequent
+        /// calls to [`env`] and [`env_extend`] make up its whole
+        /// environment.
+        ///
+        /// [`env`]: struct.Exec.html#method.env
+        /// [`env_extend`]: struct.Exec.html#method.env_extend
         pub fn env_clear(mut self) -> Exec {
             self.config.env = Some(vec![]);
+            self.inherited_env = None;
             self
         }
 
@@ -240,7 +433,7 @@ mod exec {
             self.ensure_env();
             {
                 let envvec = self.config.env.as_mut().unwrap();
-                for &(ref k, ref v) in vars {
+                for (k, v) in vars {
                     envvec.push((k.as_ref().to_owned(), v.as_ref().to_owned()));
                 }
             }
@@ -250,13 +443,50 @@ mod exec {
         /// Removes an environment variable from the child process.
         ///
         /// Other environment variables are inherited by default.
+        /// Removing a variable that is not set has no effect.  On
+        /// Windows, where environment variable names are
+        /// case-insensitive, `key` is also compared case-insensitively.
         pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Exec {
             self.ensure_env();
             self.config
                 .env
                 .as_mut()
                 .unwrap()
-                .retain(|&(ref k, ref _v)| k != key.as_ref());
+                .retain(|(k, _v)| !env_key_eq(k, key.as_ref()));
+            self
+        }
+
+        /// Keeps only the environment variables for which `pred`
+        /// returns true.
+        ///
+        /// `pred` is called with the name and value of each variable
+        /// and decides whether the child receives it.  This allows
+        /// inheriting the environment of the current process except for
+        /// a class of variables, e.g. stripping credentials before
+        /// running an untrusted program:
+        ///
+        /// ```no_run
+        /// # use subprocess::*;
+        /// let exec = Exec::cmd("helper")
+        ///     .env_filter(|name, _| !name.to_string_lossy().starts_with("AWS_"));
+        /// ```
+        ///
+        /// The filter applies to the environment as configured so far,
+        /// which is the inherited environment unless it was modified by
+        /// earlier calls.  Variables set by subsequent calls to [`env`]
+        /// and [`env_extend`] are not filtered, so the filter can be
+        /// used to prune the inherited environment before setting
+        /// variables explicitly.
+        ///
+        /// [`env`]: struct.Exec.html#method.env
+        /// [`env_extend`]: struct.Exec.html#method.env_extend
+        pub fn env_filter(mut self, pred: impl Fn(&OsStr, &OsStr) -> bool) -> Exec {
+            self.ensure_env();
+            self.config
+                .env
+                .as_mut()
+                .unwrap()
+                .retain(|(k, v)| pred(k, v));
             self
         }
 
@@ -269,6 +499,22 @@ mod exec {
             self
         }
 
+        /// Specifies the executable to run, separately from `argv[0]`.
+        ///
+        /// By default the command given to [`cmd`] is both executed and
+        /// passed to the program as `argv[0]`.  With this method, the
+        /// command is still passed as `argv[0]`, but `exe` is executed
+        /// instead, which is useful for programs that behave differently
+        /// depending on the name they are invoked under.  As with the
+        /// command, `exe` is looked up in the `PATH` if it doesn't
+        /// contain a path separator.
+        ///
+        /// [`cmd`]: struct.Exec.html#method.cmd
+        pub fn executable(mut self, exe: impl AsRef<OsStr>) -> Exec {
+            self.config.executable = Some(exe.as_ref().to_owned());
+            self
+        }
+
         /// Specifies how to set up the standard input of the child process.
         ///
         /// Argument can be:
@@ -279,10 +525,14 @@ mod exec {
         ///   for stdin, making sure that `capture` feeds that data into the
         ///   standard input of the subprocess;
         /// * [`NullFile`], which will redirect the standard input to read from
-        ///    `/dev/null`.
+        ///   `/dev/null`;
+        /// * [`MmapFile`], which will set up a `Redirection::Pipe` for
+        ///   stdin, and feed it the contents of the file from a memory
+        ///   mapping once the process is started.
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
+        /// [`MmapFile`]: struct.MmapFile.html
         pub fn stdin(mut self, stdin: impl Into<InputRedirection>) -> Exec {
             match (&self.config.stdin, stdin.into()) {
                 (&Redirection::None, InputRedirection::AsRedirection(new)) => {
@@ -293,11 +543,52 @@ mod exec {
                     self.config.stdin = Redirection::Pipe;
                     self.stdin_data = Some(data);
                 }
+                (&Redirection::None, InputRedirection::MmapFile(file)) => {
+                    self.config.stdin = Redirection::Pipe;
+                    self.stdin_mmap = Some(Arc::new(file));
+                }
                 (_, _) => panic!("stdin is already set"),
             }
             self
         }
 
+        /// Feeds the standard input of the child process with chunks
+        /// produced by `iter`.
+        ///
+        /// This sets up `stdin(Redirection::Pipe)` and, once the process
+        /// is started, a thread that pulls chunks from `iter` and writes
+        /// them to the child's standard input, closing it when `iter` is
+        /// exhausted.  Unlike passing a `Vec<u8>` to [`stdin`], this
+        /// doesn't require the whole input to be generated up front, and
+        /// since the thread blocks while the child's input pipe is full,
+        /// `iter` is never advanced faster than the child consumes the
+        /// data.  If the child exits without reading all of its input,
+        /// the thread stops without exhausting `iter`.
+        ///
+        /// The thread is joined by [`Popen::wait`].  The iterator can only
+        /// be consumed once, so if the `Exec` is cloned, only the first
+        /// of the clones to be started receives the input, and [`retry`]
+        /// can't be used.
+        ///
+        /// # Panics
+        ///
+        /// If the standard input was already set up.
+        ///
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        /// [`retry`]: struct.Exec.html#method.retry
+        /// [`Popen::wait`]: struct.Popen.html#method.wait
+        pub fn stdin_from_iter(
+            mut self,
+            iter: impl Iterator<Item = Vec<u8>> + Send + 'static,
+        ) -> Exec {
+            match self.config.stdin {
+                Redirection::None => self.config.stdin = Redirection::Pipe,
+                _ => panic!("stdin is already set"),
+            }
+            self.stdin_iter = Some(Arc::new(Mutex::new(Some(Box::new(iter)))));
+            self
+        }
+
         /// Specifies how to set up the standard output of the child process.
         ///
         /// Argument can be:
@@ -305,7 +596,7 @@ mod exec {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * [`NullFile`], which will redirect the standard output to go to
-        ///    `/dev/null`.
+        ///   `/dev/null`.
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
@@ -325,7 +616,7 @@ mod exec {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * [`NullFile`], which will redirect the standard error to go to
-        ///    `/dev/null`.
+        ///   `/dev/null`.
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
@@ -338,6 +629,287 @@ mod exec {
             self
         }
 
+        /// Redirects the standard input of the child process to read from
+        /// the file at `path`.
+        ///
+        /// This is a shorthand for opening the file with `File::open`
+        /// and passing it to [`stdin`].  The file is opened right away,
+        /// but if opening it fails, the error is reported when the
+        /// process is started, e.g. by [`popen`] or [`capture`].
+        ///
+        /// # Panics
+        ///
+        /// If the standard input was already set up.
+        ///
+        /// [`stdin`]: struct.Exec.html#method.stdin
+        /// [`popen`]: struct.Exec.html#method.popen
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn stdin_path(self, path: impl AsRef<Path>) -> Exec {
+            match File::open(path) {
+                Ok(file) => self.stdin(file),
+                Err(e) => self.defer_open_error(e),
+            }
+        }
+
+        /// Redirects the standard output of the child process to the file
+        /// at `path`.
+        ///
+        /// This is a shorthand for opening the file with `File::create`,
+        /// which creates it or truncates it if it exists, and passing it
+        /// to [`stdout`].  Errors are reported as with [`stdin_path`].
+        ///
+        /// # Panics
+        ///
+        /// If the standard output was already set up.
+        ///
+        /// [`stdout`]: struct.Exec.html#method.stdout
+        /// [`stdin_path`]: struct.Exec.html#method.stdin_path
+        pub fn stdout_path(self, path: impl AsRef<Path>) -> Exec {
+            match File::create(path) {
+                Ok(file) => self.stdout(file),
+                Err(e) => self.defer_open_error(e),
+            }
+        }
+
+        /// Redirects the standard error of the child process to the file
+        /// at `path`.
+        ///
+        /// Like [`stdout_path`], but for the standard error.
+        ///
+        /// # Panics
+        ///
+        /// If the standard error was already set up.
+        ///
+        /// [`stdout_path`]: struct.Exec.html#method.stdout_path
+        pub fn stderr_path(self, path: impl AsRef<Path>) -> Exec {
+            match File::create(path) {
+                Ok(file) => self.stderr(file),
+                Err(e) => self.defer_open_error(e),
+            }
+        }
+
+        // Remember an error from opening a redirection file, to be
+        // reported when the process is started.
+        fn defer_open_error(mut self, e: io::Error) -> Exec {
+            if self.open_error.is_none() {
+                self.open_error = Some(e);
+            }
+            self
+        }
+
+        /// Captures the standard output unless it's a terminal.
+        ///
+        /// If the standard output of the current process is a terminal,
+        /// it is left to be inherited by the child, so that the child can
+        /// use colors and progress indicators.  Otherwise, e.g. when the
+        /// output of the current process is piped or redirected to a
+        /// file, this is equivalent to `stdout(Redirection::Pipe)`.  The
+        /// check is made when this method is called, using `isatty()` on
+        /// Unix-like systems and `GetConsoleMode()` on Windows.
+        ///
+        /// Whether the output was captured can be checked on the
+        /// resulting `Popen` with [`has_stdout_pipe`].
+        ///
+        /// # Panics
+        ///
+        /// If the output is to be captured and stdout has already been
+        /// set to a different redirection.
+        ///
+        /// [`has_stdout_pipe`]: struct.Popen.html#method.has_stdout_pipe
+        pub fn stdout_auto(self) -> Exec {
+            if is_terminal(StandardStream::Output) {
+                self
+            } else {
+                self.stdout(Redirection::Pipe)
+            }
+        }
+
+        /// Captures the standard error unless it's a terminal.
+        ///
+        /// This is like [`stdout_auto`], but checks the standard error
+        /// of the current process and sets up the standard error of the
+        /// child.
+        ///
+        /// [`stdout_auto`]: struct.Exec.html#method.stdout_auto
+        pub fn stderr_auto(self) -> Exec {
+            if is_terminal(StandardStream::Error) {
+                self
+            } else {
+                self.stderr(Redirection::Pipe)
+            }
+        }
+
+        /// Sends the standard output and error of the process to a
+        /// single pipe.
+        ///
+        /// This is a shorthand for
+        /// `stdout(Redirection::Pipe).stderr(Redirection::Merge)`, the
+        /// equivalent of `2>&1` in the shell.  Both of the child's
+        /// streams refer to the write end of the same pipe, so the
+        /// parent reads a single stream in which the output appears in
+        /// the order the child wrote it.  With [`capture`], the combined
+        /// output is available through `stdout` and `stdout_str()`.
+        ///
+        /// Note that a program writing to a pipe typically buffers its
+        /// standard output but not its standard error, which can reorder
+        /// the output compared to running it in a terminal.
+        ///
+        /// # Panics
+        ///
+        /// If stdout or stderr has already been set to a different
+        /// redirection.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn combine_output(self) -> Exec {
+            self.stdout(Redirection::Pipe).stderr(Redirection::Merge)
+        }
+
+        /// Passes the standard error of the process to `callback`.
+        ///
+        /// This sets up `stderr(Redirection::Pipe)` and starts a thread
+        /// that reads the standard error and invokes `callback` with
+        /// each chunk of data as it is read, e.g. to route the child's
+        /// diagnostics into a logging framework.  The chunks are not
+        /// aligned to line boundaries.
+        ///
+        /// Since `callback` runs in a separate thread, it must be
+        /// `Send`.  The thread is joined by [`Popen::wait`] (and
+        /// therefore by [`join`] and [`capture`]), so all of the
+        /// standard error has been passed to `callback` by the time the
+        /// process is reported as finished.  `wait_timeout` and `poll`
+        /// wait for the thread only until their timeout, and report the
+        /// process as still running until the thread is done, which
+        /// takes longer than the process itself if a descendant has
+        /// inherited its standard error.  If the `Exec` is cloned,
+        /// the clones share the callback, and it is never invoked
+        /// concurrently.
+        ///
+        /// [`Popen::wait`]: struct.Popen.html#method.wait
+        /// [`join`]: struct.Exec.html#method.join
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn stderr_to_callback(mut self, callback: impl FnMut(&[u8]) + Send + 'static) -> Exec {
+            self.stderr_callback = Some(Arc::new(Mutex::new(callback)));
+            self
+        }
+
+        /// Appends the standard output of the process to `buf` as it
+        /// is produced.
+        ///
+        /// This sets up `stdout(Redirection::Pipe)` and starts a thread
+        /// that reads the standard output and appends each chunk to
+        /// `buf`, so that another thread (e.g. a UI showing progress)
+        /// can inspect the output collected so far while the process
+        /// is still running.  The output is not available through
+        /// [`capture`] or `Popen::stdout`.
+        ///
+        /// The reader thread takes the lock once per chunk and holds it
+        /// only long enough to copy the chunk.  Conversely, holding the
+        /// lock for a long time while inspecting the buffer stalls the
+        /// reader, and once the pipe buffer fills up, the child itself.
+        /// Inspecting code should therefore copy or process what it
+        /// needs and release the lock promptly.  The thread is joined by
+        /// [`Popen::wait`], so `buf` contains the complete output by the
+        /// time the process is reported as finished.
+        ///
+        /// [`Popen::wait`]: struct.Popen.html#method.wait
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn stdout_into_shared(mut self, buf: Arc<Mutex<Vec<u8>>>) -> Exec {
+            self.stdout_shared = Some(buf);
+            self
+        }
+
+        /// Prefixes each line of output forwarded to the parent with
+        /// `prefix`.
+        ///
+        /// This is meant for tools that run several commands at once and
+        /// show their output live, such as `make -j` or a process
+        /// supervisor, where it helps tell apart the lines printed by
+        /// different commands, e.g. with `prefix_output("[build] ")`.
+        ///
+        /// The prefix only applies to the standard output and error that
+        /// are forwarded, i.e. not redirected.  Such streams are piped
+        /// through a thread that reads the child's output line by line
+        /// and writes each line, preceded by `prefix`, to the standard
+        /// output or error of the current process.  A line is written
+        /// with a single call, so lines of concurrently running commands
+        /// don't get mixed up.  Output that is captured, such as the
+        /// standard output in [`capture`], or redirected elsewhere is not
+        /// modified.  Merged standard error is prefixed along with the
+        /// standard output it is merged into.
+        ///
+        /// The thread is joined by [`Popen::wait`], so all of the output
+        /// has been forwarded by the time the process is reported as
+        /// finished.
+        ///
+        /// [`Popen::wait`]: struct.Popen.html#method.wait
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn prefix_output(mut self, prefix: impl Into<String>) -> Exec {
+            self.output_prefix = Some(prefix.into());
+            self
+        }
+
+        // Copy `src` to `dest` line by line, prepending `prefix` to each
+        // line.
+        fn forward_with_prefix(src: File, prefix: &str, mut dest: impl Write) {
+            let mut src = BufReader::new(src);
+            let mut line = prefix.as_bytes().to_vec();
+            while let Ok(n) = src.read_until(b'\n', &mut line) {
+                if n == 0 || dest.write_all(&line).and_then(|_| dest.flush()).is_err() {
+                    break;
+                }
+                line.truncate(prefix.len());
+            }
+        }
+
+        // Write the contents of `file` to `dest` from memory mappings of
+        // the file, as described at MmapFile.
+        fn write_mapped(file: &File, dest: &mut File) -> io::Result<()> {
+            // multiples of both the page size and the allocation
+            // granularity of Windows
+            const WINDOW: usize = 64 << 20;
+            const CHUNK: usize = 1 << 20;
+            if !file.metadata()?.is_file() {
+                io::copy(&mut &*file, dest)?;
+                return Ok(());
+            }
+            let mut offset = 0u64;
+            'mapped: loop {
+                let size = file.metadata()?.len();
+                if offset >= size {
+                    break;
+                }
+                let len = (size - offset).min(WINDOW as u64) as usize;
+                let mapping = match map_file(file, offset, len) {
+                    Ok(mapping) => mapping,
+                    // e.g. out of address space
+                    Err(_) => break,
+                };
+                for start in (0..len).step_by(CHUNK) {
+                    let end = len.min(start + CHUNK);
+                    // Reading the mapping past the end of the file would
+                    // kill the process with SIGBUS on Unix.
+                    if file.metadata()?.len() < offset + end as u64 {
+                        offset += start as u64;
+                        break 'mapped;
+                    }
+                    dest.write_all(&mapping.as_slice()[start..end])?;
+                }
+                offset += len as u64;
+            }
+            // copy whatever couldn't be mapped
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = match read_at(file, &mut buf, offset) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                dest.write_all(&buf[..n])?;
+                offset += n as u64;
+            }
+        }
+
         fn check_no_stdin_data(&self, meth: &str) {
             if self.stdin_data.is_some() {
                 panic!("{} called with input data specified", meth);
@@ -349,8 +921,97 @@ mod exec {
         /// Starts the process, returning a `Popen` for the running process.
         pub fn popen(mut self) -> PopenResult<Popen> {
             self.check_no_stdin_data("popen");
+            if let Some(e) = self.open_error.take() {
+                return Err(e.into());
+            }
+            if self.stderr_callback.is_some() {
+                self.config.stderr = Redirection::Pipe;
+            }
+            if self.stdout_shared.is_some() {
+                self.config.stdout = Redirection::Pipe;
+            }
+            let (mut prefix_stdout, mut prefix_stderr) = (false, false);
+            if self.output_prefix.is_some() {
+                if let Redirection::None = self.config.stdout {
+                    self.config.stdout = Redirection::Pipe;
+                    prefix_stdout = true;
+                }
+                if let Redirection::None = self.config.stderr {
+                    self.config.stderr = Redirection::Pipe;
+                    prefix_stderr = true;
+                }
+            }
+            let mut response_file = None;
+            if !self.file_args.is_empty() {
+                let mut file = TempFile::create()?;
+                file.file
+                    .write_all(&format_response_file(&self.file_args))?;
+                let mut arg = OsString::from("@");
+                arg.push(&file.path);
+                self.args.push(arg);
+                response_file = Some(file);
+            }
             self.args.insert(0, self.command);
-            let p = Popen::create(&self.args, self.config)?;
+            let mut p = Popen::create(&self.args, self.config)?;
+            if let Some(file) = response_file {
+                p.add_temp_file(file);
+            }
+            if let Some(shared) = self.stdout_shared {
+                let mut stdout = p.stdout.take().unwrap();
+                p.spawn_helper_thread(move || {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = stdout.read(&mut buf) {
+                        if n == 0 {
+                            break;
+                        }
+                        shared.lock().unwrap().extend_from_slice(&buf[..n]);
+                    }
+                });
+            }
+            if prefix_stdout {
+                let stdout = p.stdout.take().unwrap();
+                let prefix = self.output_prefix.clone().unwrap();
+                p.spawn_helper_thread(move || {
+                    Exec::forward_with_prefix(stdout, &prefix, io::stdout());
+                });
+            }
+            if prefix_stderr {
+                let stderr = p.stderr.take().unwrap();
+                let prefix = self.output_prefix.clone().unwrap();
+                p.spawn_helper_thread(move || {
+                    Exec::forward_with_prefix(stderr, &prefix, io::stderr());
+                });
+            }
+            if let Some(iter) = self.stdin_iter.and_then(|iter| iter.lock().unwrap().take()) {
+                let mut stdin = p.stdin.take().unwrap();
+                p.spawn_helper_thread(move || {
+                    for chunk in iter {
+                        if stdin.write_all(&chunk).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            if let Some(file) = self.stdin_mmap {
+                let mut stdin = p.stdin.take().unwrap();
+                p.spawn_helper_thread(move || {
+                    // fails when the child exits without reading all of
+                    // its input, which is its prerogative
+                    let _ = Exec::write_mapped(&file, &mut stdin);
+                });
+            }
+            if let Some(callback) = self.stderr_callback {
+                let mut stderr = p.stderr.take().unwrap();
+                p.spawn_helper_thread(move || {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = stderr.read(&mut buf) {
+                        if n == 0 {
+                            break;
+                        }
+                        (callback.lock().unwrap())(&buf[..n]);
+                    }
+                });
+            }
             Ok(p)
         }
 
@@ -358,11 +1019,92 @@ mod exec {
         /// the exit status.
         ///
         /// This method will wait for as long as necessary for the process to
-        /// finish.  If a timeout is needed, use
-        /// `<...>.detached().popen()?.wait_timeout(...)` instead.
+        /// finish, unless a timeout was specified with [`timeout`].
+        ///
+        /// [`timeout`]: struct.Exec.html#method.timeout
         pub fn join(self) -> PopenResult<ExitStatus> {
             self.check_no_stdin_data("join");
-            self.popen()?.wait()
+            self.run_with_retry(Exec::join_once, |status| status.success())
+        }
+
+        fn join_once(self) -> PopenResult<ExitStatus> {
+            let timeout = self.timeout;
+            let mut p = self.popen()?;
+            match timeout {
+                None => p.wait(),
+                Some(timeout) => match p.wait_timeout(timeout)? {
+                    Some(exit_status) => Ok(exit_status),
+                    None => Err(Exec::kill_on_timeout(&mut p)),
+                },
+            }
+        }
+
+        // Like clone(), but reports failure to duplicate a File.
+        fn try_clone(&self) -> io::Result<Exec> {
+            Ok(Exec {
+                command: self.command.clone(),
+                args: self.args.clone(),
+                file_args: self.file_args.clone(),
+                config: self.config.try_clone()?,
+                inherited_env: self.inherited_env.clone(),
+                stdin_data: self.stdin_data.as_ref().cloned(),
+                timeout: self.timeout,
+                stderr_callback: self.stderr_callback.clone(),
+                stdout_shared: self.stdout_shared.clone(),
+                stdin_iter: self.stdin_iter.clone(),
+                stdin_mmap: self.stdin_mmap.clone(),
+                output_prefix: self.output_prefix.clone(),
+                retry: self.retry,
+                open_error: self.open_error.as_ref().map(|e| match e.raw_os_error() {
+                    Some(errno) => io::Error::from_raw_os_error(errno),
+                    None => io::Error::new(e.kind(), e.to_string()),
+                }),
+            })
+        }
+
+        // Run `run`, running it again on error or if `success` returns
+        // false, as requested by retry().
+        fn run_with_retry<T>(
+            self,
+            run: fn(Exec) -> PopenResult<T>,
+            success: fn(&T) -> bool,
+        ) -> PopenResult<T> {
+            let (mut attempts_left, backoff) = match self.retry {
+                Some(retry) => retry,
+                None => return run(self),
+            };
+            if let Redirection::File(_) | Redirection::RcFile(_) = self.config.stdin {
+                return Err(PopenError::LogicError(
+                    "retry is incompatible with stdin redirected from a file",
+                ));
+            }
+            if self.stdin_iter.is_some() {
+                return Err(PopenError::LogicError(
+                    "retry is incompatible with stdin fed from an iterator",
+                ));
+            }
+            while attempts_left > 0 {
+                match run(self.try_clone()?) {
+                    Ok(ref value) if !success(value) => (),
+                    Err(_) => (),
+                    result => return result,
+                }
+                attempts_left -= 1;
+                thread::sleep(backoff);
+            }
+            run(self)
+        }
+
+        // Kill and reap a process whose timeout has expired, and return
+        // the error to report to the caller.
+        fn kill_on_timeout(p: &mut Popen) -> PopenError {
+            if let Err(e) = p.kill() {
+                return e.into();
+            }
+            if let Err(e) = p.wait() {
+                return e;
+            }
+            PopenError::from(io::Error::new(io::ErrorKind::TimedOut, "timeout"))
         }
 
         /// Starts the process and returns a value implementing the `Read`
@@ -419,9 +1161,14 @@ mod exec {
             {
                 self = self.stdout(Redirection::Pipe);
             }
+            let timeout = self.timeout;
             let mut p = self.popen()?;
 
-            Ok((p.communicate_start(stdin_data), p))
+            let mut comm = p.communicate_start(stdin_data);
+            if let Some(timeout) = timeout {
+                comm = comm.limit_time(timeout);
+            }
+            Ok((comm, p))
         }
 
         /// Starts the process and returns a `Communicator` handle.
@@ -431,9 +1178,12 @@ mod exec {
         /// equivalent to [`Popen::communicate`].
         ///
         /// Unlike `capture()`, this method doesn't wait for the process to
-        /// finish, effectively detaching it.
+        /// finish, effectively detaching it.  A timeout specified with
+        /// [`timeout`] is used as the time limit of the `Communicator`,
+        /// but the process is not killed when it expires.
         ///
         /// [`Popen::communicate`]: struct.Popen.html#method.communicate
+        /// [`timeout`]: struct.Exec.html#method.timeout
         pub fn communicate(self) -> PopenResult<Communicator> {
             let comm = self.detached().setup_communicate()?.0;
             Ok(comm)
@@ -450,16 +1200,293 @@ mod exec {
         /// for the process to finish, rather than simply waiting for
         /// its standard streams to close.  If this is undesirable,
         /// use `detached()`.
+        ///
+        /// If a timeout was specified with [`timeout`], it covers both
+        /// reading the output and waiting for the process to finish.
+        ///
+        /// [`timeout`]: struct.Exec.html#method.timeout
         pub fn capture(self) -> PopenResult<CaptureData> {
+            self.capture_with_process().map(|(c, _)| c)
+        }
+
+        /// Starts the process, collects its output, and waits for it to
+        /// finish, also returning the `Popen`.
+        ///
+        /// Like [`capture`], but the `Popen` of the finished process is
+        /// returned along with its output, for inspection that requires
+        /// the process object, such as [`PopenExt::raw_wait_status`] or
+        /// [`Popen::spawn_metrics`].  The process has already been waited
+        /// for, so its exit status is also available through
+        /// [`Popen::exit_status`], and the pipes to it have been closed.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`PopenExt::raw_wait_status`]: unix/trait.PopenExt.html#tymethod.raw_wait_status
+        /// [`Popen::spawn_metrics`]: struct.Popen.html#method.spawn_metrics
+        /// [`Popen::exit_status`]: struct.Popen.html#method.exit_status
+        pub fn capture_with_process(self) -> PopenResult<(CaptureData, Popen)> {
+            self.run_with_retry(Exec::capture_once, |(c, _)| c.success())
+        }
+
+        fn capture_once(self) -> PopenResult<(CaptureData, Popen)> {
+            let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
             let (mut comm, mut p) = self.setup_communicate()?;
-            let (maybe_out, maybe_err) = comm.read()?;
-            Ok(CaptureData {
+            let (maybe_out, maybe_err) = match comm.read() {
+                Ok(output) => output,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    return Err(Exec::kill_on_timeout(&mut p));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let exit_status = Exec::wait_until(&mut p, deadline)?;
+            let c = CaptureData {
                 stdout: maybe_out.unwrap_or_else(Vec::new),
                 stderr: maybe_err.unwrap_or_else(Vec::new),
-                exit_status: p.wait()?,
+                exit_status,
+            };
+            Ok((c, p))
+        }
+
+        /// Starts the process, collects its output without holding all of
+        /// it in memory, and waits for it to finish.
+        ///
+        /// Like [`capture`], but standard output and error are collected
+        /// into [`CaptureSink`]s that keep up to `threshold` bytes in
+        /// memory each and spill to a temporary file beyond that.  This
+        /// is useful for commands whose output may be too large to fit in
+        /// memory, but must not be truncated.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`CaptureSink`]: struct.CaptureSink.html
+        pub fn capture_spooled(self, threshold: usize) -> PopenResult<SpooledCaptureData> {
+            let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+            let (mut comm, mut p) = self.setup_communicate()?;
+            let mut stdout = CaptureSink::new(threshold);
+            let mut stderr = CaptureSink::new(threshold);
+            match comm.read_to(&mut stdout, &mut stderr) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    return Err(Exec::kill_on_timeout(&mut p));
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let exit_status = Exec::wait_until(&mut p, deadline)?;
+            Ok(SpooledCaptureData {
+                stdout,
+                stderr,
+                exit_status,
+            })
+        }
+
+        /// Starts the process, collects its output while also forwarding
+        /// it to the current process, and waits for it to finish.
+        ///
+        /// Like [`capture`], but each chunk of the captured output is
+        /// also written to the standard output or error of the current
+        /// process as soon as it is read, so that the user can follow the
+        /// progress of e.g. a build whose output the program then parses.
+        /// Chunks are forwarded in the order they are read, and each
+        /// stream is flushed after every chunk, so forwarded output
+        /// matches the captured output of the same stream.  Like with
+        /// `capture`, standard error is only captured (and forwarded by
+        /// this method) if it was redirected to a pipe; otherwise the
+        /// process writes it directly to the inherited standard error.
+        ///
+        /// Failure to write to the current process's output, e.g. because
+        /// it was closed, doesn't stop the capture, whose result is
+        /// complete regardless.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        pub fn capture_and_tee(self) -> PopenResult<CaptureData> {
+            let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+            let (mut comm, mut p) = self.setup_communicate()?;
+            let mut stdout = TeeWriter(vec![], io::stdout());
+            let mut stderr = TeeWriter(vec![], io::stderr());
+            match comm.read_to(&mut stdout, &mut stderr) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    return Err(Exec::kill_on_timeout(&mut p));
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let exit_status = Exec::wait_until(&mut p, deadline)?;
+            Ok(CaptureData {
+                stdout: stdout.0,
+                stderr: stderr.0,
+                exit_status,
             })
         }
 
+        fn wait_until(p: &mut Popen, deadline: Option<Instant>) -> PopenResult<ExitStatus> {
+            match deadline {
+                None => p.wait(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match p.wait_timeout(remaining)? {
+                        Some(exit_status) => Ok(exit_status),
+                        None => Err(Exec::kill_on_timeout(p)),
+                    }
+                }
+            }
+        }
+
+        /// Starts the process, collects its standard output, and returns
+        /// it split into lines.
+        ///
+        /// This is a convenience method equivalent to
+        /// `capture()?.stdout_lines()`.  The exit status of the process is
+        /// not checked; to inspect it, call [`capture`] and
+        /// [`CaptureData::stdout_lines`] instead.
+        ///
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`CaptureData::stdout_lines`]: struct.CaptureData.html#method.stdout_lines
+        pub fn capture_lines(self) -> PopenResult<Vec<String>> {
+            Ok(self.capture()?.stdout_lines())
+        }
+
+        /// Starts the process, collects its standard output, and returns
+        /// it split into lines of bytes.
+        ///
+        /// Like [`capture_lines`], but doesn't require the output to be
+        /// valid UTF-8.
+        ///
+        /// [`capture_lines`]: struct.Exec.html#method.capture_lines
+        pub fn capture_lines_bytes(self) -> PopenResult<Vec<Vec<u8>>> {
+            Ok(self.capture()?.stdout_lines_bytes())
+        }
+
+        /// Returns a fingerprint of the command, usable as a cache key.
+        ///
+        /// The fingerprint is a 64-bit FNV-1a hash of the program, its
+        /// arguments, the executable override, the environment, and the
+        /// working directory.  The order of arguments is significant,
+        /// while the order in which environment variables were set is
+        /// not.  Redirections and input data are not included.  The
+        /// result is stable across runs and doesn't depend on memory
+        /// addresses.
+        ///
+        /// If the environment is inherited, only the changes made to it
+        /// by the `env*` methods are included, i.e. the variables that
+        /// were set to a different value or removed, so the fingerprint
+        /// doesn't depend on the environment of the current process.
+        /// After [`env_clear`], the whole environment of the child is
+        /// included.
+        ///
+        /// [`env_clear`]: struct.Exec.html#method.env_clear
+        pub fn fingerprint(&self) -> u64 {
+            struct Fnv1a(u64);
+            impl Fnv1a {
+                fn write(&mut self, bytes: &[u8]) {
+                    for &b in bytes {
+                        self.0 ^= b as u64;
+                        self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+                    }
+                }
+                // Prefix with length so that adjacent fields can't run
+                // into each other.
+                fn write_field(&mut self, field: Option<&OsStr>) {
+                    match field {
+                        Some(field) => {
+                            let bytes = os_str_bytes(field);
+                            self.write(&[1]);
+                            self.write(&(bytes.len() as u64).to_le_bytes());
+                            self.write(&bytes);
+                        }
+                        None => self.write(&[0]),
+                    }
+                }
+            }
+
+            let mut hash = Fnv1a(0xcbf2_9ce4_8422_2325);
+            hash.write_field(Some(&self.command));
+            hash.write(&(self.args.len() as u64).to_le_bytes());
+            for arg in &self.args {
+                hash.write_field(Some(arg));
+            }
+            hash.write_field(self.config.executable.as_deref());
+            // later entries override earlier ones, as in Popen
+            fn to_map(env: &[(OsString, OsString)]) -> BTreeMap<&OsString, &OsString> {
+                env.iter().map(|(k, v)| (k, v)).collect()
+            }
+            match self.config.env {
+                // cleared, so all of it is explicit
+                Some(ref env) if self.inherited_env.is_none() => {
+                    let env = to_map(env);
+                    hash.write(&[1]);
+                    hash.write(&(env.len() as u64).to_le_bytes());
+                    for (k, v) in env {
+                        hash.write_field(Some(k));
+                        hash.write_field(Some(v));
+                    }
+                }
+                // inherited, possibly with changes
+                ref env => {
+                    let env = env.as_deref().map(to_map).unwrap_or_default();
+                    let inherited = self
+                        .inherited_env
+                        .as_deref()
+                        .map(to_map)
+                        .unwrap_or_default();
+                    let changed: BTreeMap<_, _> = env
+                        .keys()
+                        .chain(inherited.keys())
+                        .filter(|&k| env.get(k) != inherited.get(k))
+                        .map(|&k| (k, env.get(k).copied()))
+                        .collect();
+                    hash.write(&[0]);
+                    hash.write(&(changed.len() as u64).to_le_bytes());
+                    for (k, v) in changed {
+                        hash.write_field(Some(k));
+                        hash.write_field(v.map(|v| v.as_os_str()));
+                    }
+                }
+            }
+            hash.write_field(self.config.cwd.as_deref());
+            hash.0
+        }
+
+        /// Returns what would be executed, without starting the process.
+        ///
+        /// The returned [`ExecPlan`] describes the program, resolved
+        /// against `PATH` the same way as when the process is started,
+        /// along with the argument vector, the environment, and the
+        /// working directory.  This is useful for implementing a
+        /// "dry run" mode, and for tests that check how a command is
+        /// constructed.
+        ///
+        /// [`ExecPlan`]: struct.ExecPlan.html
+        pub fn plan(&self) -> ExecPlan {
+            let program = self
+                .config
+                .executable
+                .clone()
+                .unwrap_or_else(|| self.command.clone());
+            let resolved = crate::popen::resolve_program(&program);
+            let mut argv = vec![self.command.clone()];
+            argv.extend(self.args.iter().cloned());
+            let env = match self.config.env {
+                Some(ref env) => {
+                    // later entries override earlier ones, as in Popen
+                    let mut seen = HashSet::new();
+                    let mut env: Vec<_> = env
+                        .iter()
+                        .rev()
+                        .filter(|(k, _)| seen.insert(k))
+                        .cloned()
+                        .collect();
+                    env.reverse();
+                    env
+                }
+                None => env::vars_os().collect(),
+            };
+            ExecPlan {
+                program,
+                resolved,
+                argv,
+                env,
+                cwd: self.config.cwd.clone(),
+            }
+        }
+
         // used for Debug impl
         fn display_escape(s: &str) -> Cow<'_, str> {
             fn nice_char(c: char) -> bool {
@@ -477,13 +1504,17 @@ mod exec {
         }
 
         /// Show Exec as command-line string quoted in the Unix style.
+        ///
+        /// The result can be pasted into a POSIX shell to reproduce the
+        /// command, including changes to its environment.  Arguments that
+        /// are not valid Unicode are converted lossily.
         pub fn to_cmdline_lossy(&self) -> String {
             let mut out = String::new();
             if let Some(ref cmd_env) = self.config.env {
                 let current: Vec<_> = env::vars_os().collect();
                 let current_map: HashMap<_, _> = current.iter().map(|(x, y)| (x, y)).collect();
                 for (k, v) in cmd_env {
-                    if current_map.get(&k) == Some(&&v) {
+                    if current_map.get(k) == Some(&v) {
                         continue;
                     }
                     out.push_str(&Exec::display_escape(&k.to_string_lossy()));
@@ -519,12 +1550,7 @@ mod exec {
         /// duplicates a file descriptor and can (but is not likely
         /// to) fail.  In that scenario, `Exec::clone` panics.
         fn clone(&self) -> Exec {
-            Exec {
-                command: self.command.clone(),
-                args: self.args.clone(),
-                config: self.config.try_clone().unwrap(),
-                stdin_data: self.stdin_data.as_ref().cloned(),
-            }
+            self.try_clone().unwrap()
         }
     }
 
@@ -543,6 +1569,22 @@ mod exec {
         }
     }
 
+    // Collects the data written to it, forwarding it to the inner
+    // writer on a best-effort basis.
+    struct TeeWriter<W>(Vec<u8>, W);
+
+    impl<W: Write> Write for TeeWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.extend_from_slice(buf);
+            let _ = self.1.write_all(buf).and_then(|_| self.1.flush());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
     #[derive(Debug)]
     struct ReadOutAdapter(Popen);
 
@@ -585,6 +1627,79 @@ mod exec {
         }
     }
 
+    /// Description of what an [`Exec`] would execute, returned by
+    /// [`Exec::plan`].
+    ///
+    /// The `Display` implementation renders the plan in a form suitable
+    /// for showing to the user, listing only the environment variables
+    /// that differ from the current environment.
+    ///
+    /// [`Exec`]: struct.Exec.html
+    /// [`Exec::plan`]: struct.Exec.html#method.plan
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ExecPlan {
+        /// The program to execute, either the executable if one was
+        /// specified, or the command.
+        pub program: OsString,
+        /// The file that would be executed, found by searching `PATH`
+        /// if `program` doesn't contain a directory, or `None` if the
+        /// search finds nothing.
+        pub resolved: Option<PathBuf>,
+        /// The argument vector, starting with the command.
+        pub argv: Vec<OsString>,
+        /// The complete environment of the process.
+        pub env: Vec<(OsString, OsString)>,
+        /// The working directory, or `None` to inherit the current one.
+        pub cwd: Option<OsString>,
+    }
+
+    impl fmt::Display for ExecPlan {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let escape = |s: &OsStr| Exec::display_escape(&s.to_string_lossy()).into_owned();
+            match self.resolved {
+                Some(ref resolved) => writeln!(f, "program: {}", escape(resolved.as_os_str()))?,
+                None => writeln!(f, "program: {} (not found)", escape(&self.program))?,
+            }
+            let argv: Vec<_> = self.argv.iter().map(|arg| escape(arg)).collect();
+            writeln!(f, "argv: {}", argv.join(" "))?;
+            let current: HashMap<_, _> = env::vars_os().collect();
+            let planned: HashMap<_, _> = self.env.iter().cloned().collect();
+            let mut changes = vec![];
+            for (k, v) in &self.env {
+                if current.get(k) != Some(v) {
+                    changes.push(format!("{}={}", escape(k), escape(v)));
+                }
+            }
+            let mut removed: Vec<_> = current
+                .keys()
+                .filter(|k| !planned.contains_key(*k))
+                .map(|k| format!("-{}", escape(k)))
+                .collect();
+            removed.sort();
+            changes.extend(removed);
+            if !changes.is_empty() {
+                writeln!(f, "env: {}", changes.join(" "))?;
+            }
+            if let Some(ref cwd) = self.cwd {
+                writeln!(f, "cwd: {}", escape(cwd))?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Data captured by [`Exec::capture_spooled`].
+    ///
+    /// [`Exec::capture_spooled`]: struct.Exec.html#method.capture_spooled
+    #[derive(Debug)]
+    pub struct SpooledCaptureData {
+        /// Standard output, possibly backed by a temporary file.
+        pub stdout: CaptureSink,
+        /// Standard error, possibly backed by a temporary file.
+        pub stderr: CaptureSink,
+        /// Exit status.
+        pub exit_status: ExitStatus,
+    }
+
     /// Data captured by [`Exec::capture`] and [`Pipeline::capture`].
     ///
     /// [`Exec::capture`]: struct.Exec.html#method.capture
@@ -612,16 +1727,162 @@ mod exec {
             String::from_utf8_lossy(&self.stderr).into_owned()
         }
 
+        /// Returns the standard output as string with one trailing
+        /// newline removed.
+        ///
+        /// Like the shell's `$(...)`, this removes a single trailing `\n`
+        /// or `\r\n`, if present.  Unlike `trim_end()`, it doesn't remove
+        /// other whitespace or additional newlines.
+        pub fn stdout_str_trimmed(&self) -> String {
+            let mut stdout = self.stdout_str();
+            if stdout.ends_with('\n') {
+                stdout.pop();
+                if stdout.ends_with('\r') {
+                    stdout.pop();
+                }
+            }
+            stdout
+        }
+
+        /// Returns the standard output split into lines of bytes.
+        ///
+        /// Lines are split on `\n`, which is not included in the
+        /// returned lines.  A trailing newline doesn't produce an
+        /// empty last line.
+        pub fn stdout_lines_bytes(&self) -> Vec<Vec<u8>> {
+            let mut stdout = &self.stdout[..];
+            if stdout.last() == Some(&b'\n') {
+                stdout = &stdout[..stdout.len() - 1];
+            }
+            if stdout.is_empty() {
+                return vec![];
+            }
+            stdout.split(|&b| b == b'\n').map(|l| l.to_vec()).collect()
+        }
+
+        /// Returns the standard output split into lines, converted from
+        /// bytes using `String::from_utf8_lossy`.
+        ///
+        /// Lines are split as with [`stdout_lines_bytes`].
+        ///
+        /// [`stdout_lines_bytes`]: struct.CaptureData.html#method.stdout_lines_bytes
+        pub fn stdout_lines(&self) -> Vec<String> {
+            self.stdout_lines_bytes()
+                .iter()
+                .map(|l| String::from_utf8_lossy(l).into_owned())
+                .collect()
+        }
+
+        /// Returns the standard output as string, decoded using
+        /// `encoding`.
+        ///
+        /// See [`Encoding::decode`] for details.
+        ///
+        /// [`Encoding::decode`]: enum.Encoding.html#method.decode
+        pub fn stdout_str_decoded(&self, encoding: Encoding) -> String {
+            encoding.decode(&self.stdout)
+        }
+
+        /// Returns the standard error as string, decoded using
+        /// `encoding`.
+        ///
+        /// See [`Encoding::decode`] for details.
+        ///
+        /// [`Encoding::decode`]: enum.Encoding.html#method.decode
+        pub fn stderr_str_decoded(&self, encoding: Encoding) -> String {
+            encoding.decode(&self.stderr)
+        }
+
         /// True if the exit status of the process or pipeline is 0.
         pub fn success(&self) -> bool {
             self.exit_status.success()
         }
     }
 
+    /// Text encoding of the output of a process, see
+    /// [`CaptureData::stdout_str_decoded`].
+    ///
+    /// [`CaptureData::stdout_str_decoded`]: struct.CaptureData.html#method.stdout_str_decoded
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum Encoding {
+        /// Detect the encoding from the byte order mark, falling back to
+        /// UTF-8 if there is none.
+        Auto,
+        /// UTF-8.
+        Utf8,
+        /// UTF-16, little-endian, as typically produced by Windows
+        /// programs, e.g. `cmd /u` and PowerShell.
+        Utf16Le,
+        /// UTF-16, big-endian.
+        Utf16Be,
+    }
+
+    impl Encoding {
+        /// Decodes `data` to a string.
+        ///
+        /// A byte order mark matching the encoding is removed from the
+        /// beginning of `data`.  With `Encoding::Auto`, the byte order
+        /// mark determines the encoding.  Invalid sequences, including
+        /// a trailing odd byte in UTF-16, are replaced with the `U+FFFD`
+        /// Unicode replacement character.  This can be used to decode
+        /// output obtained by other means, such as
+        /// [`Popen::communicate_bytes`].
+        ///
+        /// [`Popen::communicate_bytes`]: struct.Popen.html#method.communicate_bytes
+        pub fn decode(self, data: &[u8]) -> String {
+            let encoding = match self {
+                Encoding::Auto if data.starts_with(&[0xff, 0xfe]) => Encoding::Utf16Le,
+                Encoding::Auto if data.starts_with(&[0xfe, 0xff]) => Encoding::Utf16Be,
+                Encoding::Auto => Encoding::Utf8,
+                encoding => encoding,
+            };
+            match encoding {
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    let bom: &[u8] = if encoding == Encoding::Utf16Le {
+                        &[0xff, 0xfe]
+                    } else {
+                        &[0xfe, 0xff]
+                    };
+                    let data = if data.starts_with(bom) {
+                        &data[2..]
+                    } else {
+                        data
+                    };
+                    let units: Vec<u16> = data
+                        .chunks_exact(2)
+                        .map(|c| {
+                            if encoding == Encoding::Utf16Le {
+                                u16::from_le_bytes([c[0], c[1]])
+                            } else {
+                                u16::from_be_bytes([c[0], c[1]])
+                            }
+                        })
+                        .collect();
+                    let mut decoded = String::from_utf16_lossy(&units);
+                    if data.len() % 2 != 0 {
+                        decoded.push('\u{FFFD}');
+                    }
+                    decoded
+                }
+                _ => {
+                    let bom = b"\xef\xbb\xbf";
+                    let data = if data.starts_with(bom) {
+                        &data[3..]
+                    } else {
+                        data
+                    };
+                    String::from_utf8_lossy(data).into_owned()
+                }
+            }
+        }
+    }
+
     #[derive(Debug)]
     pub enum InputRedirection {
         AsRedirection(Redirection),
         FeedData(Vec<u8>),
+        MmapFile(File),
     }
 
     impl From<Redirection> for InputRedirection {
@@ -660,6 +1921,45 @@ mod exec {
         }
     }
 
+    /// Wrapper for [`stdin`] methods of [`Exec`] and [`Pipeline`] that
+    /// feeds the contents of a file through a memory mapping.
+    ///
+    /// The standard input is redirected to a pipe, and once the process
+    /// is started, a thread writes the contents of the file to it
+    /// directly from a read-only mapping of the file, sparing the copy
+    /// into an intermediate buffer that reading the file would require.
+    /// This is meant for feeding large files to a child that needs its
+    /// standard input to be a pipe, or to pass through a pipeline;
+    /// otherwise, passing the `File` itself to `stdin` is cheaper still,
+    /// as the child then reads the file on its own.  The thread is
+    /// joined by [`Popen::wait`], and stops writing if the child exits
+    /// without reading all of its input.
+    ///
+    /// The file is mapped in windows of 64 MiB, so files larger than
+    /// the address space of 32-bit systems can be fed as well.  Data
+    /// that can't be mapped, such as the contents of a file that isn't
+    /// a regular file, data appended to the file while it's being
+    /// written, or the whole file if mapping fails, is copied with
+    /// ordinary reads instead.  The size of the file is checked before
+    /// writing each megabyte from a mapping, and the rest is read
+    /// normally if it has shrunk, but on Unix-like systems, truncating
+    /// the file at just the wrong moment can still kill the process with
+    /// `SIGBUS`, so the file should not be truncated while it's being
+    /// fed.  Windows doesn't allow truncating a mapped file.
+    ///
+    /// [`stdin`]: struct.Exec.html#method.stdin
+    /// [`Exec`]: struct.Exec.html
+    /// [`Pipeline`]: struct.Pipeline.html
+    /// [`Popen::wait`]: struct.Popen.html#method.wait
+    #[derive(Debug)]
+    pub struct MmapFile(pub File);
+
+    impl From<MmapFile> for InputRedirection {
+        fn from(f: MmapFile) -> Self {
+            InputRedirection::MmapFile(f.0)
+        }
+    }
+
     impl From<Vec<u8>> for InputRedirection {
         fn from(v: Vec<u8>) -> Self {
             InputRedirection::FeedData(v)
@@ -703,10 +2003,25 @@ mod exec {
     #[cfg(unix)]
     pub mod unix {
         use super::Exec;
+        use crate::popen::Redirection;
 
+        /// Unix-specific extension methods for [`Exec`].
+        ///
+        /// [`Exec`]: ../struct.Exec.html
         pub trait ExecExt {
+            /// Set the user ID of the child process, see
+            /// [`PopenConfig::setuid`](../struct.PopenConfig.html#structfield.setuid).
             fn setuid(self, uid: u32) -> Self;
+            /// Set the group ID of the child process, see
+            /// [`PopenConfig::setgid`](../struct.PopenConfig.html#structfield.setgid).
             fn setgid(self, gid: u32) -> Self;
+            /// Set up descriptor `fd` of the child process, see
+            /// [`PopenConfig::extra_fds`](../struct.PopenConfig.html#structfield.extra_fds).
+            fn redirect_fd(self, fd: i32, redirection: Redirection) -> Self;
+            /// Keep other descriptors from being inherited by the child
+            /// process, see
+            /// [`PopenConfig::close_fds`](../struct.PopenConfig.html#structfield.close_fds).
+            fn close_fds(self, close: bool) -> Self;
         }
 
         impl ExecExt for Exec {
@@ -719,6 +2034,37 @@ mod exec {
                 self.config.setgid = Some(gid);
                 self
             }
+
+            fn redirect_fd(mut self, fd: i32, redirection: Redirection) -> Exec {
+                self.config.extra_fds.push((fd, redirection));
+                self
+            }
+
+            fn close_fds(mut self, close: bool) -> Exec {
+                self.config.close_fds = close;
+                self
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    pub mod windows {
+        use super::Exec;
+
+        /// Windows-specific extension methods for [`Exec`].
+        ///
+        /// [`Exec`]: ../struct.Exec.html
+        pub trait ExecExt {
+            /// Set additional process creation flags, see
+            /// [`PopenConfig::creation_flags`](../struct.PopenConfig.html#structfield.creation_flags).
+            fn creation_flags(self, flags: u32) -> Self;
+        }
+
+        impl ExecExt for Exec {
+            fn creation_flags(mut self, flags: u32) -> Exec {
+                self.config.creation_flags = flags;
+                self
+            }
         }
     }
 }
@@ -729,12 +2075,13 @@ mod pipeline {
     use std::io::{self, Read, Write};
     use std::ops::BitOr;
     use std::rc::Rc;
+    use std::thread::{self, JoinHandle};
 
     use crate::communicate::{self, Communicator};
     use crate::os_common::ExitStatus;
     use crate::popen::{Popen, Redirection, Result as PopenResult};
 
-    use super::exec::{CaptureData, Exec, InputRedirection, OutputRedirection};
+    use super::exec::{CaptureData, Exec, ExecPlan, InputRedirection, MmapFile, OutputRedirection};
 
     /// A builder for multiple [`Popen`] instances connected via
     /// pipes.
@@ -778,7 +2125,6 @@ mod pipeline {
     /// [`Popen`]: struct.Popen.html
     /// [`Exec`]: struct.Exec.html
     /// [`Pipeline`]: struct.Pipeline.html
-
     #[must_use]
     pub struct Pipeline {
         cmds: Vec<Exec>,
@@ -786,6 +2132,7 @@ mod pipeline {
         stdout: Redirection,
         stderr_file: Option<File>,
         stdin_data: Option<Vec<u8>>,
+        stdin_mmap: Option<File>,
     }
 
     impl Pipeline {
@@ -799,6 +2146,7 @@ mod pipeline {
                 stdout: Redirection::None,
                 stderr_file: None,
                 stdin_data: None,
+                stdin_mmap: None,
             }
         }
 
@@ -813,9 +2161,12 @@ mod pipeline {
         ///   for stdin, making sure that `capture` feeds that data into the
         ///   standard input of the subprocess.
         /// * `NullFile`, which will redirect the standard input to read from
-        ///    /dev/null.
+        ///   /dev/null;
+        /// * [`MmapFile`], which will feed the contents of the file to the
+        ///   first command from a memory mapping.
         ///
         /// [`Redirection`]: enum.Redirection.html
+        /// [`MmapFile`]: struct.MmapFile.html
         pub fn stdin(mut self, stdin: impl Into<InputRedirection>) -> Pipeline {
             match stdin.into() {
                 InputRedirection::AsRedirection(r) => self.stdin = r,
@@ -823,6 +2174,7 @@ mod pipeline {
                     self.stdin = Redirection::Pipe;
                     self.stdin_data = Some(data);
                 }
+                InputRedirection::MmapFile(file) => self.stdin_mmap = Some(file),
             };
             self
         }
@@ -835,7 +2187,7 @@ mod pipeline {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * `NullFile`, which will redirect the standard output to write to
-        ///    /dev/null.
+        ///   /dev/null.
         ///
         /// [`Redirection`]: enum.Redirection.html
         pub fn stdout(mut self, stdout: impl Into<OutputRedirection>) -> Pipeline {
@@ -890,8 +2242,11 @@ mod pipeline {
                     .collect();
             }
 
-            let first_cmd = self.cmds.drain(..1).next().unwrap();
-            self.cmds.insert(0, first_cmd.stdin(self.stdin));
+            let mut first_cmd = self.cmds.drain(..1).next().unwrap().stdin(self.stdin);
+            if let Some(file) = self.stdin_mmap {
+                first_cmd = first_cmd.stdin(MmapFile(file));
+            }
+            self.cmds.insert(0, first_cmd);
 
             let last_cmd = self.cmds.drain(self.cmds.len() - 1..).next().unwrap();
             self.cmds.push(last_cmd.stdout(self.stdout));
@@ -924,6 +2279,25 @@ mod pipeline {
             v.last_mut().unwrap().wait()
         }
 
+        /// Starts the pipeline, waits for it to finish, and returns
+        /// the exit statuses of all commands.
+        ///
+        /// The statuses are returned in pipeline order, like the
+        /// `PIPESTATUS` array in bash.  This allows detecting the failure
+        /// of an earlier command, which is masked in the status returned
+        /// by [`join`] when the last command succeeds.
+        ///
+        /// If some command fails to start, the error is returned as
+        /// described in [`popen`].
+        ///
+        /// [`join`]: struct.Pipeline.html#method.join
+        /// [`popen`]: struct.Pipeline.html#method.popen
+        pub fn join_all(self) -> PopenResult<Vec<ExitStatus>> {
+            self.check_no_stdin_data("join_all");
+            let mut v = self.popen()?;
+            v.iter_mut().map(Popen::wait).collect()
+        }
+
         /// Starts the pipeline and returns a value implementing the `Read`
         /// trait that reads from the standard output of the last command.
         ///
@@ -955,6 +2329,47 @@ mod pipeline {
             Ok(WritePipelineAdapter(v))
         }
 
+        /// Starts the pipeline and feeds its output to each of the
+        /// `consumers`.
+        ///
+        /// This is the equivalent of the shell's
+        /// `cmd | tee >(consumer1) >(consumer2)`.  The standard output of
+        /// the last command is redirected to a pipe, and a background
+        /// thread copies everything read from it to the standard input
+        /// of every consumer.  The consumers run concurrently, and since
+        /// the data is written to all of them in turn, the rate at which
+        /// the pipeline can produce output is governed by the slowest
+        /// consumer.  A consumer that exits before reading all of its
+        /// input stops receiving data without affecting the others.
+        ///
+        /// Returns the started processes, the commands of the pipeline
+        /// followed by the consumers in order, and the handle of the
+        /// copying thread.  Joining the handle waits for the copying to
+        /// finish and reports an error encountered while reading the
+        /// pipeline's output or writing to a consumer.  A consumer
+        /// closing its standard input early is not considered an error.
+        ///
+        /// # Panics
+        ///
+        /// If input data was specified for the pipeline, or if standard
+        /// input was already set for one of the consumers.
+        pub fn split(
+            self,
+            consumers: Vec<Exec>,
+        ) -> PopenResult<(Vec<Popen>, JoinHandle<io::Result<()>>)> {
+            self.check_no_stdin_data("split");
+            let mut v = self.stdout(Redirection::Pipe).popen()?;
+            let source = v.last_mut().unwrap().stdout.take().unwrap();
+            let mut sinks = Vec::with_capacity(consumers.len());
+            for consumer in consumers {
+                let mut p = consumer.stdin(Redirection::Pipe).popen()?;
+                sinks.push(p.stdin.take().unwrap());
+                v.push(p);
+            }
+            let copier = thread::spawn(move || fan_out(source, sinks));
+            Ok((v, copier))
+        }
+
         fn setup_communicate(mut self) -> PopenResult<(Communicator, Vec<Popen>)> {
             assert!(self.cmds.len() >= 2);
 
@@ -1016,6 +2431,123 @@ mod pipeline {
                 exit_status: status,
             })
         }
+
+        /// Starts the pipeline, collects its output, and waits for all
+        /// commands to finish, also returning their `Popen`s.
+        ///
+        /// Like [`capture`], but the `Popen`s of the finished processes
+        /// are returned along with the output, in pipeline order, e.g. to
+        /// check the exit status of each command, which `capture` only
+        /// reports for the last one.  All of the processes have already
+        /// been waited for, so their exit statuses are available through
+        /// [`Popen::exit_status`].
+        ///
+        /// [`capture`]: struct.Pipeline.html#method.capture
+        /// [`Popen::exit_status`]: struct.Popen.html#method.exit_status
+        pub fn capture_with_processes(self) -> PopenResult<(CaptureData, Vec<Popen>)> {
+            let (mut comm, mut v) = self.setup_communicate()?;
+            let (out, err) = comm.read()?;
+            let mut status = None;
+            for p in &mut v {
+                status = Some(p.wait()?);
+            }
+            let c = CaptureData {
+                stdout: out.unwrap_or_else(Vec::new),
+                stderr: err.unwrap(),
+                exit_status: status.unwrap(),
+            };
+            Ok((c, v))
+        }
+
+        /// Starts the pipeline, collects its output, and waits for it to
+        /// finish, capturing the standard error of each command
+        /// separately.
+        ///
+        /// Like [`capture`], but instead of merging the standard error of
+        /// all commands into a single stream, each command gets its own
+        /// standard error pipe, and the pipes are drained concurrently.
+        /// The returned vector contains the standard error of each command
+        /// in pipeline order, so that its `i`-th element belongs to the
+        /// `i`-th command.  The `stderr` field of the returned
+        /// `CaptureData` holds the concatenation of those outputs, also in
+        /// pipeline order.
+        ///
+        /// # Panics
+        ///
+        /// If [`stderr_to`] was called, or if the standard error of one
+        /// of the commands was already set up.
+        ///
+        /// [`capture`]: struct.Pipeline.html#method.capture
+        /// [`stderr_to`]: struct.Pipeline.html#method.stderr_to
+        pub fn capture_stderr_separately(mut self) -> PopenResult<(CaptureData, Vec<Vec<u8>>)> {
+            assert!(self.cmds.len() >= 2);
+            if self.stderr_file.is_some() {
+                panic!("capture_stderr_separately called with stderr_to specified");
+            }
+            self.cmds = self
+                .cmds
+                .into_iter()
+                .map(|cmd| cmd.stderr(Redirection::Pipe))
+                .collect();
+
+            let stdin_data = self.stdin_data.take();
+            let mut v = self.stdout(Redirection::Pipe).popen()?;
+            let vlen = v.len();
+
+            let readers: Vec<_> = v
+                .iter_mut()
+                .map(|p| {
+                    let mut err = p.stderr.take().unwrap();
+                    thread::spawn(move || -> io::Result<Vec<u8>> {
+                        let mut buf = vec![];
+                        err.read_to_end(&mut buf)?;
+                        Ok(buf)
+                    })
+                })
+                .collect();
+            let mut comm = communicate::communicate(
+                v[0].stdin.take(),
+                v[vlen - 1].stdout.take(),
+                None,
+                stdin_data,
+            );
+            let (out, _) = comm.read()?;
+
+            let mut errs = Vec::with_capacity(vlen);
+            for reader in readers {
+                errs.push(reader.join().unwrap()?);
+            }
+            let status = v[vlen - 1].wait()?;
+
+            let c = CaptureData {
+                stdout: out.unwrap_or_else(Vec::new),
+                stderr: errs.concat(),
+                exit_status: status,
+            };
+            Ok((c, errs))
+        }
+
+        /// Returns what each command of the pipeline would execute,
+        /// without starting any processes.
+        ///
+        /// See [`Exec::plan`] for details.
+        ///
+        /// [`Exec::plan`]: struct.Exec.html#method.plan
+        pub fn plan(&self) -> Vec<ExecPlan> {
+            self.cmds.iter().map(Exec::plan).collect()
+        }
+
+        /// Show Pipeline as command-line string quoted in the Unix style.
+        ///
+        /// The commands are shown as by [`Exec::to_cmdline_lossy`] and
+        /// joined with `|`, so that the result can be pasted into a POSIX
+        /// shell.
+        ///
+        /// [`Exec::to_cmdline_lossy`]: struct.Exec.html#method.to_cmdline_lossy
+        pub fn to_cmdline_lossy(&self) -> String {
+            let cmds: Vec<_> = self.cmds.iter().map(Exec::to_cmdline_lossy).collect();
+            cmds.join(" | ")
+        }
     }
 
     impl Clone for Pipeline {
@@ -1034,6 +2566,7 @@ mod pipeline {
                 stdout: self.stdout.try_clone().unwrap(),
                 stderr_file: self.stderr_file.as_ref().map(|f| f.try_clone().unwrap()),
                 stdin_data: self.stdin_data.clone(),
+                stdin_mmap: self.stdin_mmap.as_ref().map(|f| f.try_clone().unwrap()),
             }
         }
     }
@@ -1048,6 +2581,19 @@ mod pipeline {
         }
     }
 
+    impl BitOr<Pipeline> for Exec {
+        type Output = Pipeline;
+
+        /// Prepend a command to the pipeline and return a new pipeline.
+        fn bitor(self, mut rhs: Pipeline) -> Pipeline {
+            rhs.cmds.insert(0, self);
+            rhs.stdin = Redirection::None;
+            rhs.stdin_data = None;
+            rhs.stdin_mmap = None;
+            rhs
+        }
+    }
+
     impl BitOr for Pipeline {
         type Output = Pipeline;
 
@@ -1061,12 +2607,35 @@ mod pipeline {
 
     impl fmt::Debug for Pipeline {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            let mut args = vec![];
-            for cmd in &self.cmds {
-                args.push(cmd.to_cmdline_lossy());
-            }
-            write!(f, "Pipeline {{ {} }}", args.join(" | "))
+            write!(f, "Pipeline {{ {} }}", self.to_cmdline_lossy())
+        }
+    }
+
+    fn fan_out(mut source: File, mut sinks: Vec<File>) -> io::Result<()> {
+        let mut buf = [0u8; 8192];
+        let mut first_error = None;
+        while !sinks.is_empty() {
+            let n = match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            // Drop consumers that have stopped reading, which also
+            // closes their standard input.  A broken pipe just means
+            // the consumer exited; anything else is reported once the
+            // remaining consumers have been served.
+            sinks.retain(|mut sink| match sink.write_all(&buf[..n]) {
+                Ok(()) => true,
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::BrokenPipe && first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                    false
+                }
+            });
         }
+        first_error.map_or(Ok(()), Err)
     }
 
     #[derive(Debug)]
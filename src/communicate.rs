@@ -1,8 +1,18 @@
+use std::cmp::min;
+use std::env;
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::{self, ErrorKind};
-use std::time::{Duration, Instant};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::popen::LivenessToken;
 
 #[cfg(unix)]
 mod raw {
@@ -70,6 +80,8 @@ mod raw {
         stderr: Option<File>,
         input_data: Vec<u8>,
         input_pos: usize,
+        input_written: usize,
+        eof_times: Option<(Option<Instant>, Option<Instant>)>,
     }
 
     impl RawCommunicator {
@@ -79,13 +91,35 @@ mod raw {
             stderr: Option<File>,
             input_data: Option<Vec<u8>>,
         ) -> RawCommunicator {
-            let input_data = input_data.unwrap_or_else(Vec::new);
+            let input_data = input_data.unwrap_or_default();
             RawCommunicator {
                 stdin,
                 stdout,
                 stderr,
                 input_data,
                 input_pos: 0,
+                input_written: 0,
+                eof_times: None,
+            }
+        }
+
+        pub fn input_written(&self) -> usize {
+            self.input_written
+        }
+
+        pub fn track_eof(&mut self) {
+            if self.eof_times.is_none() {
+                self.eof_times = Some((None, None));
+            }
+        }
+
+        pub fn eof_times(&self) -> (Option<Instant>, Option<Instant>) {
+            self.eof_times.unwrap_or((None, None))
+        }
+
+        fn record_eof(eof_time: &mut Option<Instant>) {
+            if eof_time.is_none() {
+                *eof_time = Some(Instant::now());
             }
         }
 
@@ -104,7 +138,12 @@ mod raw {
                     buf = &mut buf[0..size_limit - total_read];
                 }
             }
-            let n = source_ref.unwrap().read(buf)?;
+            let n = match source_ref.unwrap().read(buf) {
+                // the master of a pseudo-terminal reports EIO once the
+                // child's end is closed
+                Err(ref e) if e.raw_os_error() == Some(posix::EIO) => 0,
+                result => result?,
+            };
             if n != 0 {
                 dest.extend_from_slice(&buf[..n]);
             } else {
@@ -139,6 +178,26 @@ mod raw {
                     break;
                 }
 
+                if let (None, None) = (stdout_ref, stderr_ref) {
+                    if self.stdout.is_some() || self.stderr.is_some() {
+                        // All output has been read, which normally means
+                        // that the child is gone.  Don't wait for it to
+                        // read the rest of the input, which might never
+                        // happen.
+                        self.stdin.take();
+                        self.input_data = Vec::new();
+                        break;
+                    }
+                }
+
+                if let Some(deadline) = deadline {
+                    // Check explicitly, as poll() below would keep
+                    // reporting ready streams past the deadline.
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                    }
+                }
+
                 let (in_ready, out_ready, err_ready) =
                     maybe_poll(self.stdin.as_ref(), stdout_ref, stderr_ref, deadline)?;
                 if !in_ready && !out_ready && !err_ready {
@@ -147,8 +206,20 @@ mod raw {
                 if in_ready {
                     let input = &self.input_data[self.input_pos..];
                     let chunk = &input[..min(WRITE_SIZE, input.len())];
-                    let n = self.stdin.as_ref().unwrap().write(chunk)?;
-                    self.input_pos += n;
+                    match self.stdin.as_ref().unwrap().write(chunk) {
+                        Ok(n) => {
+                            self.input_pos += n;
+                            self.input_written += n;
+                        }
+                        // The child has closed its standard input without
+                        // reading all of it, which is its prerogative.
+                        Err(ref e) if super::is_closed_by_peer(e) => {
+                            self.input_pos = self.input_data.len()
+                        }
+                        // Keep stdin open and input_pos unchanged, so
+                        // that writing resumes if read() is called again.
+                        Err(e) => return Err(e),
+                    }
                     if self.input_pos == self.input_data.len() {
                         // close stdin when done writing, so the child receives EOF
                         self.stdin.take();
@@ -163,6 +234,9 @@ mod raw {
                         size_limit,
                         outvec.len() + errvec.len(),
                     )?;
+                    if let (None, Some(eof_times)) = (stdout_ref, self.eof_times.as_mut()) {
+                        RawCommunicator::record_eof(&mut eof_times.0);
+                    }
                 }
                 if err_ready {
                     RawCommunicator::do_read(
@@ -171,6 +245,9 @@ mod raw {
                         size_limit,
                         outvec.len() + errvec.len(),
                     )?;
+                    if let (None, Some(eof_times)) = (stderr_ref, self.eof_times.as_mut()) {
+                        RawCommunicator::record_eof(&mut eof_times.1);
+                    }
                 }
             }
 
@@ -197,14 +274,31 @@ mod raw {
     }
 }
 
+// Windows implementation of the communicator.
+//
+// Only the output pipes created by Popen support overlapped IO, while a
+// communicator also gets the standard input pipe, the pipes of
+// pipelines and whatever the user stored in Popen's fields, so there is
+// nothing like poll() to wait on all of them together with a timeout.
+// Instead, each stream is serviced by a helper thread doing blocking IO,
+// and the helpers report to the main thread over a channel.  Waiting
+// with a timeout is then a matter of recv_timeout() on the channel,
+// which blocks without consuming CPU, just like waiting for the process
+// itself with WaitForSingleObject() in Popen::wait_timeout, and reading
+// with a timeout with an overlapped read in Popen::read_stdout_timeout.
 #[cfg(windows)]
 mod raw {
+    use std::fmt;
     use std::fs::File;
     use std::io::{self, Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+    use std::sync::Arc;
     use std::thread;
     use std::time::Instant;
 
+    use super::ReaderThreads;
+
     #[derive(Debug, Copy, Clone)]
     enum StreamIdent {
         In = 1 << 0,
@@ -221,45 +315,98 @@ mod raw {
     // Messages exchanged between RawCommunicator's helper threads.
     type Message = (StreamIdent, Payload);
 
-    fn read_and_transmit(mut outfile: File, ident: StreamIdent, sink: SyncSender<Message>) {
+    // The IO on a single stream, performed one step at a time, each step
+    // producing a message.  Steps producing anything other than data are
+    // final.
+    struct Helper(Box<dyn FnMut() -> Message + Send>);
+
+    impl fmt::Debug for Helper {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("Helper")
+        }
+    }
+
+    fn reader(mut outfile: File, ident: StreamIdent) -> Helper {
         let mut chunk = [0u8; 4096];
+        Helper(Box::new(move || match outfile.read(&mut chunk) {
+            Ok(0) => (ident, Payload::EOF),
+            Ok(nread) => (ident, Payload::Data(chunk[..nread].to_vec())),
+            Err(e) => (ident, Payload::Err(e)),
+        }))
+    }
+
+    fn writer(mut stdin: File, input_data: Vec<u8>, written: Arc<AtomicUsize>) -> Helper {
+        Helper(Box::new(move || {
+            match write_counted(&mut stdin, &input_data, &written) {
+                Ok(()) => (StreamIdent::In, Payload::EOF),
+                // the child closed its standard input without reading all
+                // of it, which is its prerogative
+                Err(ref e) if super::is_closed_by_peer(e) => (StreamIdent::In, Payload::EOF),
+                Err(e) => (StreamIdent::In, Payload::Err(e)),
+            }
+        }))
+    }
+
+    fn run_and_transmit(mut helper: Helper, sink: SyncSender<Message>) {
         // Note: failing to send to the sink means we're done.  Sending will
         // fail if the main thread drops the RawCommunicator (and with it the
         // receiver) prematurely e.g. because a limit was reached or another
         // helper encountered an IO error.
         loop {
-            match outfile.read(&mut chunk) {
-                Ok(0) => {
-                    let _ = sink.send((ident, Payload::EOF));
-                    break;
-                }
-                Ok(nread) => {
-                    if let Err(_) = sink.send((ident, Payload::Data(chunk[..nread].to_vec()))) {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    let _ = sink.send((ident, Payload::Err(e)));
-                    break;
-                }
+            let message = (helper.0)();
+            let last = !matches!(message.1, Payload::Data(_));
+            if sink.send(message).is_err() || last {
+                break;
             }
         }
     }
 
-    fn spawn_with_arg<T: Send + 'static>(f: impl FnOnce(T) + Send + 'static, arg: T) {
-        thread::spawn(move || f(arg));
+    // Releases the helper thread's slot when the thread exits, even by
+    // panicking.
+    struct ThreadSlot;
+
+    impl Drop for ThreadSlot {
+        fn drop(&mut self) {
+            ReaderThreads::get().release();
+        }
+    }
+
+    // The slot must have been acquired from ReaderThreads.
+    fn spawn_helper(helper: Helper, sink: SyncSender<Message>) {
+        thread::spawn(move || {
+            let _slot = ThreadSlot;
+            run_and_transmit(helper, sink)
+        });
     }
 
     #[derive(Debug)]
     pub struct RawCommunicator {
         rx: mpsc::Receiver<Message>,
+        tx: SyncSender<Message>,
+        // helpers not yet started, which happens on the first read, when
+        // the deadline for acquiring their threads is known
+        pending: Vec<Helper>,
+        // a helper run in the calling thread for lack of a thread slot
+        inline: Option<Helper>,
         helper_set: u8,
         requested_streams: u8,
         leftover: Option<(StreamIdent, Vec<u8>)>,
+        input_written: Arc<AtomicUsize>,
+        eof_times: Option<(Option<Instant>, Option<Instant>)>,
     }
 
     struct Timeout;
 
+    // Write `data` to `stdin` in chunks, counting the bytes written so
+    // far in `written`.
+    fn write_counted(stdin: &mut File, data: &[u8], written: &AtomicUsize) -> io::Result<()> {
+        for chunk in data.chunks(64 * 1024) {
+            stdin.write_all(chunk)?;
+            written.fetch_add(chunk.len(), Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
     impl RawCommunicator {
         pub fn new(
             stdin: Option<File>,
@@ -269,41 +416,87 @@ mod raw {
         ) -> RawCommunicator {
             let mut helper_set = 0u8;
             let mut requested_streams = 0u8;
+            let mut pending = vec![];
 
-            let read_stdout = stdout.map(|stdout| {
+            if let Some(stdout) = stdout {
                 helper_set |= StreamIdent::Out as u8;
                 requested_streams |= StreamIdent::Out as u8;
-                |tx| read_and_transmit(stdout, StreamIdent::Out, tx)
-            });
-            let read_stderr = stderr.map(|stderr| {
+                pending.push(reader(stdout, StreamIdent::Out));
+            }
+            if let Some(stderr) = stderr {
                 helper_set |= StreamIdent::Err as u8;
                 requested_streams |= StreamIdent::Err as u8;
-                |tx| read_and_transmit(stderr, StreamIdent::Err, tx)
-            });
-            let write_stdin = stdin.map(|mut stdin| {
+                pending.push(reader(stderr, StreamIdent::Err));
+            }
+            let input_written = Arc::new(AtomicUsize::new(0));
+            if let Some(stdin) = stdin {
                 let input_data = input_data.expect("must provide input to redirected stdin");
                 helper_set |= StreamIdent::In as u8;
-                move |tx: SyncSender<_>| match stdin.write_all(&input_data) {
-                    Ok(()) => drop(tx.send((StreamIdent::In, Payload::EOF))),
-                    Err(e) => drop(tx.send((StreamIdent::In, Payload::Err(e)))),
-                }
-            });
+                pending.push(writer(stdin, input_data, Arc::clone(&input_written)));
+            }
 
             let (tx, rx) = mpsc::sync_channel(0);
 
-            read_stdout.map(|f| spawn_with_arg(f, tx.clone()));
-            read_stderr.map(|f| spawn_with_arg(f, tx.clone()));
-            write_stdin.map(|f| spawn_with_arg(f, tx.clone()));
-
             RawCommunicator {
                 rx,
+                tx,
+                pending,
+                inline: None,
                 helper_set,
                 requested_streams,
                 leftover: None,
+                input_written,
+                eof_times: None,
             }
         }
 
-        fn recv_until(&self, deadline: Option<Instant>) -> Result<Message, Timeout> {
+        pub fn input_written(&self) -> usize {
+            self.input_written.load(Ordering::SeqCst)
+        }
+
+        pub fn track_eof(&mut self) {
+            if self.eof_times.is_none() {
+                self.eof_times = Some((None, None));
+            }
+        }
+
+        pub fn eof_times(&self) -> (Option<Instant>, Option<Instant>) {
+            self.eof_times.unwrap_or((None, None))
+        }
+
+        // Start the helpers, in threads if slots for them can be acquired
+        // by the deadline.  A single helper doesn't need a thread of its
+        // own, as there is no other stream it could deadlock against, so
+        // without a deadline to honor, it runs in the calling thread when
+        // no slot is free.
+        fn start_helpers(&mut self, deadline: Option<Instant>) -> Result<(), Timeout> {
+            if self.pending.is_empty() {
+                return Ok(());
+            }
+            let count = self.pending.len();
+            let may_run_inline = count == 1 && deadline.is_none();
+            let wait_until = if may_run_inline {
+                Some(Instant::now())
+            } else {
+                deadline
+            };
+            if !ReaderThreads::get().acquire(count, wait_until) {
+                if may_run_inline {
+                    self.inline = self.pending.pop();
+                    return Ok(());
+                }
+                return Err(Timeout);
+            }
+            for helper in self.pending.drain(..) {
+                spawn_helper(helper, self.tx.clone());
+            }
+            Ok(())
+        }
+
+        fn recv_until(&mut self, deadline: Option<Instant>) -> Result<Message, Timeout> {
+            if let Some(ref mut helper) = self.inline {
+                return Ok((helper.0)());
+            }
             if let Some(deadline) = deadline {
                 let now = Instant::now();
                 if now >= deadline {
@@ -360,10 +553,29 @@ mod raw {
                 }
             }
 
+            if self.helper_set != 0 && self.start_helpers(deadline).is_err() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+            }
             while self.helper_set != 0 {
+                if self.helper_set == StreamIdent::In as u8 && self.requested_streams != 0 {
+                    // All output has been read, which normally means that
+                    // the child is gone.  Don't wait for it to read the
+                    // rest of the input, which might never happen.  The
+                    // writer thread exits once its write fails or the
+                    // communicator is dropped.
+                    self.helper_set = 0;
+                    break;
+                }
                 match self.recv_until(deadline) {
                     Ok((ident, Payload::EOF)) => {
                         self.helper_set &= !(ident as u8);
+                        if let Some(ref mut eof_times) = self.eof_times {
+                            match ident {
+                                StreamIdent::Out => eof_times.0 = Some(Instant::now()),
+                                StreamIdent::Err => eof_times.1 = Some(Instant::now()),
+                                StreamIdent::In => (),
+                            }
+                        }
                         continue;
                     }
                     Ok((ident, Payload::Data(data))) => {
@@ -417,6 +629,101 @@ mod raw {
 
 use raw::RawCommunicator;
 
+// Process-wide limit on the number of communicator helper threads.  Only
+// the Windows communicator uses threads.
+struct ReaderThreads {
+    // the maximum, 0 meaning unlimited, and the number of running threads
+    state: Mutex<(usize, usize)>,
+    released: Condvar,
+}
+
+impl ReaderThreads {
+    fn get() -> &'static ReaderThreads {
+        static INIT: Once = Once::new();
+        static INSTANCE: AtomicPtr<ReaderThreads> = AtomicPtr::new(ptr::null_mut());
+        INIT.call_once(|| {
+            let instance = Box::new(ReaderThreads {
+                state: Mutex::new((0, 0)),
+                released: Condvar::new(),
+            });
+            INSTANCE.store(Box::into_raw(instance), Ordering::SeqCst);
+        });
+        // never freed, so the reference is valid for the rest of the
+        // program
+        unsafe { &*INSTANCE.load(Ordering::SeqCst) }
+    }
+
+    // Wait until `count` more threads may run, or until `deadline`, and
+    // account for them.  Returns false if the deadline passed first.  The
+    // slots of a communicator are acquired all at once, so that
+    // communicators waiting for each other's slots can't deadlock.
+    #[cfg(windows)]
+    fn acquire(&self, count: usize, deadline: Option<Instant>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        // A request exceeding the maximum on its own is let through
+        // once no other threads are running, rather than never.
+        while state.0 != 0 && state.1 != 0 && state.1 + count > state.0 {
+            state = match deadline {
+                None => self.released.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.released.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+        state.1 += count;
+        true
+    }
+
+    #[cfg(windows)]
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.1 -= 1;
+        self.released.notify_all();
+    }
+}
+
+/// Limit the number of threads used by communicators across the process.
+///
+/// On Windows, a [`Communicator`] reads standard output and error and
+/// writes standard input using a helper thread for each redirected
+/// stream, so a program communicating with thousands of subprocesses
+/// at once may run into the system's limit on threads.  With a limit in
+/// place, the first read of a communicator (e.g. with
+/// [`Popen::communicate`] or [`Exec::capture`]) waits until the threads
+/// of enough other communicators have finished.  The wait counts against
+/// the communicator's time limit or deadline, and the read fails with
+/// `ErrorKind::TimedOut` if the threads can't be started in time.  A
+/// communicator servicing a single stream without a time limit doesn't
+/// wait: when the limit is reached, it does its IO in the calling thread
+/// instead.  The threads of a communicator are allocated together, so
+/// that waiting communicators can't deadlock, and a communicator needing
+/// more threads than `max` is allowed to run alone.  A helper thread
+/// finishes when its stream is exhausted, or when the communicator is
+/// dropped and the thread's pending read or write completes, so
+/// communicators should not be kept around unnecessarily while a limit
+/// is in effect.
+///
+/// `max` of 0, the default, means no limit.  On Unix, communication is
+/// done in the calling thread using `poll()`, so the limit has no
+/// effect.
+///
+/// [`Communicator`]: struct.Communicator.html
+/// [`Popen::communicate`]: struct.Popen.html#method.communicate
+/// [`Exec::capture`]: struct.Exec.html#method.capture
+pub fn set_max_reader_threads(max: usize) {
+    let instance = ReaderThreads::get();
+    instance.state.lock().unwrap().0 = max;
+    instance.released.notify_all();
+}
+
+fn is_closed_by_peer(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset)
+}
+
 /// Unattended data exchange with the subprocess.
 ///
 /// When a subprocess both expects input and provides output, care must be
@@ -433,6 +740,9 @@ pub struct Communicator {
     inner: RawCommunicator,
     size_limit: Option<usize>,
     time_limit: Option<Duration>,
+    deadline: Option<Instant>,
+    stdout_prefix: Vec<u8>,
+    process: Option<LivenessToken>,
 }
 
 impl Communicator {
@@ -446,6 +756,45 @@ impl Communicator {
             inner: RawCommunicator::new(stdin, stdout, stderr, input_data),
             size_limit: None,
             time_limit: None,
+            deadline: None,
+            stdout_prefix: vec![],
+            process: None,
+        }
+    }
+
+    // Output already read from stdout by Popen::wait_for_output, to be
+    // returned ahead of the data read from the pipe.
+    pub(crate) fn stdout_prefix(mut self, prefix: Vec<u8>) -> Communicator {
+        self.stdout_prefix = prefix;
+        self
+    }
+
+    // The process communicated with, to be killed once the deadline
+    // passes.
+    pub(crate) fn process(mut self, process: LivenessToken) -> Communicator {
+        self.process = Some(process);
+        self
+    }
+
+    fn read_deadline(&self) -> Option<Instant> {
+        match (self.time_limit, self.deadline) {
+            (Some(timeout), Some(deadline)) => Some(min(Instant::now() + timeout, deadline)),
+            (Some(timeout), None) => Some(Instant::now() + timeout),
+            (None, deadline) => deadline,
+        }
+    }
+
+    // Kill the process if a read bounded by read_deadline timed out
+    // because of the overall deadline rather than the time limit.
+    fn check_deadline(&self, error: &io::Error, read_deadline: Option<Instant>) {
+        if error.kind() != ErrorKind::TimedOut
+            || read_deadline.is_none()
+            || read_deadline != self.deadline
+        {
+            return;
+        }
+        if let Some(ref process) = self.process {
+            process.kill().ok();
         }
     }
 
@@ -473,6 +822,12 @@ impl Communicator {
     /// Subsequent data can be retrieved by calling `read()` again, which can
     /// be repeated until `read()` returns all-empty data, which marks EOF.
     ///
+    /// If the subprocess closes its standard input before consuming all of
+    /// the input data, e.g. because it exits without reading it, the rest
+    /// of the input is silently discarded.  The same happens when both
+    /// output and error (of those redirected to a pipe) reach end-of-file
+    /// while input is still pending.
+    ///
     /// Note that this method does not wait for the subprocess to finish, only
     /// to close its output/error streams.  It is rare but possible for the
     /// program to continue running after having closed the streams, in which
@@ -490,18 +845,78 @@ impl Communicator {
     /// # Errors
     ///
     /// * `Err(CommunicateError)` if a system call fails.  In case of timeout,
-    /// the underlying error kind will be `ErrorKind::TimedOut`.
+    ///   the underlying error kind will be `ErrorKind::TimedOut`.
     ///
     /// Regardless of the nature of the error, the content prior to the error
     /// can be retrieved using the [`capture`] attribute of the error.
     ///
     /// [`capture`]: struct.CommunicateError.html#structfield.capture
-
     pub fn read(&mut self) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), CommunicateError> {
-        let deadline = self.time_limit.map(|timeout| Instant::now() + timeout);
-        match self.inner.read(deadline, self.size_limit) {
-            (None, capture) => Ok(capture),
-            (Some(error), capture) => Err(CommunicateError { error, capture }),
+        let deadline = self.read_deadline();
+        let (error, mut capture) = self.inner.read(deadline, self.size_limit);
+        if let Some(ref mut out) = capture.0 {
+            if !self.stdout_prefix.is_empty() {
+                let mut prefixed = mem::take(&mut self.stdout_prefix);
+                prefixed.extend_from_slice(out);
+                *out = prefixed;
+            }
+        }
+        match error {
+            None => Ok(capture),
+            Some(error) => {
+                self.check_deadline(&error, deadline);
+                Err(CommunicateError { error, capture })
+            }
+        }
+    }
+
+    /// Communicate with the subprocess, writing its standard output and
+    /// error to the provided writers.
+    ///
+    /// Like `read()`, but instead of accumulating the data in memory, it
+    /// is written to `out` and `err` in chunks as it arrives, until
+    /// end-of-file is reached on both streams.  The chunk size is given
+    /// by `limit_size`, defaulting to 64 KiB.  Streams not redirected to
+    /// a pipe leave the corresponding writer untouched.  Combined with
+    /// [`CaptureSink`], this allows capturing output of arbitrary size
+    /// without holding all of it in memory.
+    ///
+    /// # Errors
+    ///
+    /// * `Err(CommunicateError)` if a system call fails or if writing to
+    ///   `out` or `err` fails.  In case of timeout, the underlying error
+    ///   kind will be `ErrorKind::TimedOut`.
+    ///
+    /// Data captured before the error is written to the writers, so the
+    /// `capture` attribute of the returned error is always empty.
+    ///
+    /// [`CaptureSink`]: struct.CaptureSink.html
+    pub fn read_to(
+        &mut self,
+        out: &mut dyn Write,
+        err: &mut dyn Write,
+    ) -> Result<(), CommunicateError> {
+        let deadline = self.read_deadline();
+        let chunk_size = self.size_limit.unwrap_or(64 * 1024);
+        let to_error = |error| CommunicateError {
+            error,
+            capture: (None, None),
+        };
+        out.write_all(&mem::take(&mut self.stdout_prefix))
+            .map_err(to_error)?;
+        loop {
+            let (read_error, (o, e)) = self.inner.read(deadline, Some(chunk_size));
+            let o = o.unwrap_or_default();
+            let e = e.unwrap_or_default();
+            out.write_all(&o).map_err(to_error)?;
+            err.write_all(&e).map_err(to_error)?;
+            if let Some(error) = read_error {
+                self.check_deadline(&error, deadline);
+                return Err(to_error(error));
+            }
+            if o.is_empty() && e.is_empty() {
+                return Ok(());
+            }
         }
     }
 
@@ -531,6 +946,77 @@ impl Communicator {
         self.time_limit = Some(time);
         self
     }
+
+    /// Limit the time all subsequent `read()` calls may spend reading
+    /// from the subprocess to the absolute point in time `deadline`.
+    ///
+    /// Unlike `limit_time`, which applies to each `read()` separately,
+    /// the deadline is a total budget for the whole interaction,
+    /// regardless of how many reads it takes.  If both are specified,
+    /// whichever expires first applies.  Once the deadline has passed,
+    /// `read()` returns an error of kind `io::ErrorKind::TimedOut`.
+    ///
+    /// When the deadline passes, the subprocess is also killed as with
+    /// [`Popen::kill`], so that a subprocess that keeps producing output
+    /// slowly cannot keep the interaction alive.  This requires the
+    /// `Communicator` to be obtained from [`Popen::communicate_start`]
+    /// or [`Exec::communicate`]; communicators of a [`Pipeline`] don't
+    /// refer to a single process, and leave the processes running.
+    ///
+    /// [`Popen::kill`]: struct.Popen.html#method.kill
+    /// [`Popen::communicate_start`]: struct.Popen.html#method.communicate_start
+    /// [`Exec::communicate`]: struct.Exec.html#method.communicate
+    /// [`Pipeline`]: struct.Pipeline.html
+    pub fn deadline(mut self, deadline: Instant) -> Communicator {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Record the time at which end-of-file is reached on standard
+    /// output and standard error.
+    ///
+    /// This is diagnostic information, useful to find out e.g. whether
+    /// the subprocess closed its standard output before or after
+    /// writing to standard error.  The recorded times are available
+    /// through [`eof_times`].  Tracking doesn't change the data
+    /// returned by `read()`, and is disabled by default.
+    ///
+    /// [`eof_times`]: struct.Communicator.html#method.eof_times
+    pub fn track_eof(mut self) -> Communicator {
+        self.inner.track_eof();
+        self
+    }
+
+    /// Return the times at which end-of-file was observed on standard
+    /// output and standard error, respectively.
+    ///
+    /// A time is `None` if the stream was not redirected to a pipe, if
+    /// end-of-file hasn't been reached yet, or if [`track_eof`] was not
+    /// called before reading.
+    ///
+    /// [`track_eof`]: struct.Communicator.html#method.track_eof
+    pub fn eof_times(&self) -> (Option<Instant>, Option<Instant>) {
+        self.inner.eof_times()
+    }
+
+    /// Return the number of bytes of input data written to the
+    /// subprocess so far.
+    ///
+    /// This is useful for finding out how much of the input was
+    /// delivered when the subprocess exits without reading all of it,
+    /// which `read()` doesn't treat as an error, or when writing fails.
+    /// The count includes data accepted by the pipe that the subprocess
+    /// didn't get around to reading before it exited, up to the size of
+    /// the pipe buffer.
+    ///
+    /// On Unix, if writing the input fails for a reason other than the
+    /// subprocess closing its standard input, the standard input is kept
+    /// open, and calling `read()` again resumes writing from where it
+    /// left off.  On Windows, the input is written by a helper thread
+    /// that gives up on the first error.
+    pub fn input_written(&self) -> usize {
+        self.inner.input_written()
+    }
 }
 
 pub fn communicate(
@@ -587,3 +1073,171 @@ impl fmt::Display for CommunicateError {
         self.error.fmt(f)
     }
 }
+
+// A file in the temporary directory, removed when dropped.  It holds
+// captured output or arguments that may be sensitive, so on Unix it is
+// created readable and writable only by the owner.
+#[derive(Debug)]
+pub(crate) struct TempFile {
+    pub(crate) file: File,
+    pub(crate) path: PathBuf,
+}
+
+impl TempFile {
+    pub(crate) fn create() -> io::Result<TempFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        loop {
+            let path = env::temp_dir().join(format!(
+                "subprocess-{}-{}-{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                nanos
+            ));
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            match options.open(&path) {
+                Ok(file) => return Ok(TempFile { file, path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug)]
+enum SinkState {
+    Memory(Vec<u8>),
+    Spilled(TempFile),
+}
+
+/// Destination for captured output that spills to a temporary file once
+/// it grows large.
+///
+/// Data written to the sink is kept in memory until its size exceeds the
+/// threshold given to [`new`], after which the data is moved to a
+/// temporary file and all further writes go to the file.  This allows
+/// capturing output of unbounded size without unbounded memory use.  The
+/// captured data is retrieved with [`into_reader`].
+///
+/// The temporary file is a regular file on disk, created in
+/// `std::env::temp_dir()` and removed when the sink or the reader
+/// obtained from it is dropped.  On Unix-like systems it is created with
+/// mode 0600, so that other users cannot read the captured output.
+///
+/// [`new`]: struct.CaptureSink.html#method.new
+/// [`into_reader`]: struct.CaptureSink.html#method.into_reader
+#[derive(Debug)]
+pub struct CaptureSink {
+    state: SinkState,
+    threshold: usize,
+    len: u64,
+}
+
+impl CaptureSink {
+    /// Create a sink that keeps up to `threshold` bytes in memory.
+    pub fn new(threshold: usize) -> CaptureSink {
+        CaptureSink {
+            state: SinkState::Memory(Vec::new()),
+            threshold,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of bytes written to the sink.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if no data was written to the sink.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true if the data has been moved to a temporary file.
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.state, SinkState::Spilled(_))
+    }
+
+    /// Returns a reader over the data written to the sink.
+    ///
+    /// If the data was spilled to a temporary file, the reader takes
+    /// over the file and removes it when dropped.
+    pub fn into_reader(self) -> io::Result<CaptureReader> {
+        Ok(CaptureReader {
+            state: match self.state {
+                SinkState::Memory(data) => ReaderState::Memory(Cursor::new(data)),
+                SinkState::Spilled(mut tmp) => {
+                    tmp.file.flush()?;
+                    tmp.file.seek(SeekFrom::Start(0))?;
+                    ReaderState::Spilled(tmp)
+                }
+            },
+        })
+    }
+}
+
+impl Write for CaptureSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let SinkState::Memory(ref data) = self.state {
+            if data.len() + buf.len() > self.threshold {
+                let mut tmp = TempFile::create()?;
+                tmp.file.write_all(data)?;
+                self.state = SinkState::Spilled(tmp);
+            }
+        }
+        let n = match self.state {
+            SinkState::Memory(ref mut data) => {
+                data.extend_from_slice(buf);
+                buf.len()
+            }
+            SinkState::Spilled(ref mut tmp) => tmp.file.write(buf)?,
+        };
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.state {
+            SinkState::Memory(_) => Ok(()),
+            SinkState::Spilled(ref mut tmp) => tmp.file.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ReaderState {
+    Memory(Cursor<Vec<u8>>),
+    Spilled(TempFile),
+}
+
+/// Reader over the data captured by a [`CaptureSink`].
+///
+/// [`CaptureSink`]: struct.CaptureSink.html
+#[derive(Debug)]
+pub struct CaptureReader {
+    state: ReaderState,
+}
+
+impl Read for CaptureReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.state {
+            ReaderState::Memory(ref mut cursor) => cursor.read(buf),
+            ReaderState::Spilled(ref mut tmp) => tmp.file.read(buf),
+        }
+    }
+}
@@ -87,13 +87,32 @@ mod win32;
 
 mod os_common;
 
-pub use self::builder::{CaptureData, Exec, NullFile, Pipeline};
-pub use self::communicate::{CommunicateError, Communicator};
+pub use self::builder::{
+    CaptureData, Encoding, Exec, ExecPlan, MmapFile, NullFile, Pipeline, SpooledCaptureData,
+};
+pub use self::communicate::{
+    set_max_reader_threads, CaptureReader, CaptureSink, CommunicateError, Communicator,
+};
 pub use self::os_common::ExitStatus;
-pub use self::popen::{make_pipe, Popen, PopenConfig, PopenError, Redirection, Result};
+#[cfg(windows)]
+pub use self::popen::ConsoleMode;
+pub use self::popen::{
+    make_pipe, ChildStderr, ChildStdin, ChildStdout, ExecErrorKind, LivenessToken, Popen,
+    PopenConfig, PopenError, Redirection, Result, SpawnMetrics, TerminateOnDrop,
+};
 
 /// Subprocess extensions for Unix platforms.
 pub mod unix {
+    #[cfg(unix)]
+    pub use super::builder::unix::ExecExt;
+    #[cfg(unix)]
+    pub use super::popen::os_ext::*;
+}
+
+/// Subprocess extensions for Windows platforms.
+#[cfg(windows)]
+pub mod windows {
+    pub use super::builder::windows::ExecExt;
     pub use super::popen::os_ext::*;
 }
 
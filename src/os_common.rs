@@ -1,6 +1,13 @@
+use std::fmt;
+
 /// Exit status of a process.
+///
+/// With the `serde` feature enabled, `ExitStatus` implements
+/// `Serialize` and `Deserialize`, using serde's default representation
+/// of enums, e.g. `{"Exited":0}` or `"Undetermined"` in JSON.
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExitStatus {
     /// The process exited with the specified exit code.
     ///
@@ -31,11 +38,97 @@ pub enum ExitStatus {
 
 impl ExitStatus {
     /// True if the exit status of the process is 0.
-    pub fn success(self) -> bool {
-        if let ExitStatus::Exited(0) = self {
-            true
-        } else {
-            false
+    pub const fn success(self) -> bool {
+        matches!(self, ExitStatus::Exited(0))
+    }
+
+    /// Returns the exit code if the process exited normally.
+    ///
+    /// Returns `None` if the process was terminated by a signal or its
+    /// exit status is otherwise unavailable.
+    pub const fn code(self) -> Option<u32> {
+        match self {
+            ExitStatus::Exited(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of the signal that terminated the process.
+    ///
+    /// Returns `None` if the process was not terminated by a signal.
+    /// Always returns `None` on Windows.
+    pub const fn signal(self) -> Option<u8> {
+        match self {
+            ExitStatus::Signaled(signum) => Some(signum),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the signal that terminated the process, such
+    /// as `"SIGTERM"`.
+    ///
+    /// Returns `None` if the process was not terminated by a signal, or
+    /// if the signal number is not one of the standard signals.  Always
+    /// returns `None` on Windows.
+    pub fn signal_name(self) -> Option<&'static str> {
+        match self {
+            ExitStatus::Signaled(signum) => signal_name(signum.into()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_name(signum: i32) -> Option<&'static str> {
+    Some(match signum {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGURG => "SIGURG",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGVTALRM => "SIGVTALRM",
+        libc::SIGPROF => "SIGPROF",
+        libc::SIGWINCH => "SIGWINCH",
+        libc::SIGIO => "SIGIO",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    })
+}
+
+#[cfg(windows)]
+fn signal_name(_signum: i32) -> Option<&'static str> {
+    None
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExitStatus::Exited(code) => write!(f, "exit code {}", code),
+            ExitStatus::Signaled(signum) => match self.signal_name() {
+                Some(name) => write!(f, "signal {} ({})", signum, name),
+                None => write!(f, "signal {}", signum),
+            },
+            ExitStatus::Other(status) => write!(f, "exit status {}", status),
+            ExitStatus::Undetermined => f.write_str("undetermined exit status"),
         }
     }
 }
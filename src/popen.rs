@@ -4,13 +4,24 @@ use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs::File;
-use std::io;
+use std::io::{self, Read, Write};
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{FromRawHandle, RawHandle};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::rc::Rc;
 use std::result;
-use std::time::Duration;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::communicate;
+use crate::communicate::{self, TempFile};
 use crate::os_common::{ExitStatus, StandardStream};
+#[cfg(unix)]
+use crate::posix;
 
 use self::ChildState::*;
 
@@ -66,12 +77,36 @@ pub struct Popen {
 
     child_state: ChildState,
     detached: bool,
+    terminate_on_drop: Option<TerminateOnDrop>,
+    warn_unread_output: Option<fn(&str)>,
+    helper_threads: Vec<(JoinHandle<()>, Receiver<()>)>,
+    temp_files: Vec<TempFile>,
+    stdout_buffer: Vec<u8>,
+    spawn_metrics: Option<SpawnMetrics>,
+    resolved_path: Option<PathBuf>,
+    #[cfg(unix)]
+    max_poll_interval: Duration,
+    #[cfg(unix)]
+    extra_fds: Vec<(i32, File)>,
+    #[cfg(unix)]
+    raw_wait_status: Option<i32>,
+    #[cfg(unix)]
+    pty: Option<File>,
+    pending_exec: Option<os::PendingExec>,
+    #[cfg(windows)]
+    new_process_group: bool,
+    #[cfg(windows)]
+    terminate_requested: bool,
 }
 
 #[derive(Debug)]
 enum ChildState {
     Preparing, // only during construction
-    Running { pid: u32, ext: os::ExtChildState },
+    Running {
+        pid: u32,
+        #[allow(dead_code)]
+        ext: os::ExtChildState,
+    },
     Finished(ExitStatus),
 }
 
@@ -112,6 +147,57 @@ pub struct PopenConfig {
     /// Whether the `Popen` instance is initially detached.
     pub detached: bool,
 
+    /// Terminate the process when the `Popen` instance is dropped.
+    ///
+    /// If specified, dropping a `Popen` whose process is still running
+    /// first asks it to exit as described by [`TerminateOnDrop`], waits
+    /// for at most its grace period, and then forcibly kills it with
+    /// [`Popen::kill`].  Errors encountered in the process are ignored.
+    /// This has no effect if the `Popen` is detached.
+    ///
+    /// [`TerminateOnDrop`]: struct.TerminateOnDrop.html
+    /// [`Popen::kill`]: struct.Popen.html#method.kill
+    pub terminate_on_drop: Option<TerminateOnDrop>,
+
+    /// Report output left unread when the `Popen` instance is dropped.
+    ///
+    /// Redirecting output to a pipe and never reading it is a common
+    /// mistake that can make the child block on a full pipe.  If
+    /// specified, dropping a `Popen` whose `stdout` or `stderr` pipe
+    /// still holds unread data calls the function with a message
+    /// describing the problem.  In debug builds, this defaults to a
+    /// function that prints the message to the standard error, unless
+    /// the `SUBPROCESS_NO_UNREAD_WARNING` environment variable is set.
+    /// In release builds, it defaults to `None`, which doesn't check.
+    /// A function specified here, e.g. one that forwards the message
+    /// to a logging framework, replaces the default, and `None` turns
+    /// the check off.
+    pub warn_unread_output: Option<fn(&str)>,
+
+    /// Retry starting the process on transient errors.
+    ///
+    /// If specified as `Some((attempts, delay))`, a failure to create the
+    /// process that is known to be transient is retried up to `attempts`
+    /// more times, sleeping for `delay` before each retry.  On Unix-like
+    /// systems the transient errors are `EAGAIN`, which `fork()` reports
+    /// when the process limit is reached, and `EINTR`.  On Windows they
+    /// are `ERROR_NOT_ENOUGH_MEMORY` and `ERROR_NO_SYSTEM_RESOURCES`.
+    /// Other errors, such as a missing program, are reported
+    /// immediately.  Defaults to `None`, which doesn't retry.
+    pub spawn_retry: Option<(u32, Duration)>,
+
+    /// Check that the process survives its startup.
+    ///
+    /// If specified, `Popen::create` waits for up to the given duration
+    /// after starting the process.  If the process exits unsuccessfully
+    /// within that time, typically because it rejected its arguments or
+    /// configuration, `create` reports `PopenError::EarlyExit` instead
+    /// of returning a `Popen`.  A process that is still running when the
+    /// duration elapses, or that has exited successfully, is returned
+    /// as usual, so `create` returns early for short-lived processes.
+    /// Defaults to `None`, which doesn't wait.
+    pub early_exit_check: Option<Duration>,
+
     /// Executable to run.
     ///
     /// If provided, this executable will be used to run the program
@@ -121,6 +207,23 @@ pub struct PopenConfig {
     /// even though `executable` is actually running.
     pub executable: Option<OsString>,
 
+    /// Refuse to run a program that is not given by an absolute path.
+    ///
+    /// A program name without a path separator is normally looked up in
+    /// the directories listed in the `PATH` environment variable of the
+    /// current process (not the one passed in [`env`]).  If `PATH`
+    /// includes `.` or a directory writable by others, that lookup can
+    /// run an unexpected binary.  Setting this to true disables the
+    /// lookup: unless the program (i.e. [`executable`] if specified, or
+    /// `argv[0]` otherwise) is an absolute path, `Popen::create` fails
+    /// with `PopenError::LogicError` without starting anything.  Relative
+    /// paths such as `./prog` are rejected as well, because they depend
+    /// on the working directory.  Defaults to false.
+    ///
+    /// [`env`]: #structfield.env
+    /// [`executable`]: #structfield.executable
+    pub require_absolute: bool,
+
     /// Environment variables to pass to the subprocess.
     ///
     /// If this is None, environment variables are inherited from the calling
@@ -135,6 +238,22 @@ pub struct PopenConfig {
     /// None means inherit the working directory from the parent.
     pub cwd: Option<OsString>,
 
+    /// Initial current working directory of the subprocess, given as
+    /// an open directory file descriptor.
+    ///
+    /// If specified, calls `fchdir()` before execing the child process.
+    /// Unlike [`cwd`], this is not subject to races with the directory
+    /// being renamed or replaced between the time it was opened and the
+    /// time the child starts, and works even if the directory has been
+    /// moved or unlinked.  The descriptor is used before the child's
+    /// standard streams are set up and before exec, so it may have
+    /// `FD_CLOEXEC` set, but it must remain open until `Popen::create`
+    /// returns.  Specifying both `cwd` and `cwd_fd` is a `LogicError`.
+    ///
+    /// [`cwd`]: #structfield.cwd
+    #[cfg(unix)]
+    pub cwd_fd: Option<i32>,
+
     /// Set user ID for the subprocess.
     ///
     /// If specified, calls `setuid()` before execing the child process.
@@ -147,6 +266,253 @@ pub struct PopenConfig {
     #[cfg(unix)]
     pub setgid: Option<u32>,
 
+    /// Start the subprocess in a new process group.
+    ///
+    /// If true, calls `setpgid(0, 0)` before execing the child process,
+    /// making it the leader of a new process group whose ID is equal to
+    /// its PID.  The whole group, including any descendants that don't
+    /// change their own group, can then be signaled with
+    /// [`PopenExt::send_signal_group`].  This also keeps signals
+    /// generated by the terminal, such as `SIGINT` on Ctrl-C, from
+    /// reaching the subprocess.  If false, the subprocess stays in the
+    /// process group of the current process.  Defaults to true.
+    ///
+    /// [`PopenExt::send_signal_group`]: unix/trait.PopenExt.html#tymethod.send_signal_group
+    #[cfg(unix)]
+    pub setpgid: bool,
+
+    /// Restrict the subprocess to the specified CPUs.
+    ///
+    /// If specified, calls `sched_setaffinity()` before execing the
+    /// child process, so that it (and any threads it creates) runs
+    /// only on the listed CPUs.  An index that is out of range or
+    /// refers to an offline CPU makes `Popen::create` fail with
+    /// `EINVAL`.
+    #[cfg(target_os = "linux")]
+    pub cpu_affinity: Option<Vec<usize>>,
+
+    /// Adjust the likelihood of the subprocess being chosen by the
+    /// OOM killer.
+    ///
+    /// If specified, the value is written to `/proc/self/oom_score_adj`
+    /// before execing the child process.  It ranges from -1000, which
+    /// exempts the process from being OOM-killed, to 1000, which makes
+    /// it the preferred victim; a positive value can therefore be used
+    /// to ensure that a memory-hungry child is killed before its
+    /// supervisor.  A value out of range makes `Popen::create` fail
+    /// with `EINVAL`, and lowering the score below that of the parent
+    /// requires the `CAP_SYS_RESOURCE` capability, failing with
+    /// `EACCES` otherwise.  The adjustment is inherited by the
+    /// child's own children.
+    #[cfg(target_os = "linux")]
+    pub oom_score_adj: Option<i32>,
+
+    /// Namespaces to create for the subprocess.
+    ///
+    /// A bitwise or of `CLONE_NEW*` flags from the [`libc`] crate, such
+    /// as `libc::CLONE_NEWNS | libc::CLONE_NEWNET`.  If non-zero, the
+    /// child is created with `clone()` instead of `fork()`, so that it
+    /// starts as a member of the new namespaces.  In particular, with
+    /// `CLONE_NEWPID` the child becomes process 1 of the new PID
+    /// namespace.  Defaults to 0, which creates no namespaces.
+    ///
+    /// Creating namespaces other than user namespaces requires the
+    /// `CAP_SYS_ADMIN` capability, unless `CLONE_NEWUSER` is included
+    /// as well, in which case unprivileged users can create them if the
+    /// system allows unprivileged user namespaces.  If the namespaces
+    /// can't be created, `Popen::create` fails with `EPERM` or `EINVAL`.
+    ///
+    /// Inside a new user namespace the child runs as the overflow user
+    /// (normally `nobody`) until a UID mapping is written to its
+    /// `/proc/<pid>/uid_map`, which is left to the caller.  Until then,
+    /// [`setuid`] and [`setgid`] fail, causing `Popen::create` to fail.
+    /// The child's working directory is changed after entering the
+    /// namespaces, so with `CLONE_NEWNS` the `cwd` is resolved in the
+    /// new mount namespace, which starts out as a copy of the current
+    /// one.
+    ///
+    /// [`libc`]: https://docs.rs/libc/
+    /// [`setuid`]: #structfield.setuid
+    /// [`setgid`]: #structfield.setgid
+    #[cfg(target_os = "linux")]
+    pub namespaces: i32,
+
+    /// Signals to block in the subprocess.
+    ///
+    /// Before execing the child process, its signal mask is first
+    /// cleared and `SIGPIPE` is reset to its default disposition.  If
+    /// specified, the listed signals are then blocked using
+    /// `sigprocmask()`.  The signal mask is preserved across exec, so
+    /// the new program starts with these signals blocked, and can
+    /// e.g. install its handlers before unblocking them.
+    #[cfg(unix)]
+    pub block_signals: Option<Vec<i32>>,
+
+    /// Give the subprocess `/dev/null` in place of closed standard
+    /// streams.
+    ///
+    /// A standard stream with `Redirection::None` is inherited from the
+    /// current process.  If the current process runs with some of the
+    /// descriptors 0, 1 and 2 closed, as daemons sometimes do, the child
+    /// inherits them closed as well.  The next file the child opens then
+    /// gets the lowest free descriptor, and is mistaken for its standard
+    /// input or output, so that e.g. diagnostics printed to standard
+    /// error end up overwriting a data file.
+    ///
+    /// If set to true, standard streams that are not redirected and
+    /// would be closed in the child are opened to `/dev/null` before
+    /// execing it, so that the child always starts with valid
+    /// descriptors 0, 1 and 2.  This is off by default, so that closed
+    /// descriptors are passed on unchanged to programs that expect
+    /// them.
+    #[cfg(unix)]
+    pub null_closed_std: bool,
+
+    /// Maximum interval between checks whether the process has finished.
+    ///
+    /// [`Popen::wait_timeout`] polls for the process to finish, starting
+    /// with a short interval which is doubled after each check, up to
+    /// this value.  Lowering it detects the process exit sooner, at the
+    /// cost of more CPU spent polling.  Defaults to 100 milliseconds.
+    ///
+    /// On Linux 5.3 and later, `wait_timeout` is notified of the exit
+    /// through a pidfd and doesn't poll, so this setting only applies
+    /// to older kernels.
+    ///
+    /// [`Popen::wait_timeout`]: struct.Popen.html#method.wait_timeout
+    #[cfg(unix)]
+    pub max_poll_interval: Duration,
+
+    /// Additional file descriptors to set up in the subprocess.
+    ///
+    /// Each entry maps a descriptor number in the subprocess, which must
+    /// be 3 or higher, to a redirection.  This serves programs that
+    /// follow the `--status-fd`/`--pass-fd` convention of communicating
+    /// over a numbered descriptor.
+    ///
+    /// `Redirection::File`, `Redirection::RcFile` and `Redirection::RawFd`
+    /// make the descriptor refer to the given file.  `Redirection::Pipe`
+    /// creates a Unix domain socket pair, which can be used for
+    /// communication in either direction; the parent's end is available
+    /// through [`PopenExt::extra_fd`].  `Redirection::None` is ignored,
+    /// and `Redirection::Merge` is invalid.
+    ///
+    /// Windows has no equivalent of numbered descriptors beyond the
+    /// standard streams, so this is only available on Unix.
+    ///
+    /// [`PopenExt::extra_fd`]: unix/trait.PopenExt.html#tymethod.extra_fd
+    #[cfg(unix)]
+    pub extra_fds: Vec<(i32, Redirection)>,
+
+    /// File descriptors not to be inherited by the subprocess.
+    ///
+    /// Descriptors opened by the Rust standard library are close-on-exec,
+    /// but ones opened by C libraries or inherited from the parent of the
+    /// current process often are not, and leak into every subprocess.
+    /// The listed descriptors are marked close-on-exec in the child
+    /// before it executes the program, leaving all other descriptors,
+    /// and the descriptors in the current process, unchanged.  Entries
+    /// that are not open are ignored.
+    ///
+    /// This is meant for keeping a handful of known sensitive
+    /// descriptors, such as a credentials file or a listening socket,
+    /// away from the subprocess.  Unlike closing all descriptors other
+    /// than the standard streams, it doesn't require knowing every
+    /// descriptor the subprocess does need.  Standard streams and
+    /// `extra_fds` set up for the subprocess are not affected, even if
+    /// listed.
+    ///
+    /// This is only available on Unix.  On Windows, the subprocess
+    /// inherits every handle of the current process that is marked
+    /// inheritable, and there is no equivalent of closing some of them
+    /// in the subprocess alone.
+    ///
+    /// To keep all descriptors other than the ones set up for the
+    /// subprocess from being inherited, use [`close_fds`] instead.
+    ///
+    /// [`close_fds`]: #structfield.close_fds
+    #[cfg(unix)]
+    pub close_fds_list: Vec<i32>,
+
+    /// Don't let the subprocess inherit descriptors other than its
+    /// standard streams and `extra_fds`.
+    ///
+    /// When set, all other descriptors are closed when the subprocess
+    /// executes the program, including ones that are not close-on-exec
+    /// in the current process.  This keeps descriptors opened by C
+    /// libraries or inherited from the parent of the current process
+    /// from leaking into the subprocess, without having to list them
+    /// in [`close_fds_list`].  The descriptors of the current process
+    /// are unchanged.
+    ///
+    /// The descriptors are marked close-on-exec, so they remain open in
+    /// a subprocess started with `fork_run`, which doesn't execute a
+    /// program.  On Linux this takes a single system call; elsewhere,
+    /// it takes one per possible descriptor number, up to the limit on
+    /// open files.  Defaults to `false`.
+    ///
+    /// This is only available on Unix.  On Windows, the subprocess
+    /// inherits every handle of the current process that is marked
+    /// inheritable, including ones created by other code; keeping them
+    /// out would require the handle list of the extended startup
+    /// information, which is not supported.
+    ///
+    /// [`close_fds_list`]: #structfield.close_fds_list
+    #[cfg(unix)]
+    pub close_fds: bool,
+
+    /// Stop the subprocess just before it executes the program, until
+    /// released.
+    ///
+    /// If set to true, the child process is created and set up as
+    /// usual, but then waits for [`PopenExt::release`] before executing
+    /// the program.  This is meant for tests that reproduce races
+    /// between processes: they can create a number of children at
+    /// leisure, and then release them all within a short window.
+    ///
+    /// On Unix-like systems, `Popen::create` returns as soon as the
+    /// child has been forked, and errors in setting up the child or
+    /// executing the program are reported by `release` instead.  On
+    /// Windows, the process is created with `CREATE_SUSPENDED`, so the
+    /// program is loaded but its main thread doesn't run until
+    /// released with [`windows::PopenExt::release`].  If the `Popen`
+    /// is dropped without being released, the child exits, or on
+    /// Windows is terminated, without running the program.  Waiting
+    /// for a child that was not released blocks forever.  Defaults to
+    /// false.
+    ///
+    /// [`PopenExt::release`]: unix/trait.PopenExt.html#tymethod.release
+    /// [`windows::PopenExt::release`]: windows/trait.PopenExt.html#tymethod.release
+    pub exec_barrier: bool,
+
+    /// Console of the subprocess.
+    ///
+    /// Defaults to [`ConsoleMode::Inherit`], which shares the console of
+    /// the current process.
+    ///
+    /// [`ConsoleMode::Inherit`]: enum.ConsoleMode.html#variant.Inherit
+    #[cfg(windows)]
+    pub console_mode: ConsoleMode,
+
+    /// Additional process creation flags.
+    ///
+    /// These are passed to `CreateProcess` along with the flags set by
+    /// [`console_mode`] and the ones that `subprocess` sets internally,
+    /// such as `CREATE_UNICODE_ENVIRONMENT`.  The flags are combined
+    /// with a bitwise OR, so they can add to but not remove any of
+    /// those flags.  This allows requesting e.g. `CREATE_NEW_PROCESS_GROUP`
+    /// or `CREATE_SUSPENDED`; the main thread of a process created
+    /// suspended is resumed with [`windows::PopenExt::release`], and the
+    /// process is terminated if the `Popen` is dropped before that.
+    /// Console flags should be requested through
+    /// `console_mode`, which prevents combining flags that are mutually
+    /// exclusive.  Defaults to 0.
+    ///
+    /// [`console_mode`]: #structfield.console_mode
+    /// [`windows::PopenExt::release`]: windows/trait.PopenExt.html#tymethod.release
+    #[cfg(windows)]
+    pub creation_flags: u32,
+
     // Add this field to force construction using ..Default::default() for
     // backward compatibility.  Unfortunately we can't mark this non-public
     // because then ..Default::default() wouldn't work either.
@@ -170,13 +536,49 @@ impl PopenConfig {
             stdout: self.stdout.try_clone()?,
             stderr: self.stderr.try_clone()?,
             detached: self.detached,
+            terminate_on_drop: self.terminate_on_drop,
+            warn_unread_output: self.warn_unread_output,
+            spawn_retry: self.spawn_retry,
+            early_exit_check: self.early_exit_check,
             executable: self.executable.as_ref().cloned(),
+            require_absolute: self.require_absolute,
             env: self.env.clone(),
             cwd: self.cwd.clone(),
             #[cfg(unix)]
+            cwd_fd: self.cwd_fd,
+            #[cfg(unix)]
             setuid: self.setuid,
             #[cfg(unix)]
             setgid: self.setgid,
+            #[cfg(unix)]
+            setpgid: self.setpgid,
+            #[cfg(target_os = "linux")]
+            cpu_affinity: self.cpu_affinity.clone(),
+            #[cfg(target_os = "linux")]
+            oom_score_adj: self.oom_score_adj,
+            #[cfg(target_os = "linux")]
+            namespaces: self.namespaces,
+            #[cfg(unix)]
+            block_signals: self.block_signals.clone(),
+            #[cfg(unix)]
+            null_closed_std: self.null_closed_std,
+            #[cfg(unix)]
+            max_poll_interval: self.max_poll_interval,
+            #[cfg(unix)]
+            extra_fds: self
+                .extra_fds
+                .iter()
+                .map(|(fd, redirection)| Ok((*fd, redirection.try_clone()?)))
+                .collect::<io::Result<_>>()?,
+            #[cfg(unix)]
+            close_fds_list: self.close_fds_list.clone(),
+            #[cfg(unix)]
+            close_fds: self.close_fds,
+            exec_barrier: self.exec_barrier,
+            #[cfg(windows)]
+            console_mode: self.console_mode,
+            #[cfg(windows)]
+            creation_flags: self.creation_flags,
             _use_default_to_construct: (),
         })
     }
@@ -197,18 +599,90 @@ impl Default for PopenConfig {
             stdout: Redirection::None,
             stderr: Redirection::None,
             detached: false,
+            terminate_on_drop: None,
+            #[cfg(debug_assertions)]
+            warn_unread_output: Some(print_unread_warning),
+            #[cfg(not(debug_assertions))]
+            warn_unread_output: None,
+            spawn_retry: None,
+            early_exit_check: None,
             executable: None,
+            require_absolute: false,
             env: None,
             cwd: None,
             #[cfg(unix)]
+            cwd_fd: None,
+            #[cfg(unix)]
             setuid: None,
             #[cfg(unix)]
             setgid: None,
+            #[cfg(unix)]
+            setpgid: true,
+            #[cfg(target_os = "linux")]
+            cpu_affinity: None,
+            #[cfg(target_os = "linux")]
+            oom_score_adj: None,
+            #[cfg(target_os = "linux")]
+            namespaces: 0,
+            #[cfg(unix)]
+            block_signals: None,
+            #[cfg(unix)]
+            null_closed_std: false,
+            #[cfg(unix)]
+            max_poll_interval: Duration::from_millis(100),
+            #[cfg(unix)]
+            extra_fds: vec![],
+            #[cfg(unix)]
+            close_fds_list: vec![],
+            #[cfg(unix)]
+            close_fds: false,
+            exec_barrier: false,
+            #[cfg(windows)]
+            console_mode: ConsoleMode::Inherit,
+            #[cfg(windows)]
+            creation_flags: 0,
             _use_default_to_construct: (),
         }
     }
 }
 
+/// Console of a subprocess on Windows, see
+/// [`PopenConfig::console_mode`].
+///
+/// Each variant corresponds to a single process creation flag, which
+/// prevents combining flags that are mutually exclusive.
+///
+/// [`PopenConfig::console_mode`]: struct.PopenConfig.html#structfield.console_mode
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleMode {
+    /// Share the console of the current process.  No flag is set.
+    Inherit,
+    /// Create a new console window for the subprocess.  Sets
+    /// `CREATE_NEW_CONSOLE`.
+    New,
+    /// Run the subprocess without a console.  A console application can
+    /// still create one by calling `AllocConsole`.  Sets
+    /// `DETACHED_PROCESS`.
+    None,
+    /// Give the subprocess a console without a window, useful for
+    /// running console applications from GUI programs without flashing
+    /// a window.  Sets `CREATE_NO_WINDOW`.
+    Hidden,
+}
+
+#[cfg(windows)]
+impl ConsoleMode {
+    fn creation_flags(self) -> u32 {
+        match self {
+            ConsoleMode::Inherit => 0,
+            ConsoleMode::New => crate::win32::CREATE_NEW_CONSOLE,
+            ConsoleMode::None => crate::win32::DETACHED_PROCESS,
+            ConsoleMode::Hidden => crate::win32::CREATE_NO_WINDOW,
+        }
+    }
+}
+
 /// Instruction what to do with a stream in the child process.
 ///
 /// `Redirection` values are used for the `stdin`, `stdout`, and
@@ -218,6 +692,7 @@ impl Default for PopenConfig {
 /// parent.
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Redirection {
     /// Do nothing with the stream.
     ///
@@ -235,6 +710,26 @@ pub enum Redirection {
     ///
     /// The field with `Popen` corresponding to the stream will be
     /// `Some(file)`, `File` being the parent's end of the pipe.
+    ///
+    /// The pipe transfers bytes exactly as written, on all platforms;
+    /// `subprocess` never translates line endings.  Note, however, that
+    /// on Windows a child built against the C runtime may open its
+    /// standard streams in text mode, in which case the child itself
+    /// converts `\n` to `\r\n` on output and `\r\n` to `\n` on input.
+    /// `subprocess` doesn't pass the C runtime's file mode information
+    /// to the child, so this can only be controlled by the child, e.g.
+    /// by calling `_setmode(fd, _O_BINARY)`.
+    ///
+    /// A pipe is not a terminal, so a child that checks `isatty()`
+    /// typically switches to non-interactive behavior, e.g. buffers its
+    /// output or disables colors.  Programs that need a terminal
+    /// regardless, such as pagers and editors, usually open `/dev/tty`,
+    /// which refers to the controlling terminal of the current process,
+    /// if any, and is not affected by redirection.  On Unix-like
+    /// systems, [`Redirection::Pty`] connects the child to a
+    /// pseudo-terminal instead.
+    ///
+    /// [`Redirection::Pty`]: #variant.Pty
     Pipe,
 
     /// Merge the stream to the other output stream.
@@ -264,6 +759,11 @@ pub enum Redirection {
     /// from or write to the provided file on its own, without any
     /// intervention by the parent.
     ///
+    /// For feeding a large file on disk to the child's standard input,
+    /// this is more efficient than any copying done by the parent,
+    /// including copying from a memory mapping: the parent never touches
+    /// the data, and the child reads it directly from the file.
+    ///
     /// The field in `Popen` corresponding to the stream will be
     /// `None`.
     File(File),
@@ -272,6 +772,71 @@ pub enum Redirection {
     ///
     /// This allows the same file to be used in multiple redirections.
     RcFile(Rc<File>),
+
+    /// Redirect the stream to the specified raw file descriptor.
+    ///
+    /// This is useful for descriptors not owned by a `File`, such as
+    /// a socket or a descriptor inherited from the service manager.
+    /// The descriptor is duplicated for the child, so it remains open
+    /// and owned by the caller, who must keep it open until the child
+    /// has been created.
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `None`.
+    #[cfg(unix)]
+    RawFd(RawFd),
+
+    /// Connect the stream to a pseudo-terminal.
+    ///
+    /// All standard streams specified as `Pty` are connected to the
+    /// same newly created pseudo-terminal, which the child sees as a
+    /// regular terminal: it passes `isatty()` checks, and the child and
+    /// its descendants can open it as `/dev/tty`.  To that end, the
+    /// child becomes the leader of a new session, regardless of
+    /// [`PopenConfig::setpgid`], with the pseudo-terminal as its
+    /// controlling terminal.  This allows driving interactive and
+    /// full-screen programs, such as shells, pagers, and editors.
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `Some(file)`, `File` being the parent's (master) end of the
+    /// pseudo-terminal.  Since the output written to stdout and stderr
+    /// arrives at the same terminal, if both are specified as `Pty`, it
+    /// is all read through `stdout`, and `stderr` will be `None`.
+    ///
+    /// Unlike a pipe, the terminal processes the data passing through
+    /// it according to its settings.  By default, it echoes the input,
+    /// and `\n` written by the child is read as `\r\n`.  Closing the
+    /// parent's `stdin` doesn't signal the end of input, which is done
+    /// by writing the end-of-file character, `\x04` by default, at the
+    /// start of a line.  Once the child and its descendants have closed
+    /// the terminal, reading from it may fail with `EIO` instead of
+    /// reporting end of file; the `communicate` family of methods treats
+    /// that as end of file.
+    ///
+    /// The terminal is created with a size of zero rows and columns,
+    /// which can be changed with [`PopenExt::set_pty_size`].  To set it
+    /// before the program starts, combine this with
+    /// [`PopenConfig::exec_barrier`].
+    ///
+    /// This is only available on Unix-like systems.
+    ///
+    /// [`PopenConfig::setpgid`]: struct.PopenConfig.html#structfield.setpgid
+    /// [`PopenConfig::exec_barrier`]: struct.PopenConfig.html#structfield.exec_barrier
+    /// [`PopenExt::set_pty_size`]: unix/trait.PopenExt.html#tymethod.set_pty_size
+    #[cfg(unix)]
+    Pty,
+
+    /// Redirect the stream to the specified raw handle.
+    ///
+    /// This is useful for handles not owned by a `File`, such as a
+    /// socket.  The handle is duplicated for the child, so it remains
+    /// open and owned by the caller, who must keep it open until the
+    /// child has been created.
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `None`.
+    #[cfg(windows)]
+    RawHandle(RawHandle),
 }
 
 impl Redirection {
@@ -284,11 +849,31 @@ impl Redirection {
             Redirection::Pipe => Redirection::Pipe,
             Redirection::Merge => Redirection::Merge,
             Redirection::File(ref f) => Redirection::File(f.try_clone()?),
-            Redirection::RcFile(ref f) => Redirection::RcFile(Rc::clone(&f)),
+            Redirection::RcFile(ref f) => Redirection::RcFile(Rc::clone(f)),
+            #[cfg(unix)]
+            Redirection::RawFd(fd) => Redirection::RawFd(fd),
+            #[cfg(unix)]
+            Redirection::Pty => Redirection::Pty,
+            #[cfg(windows)]
+            Redirection::RawHandle(handle) => Redirection::RawHandle(handle),
         })
     }
 }
 
+// Duplicate a descriptor owned by the caller, leaving it open.
+#[cfg(unix)]
+fn dup_raw(fd: RawFd) -> io::Result<File> {
+    let borrowed = mem::ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    borrowed.try_clone()
+}
+
+// Duplicate a handle owned by the caller, leaving it open.
+#[cfg(windows)]
+fn dup_raw(handle: RawHandle) -> io::Result<File> {
+    let borrowed = mem::ManuallyDrop::new(unsafe { File::from_raw_handle(handle) });
+    borrowed.try_clone()
+}
+
 impl Popen {
     /// Execute an external program in a new process.
     ///
@@ -306,6 +891,15 @@ impl Popen {
     /// # }
     /// ```
     ///
+    /// When `create` returns `Ok`, the program has been successfully
+    /// executed, i.e. the child is no longer a copy of the parent.  On
+    /// Unix-like systems, the child reports the failure of any step up
+    /// to and including `exec()` through a close-on-exec pipe, and the
+    /// parent waits until the pipe is either written to or closed by a
+    /// successful `exec()`.  On Windows, `CreateProcess` only succeeds
+    /// once the program has been loaded.  The time it took to get to
+    /// this point is available from [`spawn_metrics`].
+    ///
     /// # Errors
     ///
     /// If the external program cannot be executed for any reason, an
@@ -315,20 +909,122 @@ impl Popen {
     /// program running and then exiting with a failure code - this
     /// can be detected by calling the `wait` method to obtain its
     /// exit status.
+    ///
+    /// Failure to create the process, e.g. due to the process limit,
+    /// is reported as `PopenError::SpawnError`, and failure to execute
+    /// the program, e.g. because it doesn't exist, as
+    /// `PopenError::ExecError`.  If [`early_exit_check`] is specified,
+    /// a program that exits unsuccessfully right after starting is
+    /// reported as `PopenError::EarlyExit`.  Other errors, such as
+    /// failure to set up redirections or to change to the working
+    /// directory, are reported as `PopenError::IoError`.
+    ///
+    /// [`spawn_metrics`]: struct.Popen.html#method.spawn_metrics
+    /// [`early_exit_check`]: struct.PopenConfig.html#structfield.early_exit_check
     pub fn create(argv: &[impl AsRef<OsStr>], config: PopenConfig) -> Result<Popen> {
         if argv.is_empty() {
             return Err(PopenError::LogicError("argv must not be empty"));
         }
+        if config.require_absolute {
+            let program = config.executable.as_deref().unwrap_or(argv[0].as_ref());
+            if !Path::new(program).is_absolute() {
+                return Err(PopenError::LogicError("program must be an absolute path"));
+            }
+        }
         let argv: Vec<OsString> = argv.iter().map(|p| p.as_ref().to_owned()).collect();
-        let mut inst = Popen {
+        let mut inst = Popen::new(&config);
+        let early_exit_check = config.early_exit_check;
+        inst.os_start(argv, config)?;
+        if let Some(window) = early_exit_check {
+            if let Some(status) = inst.wait_timeout(window)? {
+                if !status.success() {
+                    return Err(PopenError::EarlyExit(status));
+                }
+            }
+        }
+        Ok(inst)
+    }
+
+    /// Adopt a child process spawned by `std::process::Command`.
+    ///
+    /// This allows using the methods of `Popen`, such as `wait_timeout`
+    /// and `communicate`, on a process started by the standard library.
+    /// The pipes of the child's standard streams, if any, are moved to
+    /// the corresponding `stdin`, `stdout` and `stderr` fields.  The
+    /// resulting `Popen` behaves like one created with default
+    /// configuration: it waits for the process when dropped, unless
+    /// detached.
+    ///
+    /// The child must not have been waited for already.  If it has,
+    /// e.g. by a successful call to `Child::try_wait`, its exit status
+    /// is no longer available, and on Unix-like systems waiting for it
+    /// reports `ExitStatus::Undetermined`.
+    pub fn from_std(mut child: process::Child) -> Popen {
+        let mut inst = Popen::new(&PopenConfig::default());
+        inst.stdin = child.stdin.take().map(os::std_stream_into_file);
+        inst.stdout = child.stdout.take().map(os::std_stream_into_file);
+        inst.stderr = child.stderr.take().map(os::std_stream_into_file);
+        let (pid, ext) = os::adopt_std_child(child);
+        inst.child_state = Running { pid, ext };
+        inst
+    }
+
+    // Construct a Popen for a child that is yet to be started.
+    fn new(config: &PopenConfig) -> Popen {
+        Popen {
             stdin: None,
             stdout: None,
             stderr: None,
             child_state: ChildState::Preparing,
             detached: config.detached,
-        };
-        inst.os_start(argv, config)?;
-        Ok(inst)
+            terminate_on_drop: config.terminate_on_drop,
+            warn_unread_output: config.warn_unread_output,
+            helper_threads: vec![],
+            temp_files: vec![],
+            stdout_buffer: vec![],
+            spawn_metrics: None,
+            resolved_path: None,
+            #[cfg(unix)]
+            max_poll_interval: config.max_poll_interval,
+            #[cfg(unix)]
+            extra_fds: vec![],
+            #[cfg(unix)]
+            raw_wait_status: None,
+            #[cfg(unix)]
+            pty: None,
+            pending_exec: None,
+            #[cfg(windows)]
+            new_process_group: false,
+            #[cfg(windows)]
+            terminate_requested: false,
+        }
+    }
+
+    /// Execute an external program in a new process, with the standard
+    /// streams given as an array.
+    ///
+    /// `stdio` holds the redirections for the standard input, output,
+    /// and error, in that order, and overrides the `stdin`, `stdout`,
+    /// and `stderr` fields of `config`.  This is otherwise equivalent
+    /// to [`create`], and is convenient when the redirections are
+    /// computed programmatically.
+    ///
+    /// [`create`]: struct.Popen.html#method.create
+    pub fn create_with_stdio(
+        argv: &[impl AsRef<OsStr>],
+        stdio: [Redirection; 3],
+        config: PopenConfig,
+    ) -> Result<Popen> {
+        let [stdin, stdout, stderr] = stdio;
+        Popen::create(
+            argv,
+            PopenConfig {
+                stdin,
+                stdout,
+                stderr,
+                ..config
+            },
+        )
     }
 
     // Create the pipes requested by stdin, stdout, and stderr from
@@ -341,6 +1037,10 @@ impl Popen {
     //
     // For Redirection::File, this transfers the ownership of the File
     // to the corresponding child.
+    //
+    // For Redirection::Pty, this creates the pseudo-terminal shared by
+    // all such streams, stores its master to self.pty, and gives the
+    // parent a duplicate of the master and the child the slave.
     fn setup_streams(
         &mut self,
         stdin: Redirection,
@@ -354,7 +1054,11 @@ impl Popen {
         ) -> Result<()> {
             // Store the parent's end of the pipe into the given
             // reference, and store the child end.
-            let (read, write) = os::make_pipe()?;
+            let (read, write) = if parent_writes {
+                os::make_pipe()?
+            } else {
+                os::make_parent_read_pipe()?
+            };
             let (parent_end, child_end) = if parent_writes {
                 (write, read)
             } else {
@@ -377,6 +1081,24 @@ impl Popen {
             *child_ref = Some(file);
             Ok(())
         }
+        #[cfg(unix)]
+        fn prepare_pty(
+            pty: &mut Option<(File, Rc<File>)>,
+            parent_ref: Option<&mut Option<File>>,
+            child_ref: &mut Option<Rc<File>>,
+        ) -> io::Result<()> {
+            if pty.is_none() {
+                let (master, slave) = posix::openpty()?;
+                os::set_inheritable(&slave, true)?;
+                *pty = Some((master, Rc::new(slave)));
+            }
+            let (master, slave) = pty.as_ref().unwrap();
+            if let Some(parent_ref) = parent_ref {
+                *parent_ref = Some(master.try_clone()?);
+            }
+            *child_ref = Some(Rc::clone(slave));
+            Ok(())
+        }
         fn reuse_stream(
             dest: &mut Option<Rc<File>>,
             src: &mut Option<Rc<File>>,
@@ -400,11 +1122,23 @@ impl Popen {
         let mut merge: MergeKind = MergeKind::None;
 
         let (mut child_stdin, mut child_stdout, mut child_stderr) = (None, None, None);
+        #[cfg(unix)]
+        let mut pty = None;
+        // the output of both streams is read from the terminal through
+        // stdout
+        #[cfg(unix)]
+        let stdout_pty = matches!(stdout, Redirection::Pty);
 
         match stdin {
             Redirection::Pipe => prepare_pipe(true, &mut self.stdin, &mut child_stdin)?,
             Redirection::File(file) => prepare_file(file, &mut child_stdin)?,
             Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdin)?,
+            #[cfg(unix)]
+            Redirection::RawFd(fd) => prepare_file(dup_raw(fd)?, &mut child_stdin)?,
+            #[cfg(unix)]
+            Redirection::Pty => prepare_pty(&mut pty, Some(&mut self.stdin), &mut child_stdin)?,
+            #[cfg(windows)]
+            Redirection::RawHandle(handle) => prepare_file(dup_raw(handle)?, &mut child_stdin)?,
             Redirection::Merge => {
                 return Err(PopenError::LogicError(
                     "Redirection::Merge not valid for stdin",
@@ -416,6 +1150,12 @@ impl Popen {
             Redirection::Pipe => prepare_pipe(false, &mut self.stdout, &mut child_stdout)?,
             Redirection::File(file) => prepare_file(file, &mut child_stdout)?,
             Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdout)?,
+            #[cfg(unix)]
+            Redirection::RawFd(fd) => prepare_file(dup_raw(fd)?, &mut child_stdout)?,
+            #[cfg(unix)]
+            Redirection::Pty => prepare_pty(&mut pty, Some(&mut self.stdout), &mut child_stdout)?,
+            #[cfg(windows)]
+            Redirection::RawHandle(handle) => prepare_file(dup_raw(handle)?, &mut child_stdout)?,
             Redirection::Merge => merge = MergeKind::OutToErr,
             Redirection::None => (),
         };
@@ -423,6 +1163,19 @@ impl Popen {
             Redirection::Pipe => prepare_pipe(false, &mut self.stderr, &mut child_stderr)?,
             Redirection::File(file) => prepare_file(file, &mut child_stderr)?,
             Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stderr)?,
+            #[cfg(unix)]
+            Redirection::RawFd(fd) => prepare_file(dup_raw(fd)?, &mut child_stderr)?,
+            #[cfg(unix)]
+            Redirection::Pty => {
+                let parent_ref = if stdout_pty {
+                    None
+                } else {
+                    Some(&mut self.stderr)
+                };
+                prepare_pty(&mut pty, parent_ref, &mut child_stderr)?
+            }
+            #[cfg(windows)]
+            Redirection::RawHandle(handle) => prepare_file(dup_raw(handle)?, &mut child_stderr)?,
             Redirection::Merge => merge = MergeKind::ErrToOut,
             Redirection::None => (),
         };
@@ -443,6 +1196,10 @@ impl Popen {
             }
             MergeKind::None => (),
         }
+        #[cfg(unix)]
+        {
+            self.pty = pty.map(|(master, _)| master);
+        }
 
         Ok((child_stdin, child_stdout, child_stderr))
     }
@@ -458,6 +1215,295 @@ impl Popen {
         self.detached = true;
     }
 
+    /// Close the parent's end of the child's standard input.
+    ///
+    /// This signals end of input to the child while keeping its
+    /// standard output and error available for reading, i.e. performs
+    /// a "half-close".  It is equivalent to dropping `self.stdin`,
+    /// and does nothing if `stdin` is not a pipe or was already
+    /// closed.
+    pub fn close_stdin(&mut self) {
+        self.stdin.take();
+    }
+
+    /// Close the parent's end of the child's standard output.
+    ///
+    /// Equivalent to dropping `self.stdout`.  Does nothing if `stdout`
+    /// is not a pipe or was already closed.
+    pub fn close_stdout(&mut self) {
+        self.stdout.take();
+    }
+
+    /// Close the parent's end of the child's standard error.
+    ///
+    /// Equivalent to dropping `self.stderr`.  Does nothing if `stderr`
+    /// is not a pipe or was already closed.
+    pub fn close_stderr(&mut self) {
+        self.stderr.take();
+    }
+
+    /// Return whether the parent holds a pipe to the child's standard
+    /// input, i.e. whether `self.stdin` is `Some`.
+    ///
+    /// Input data may only be passed to [`communicate_start`] and
+    /// friends if this returns true.
+    ///
+    /// [`communicate_start`]: struct.Popen.html#method.communicate_start
+    pub fn has_stdin_pipe(&self) -> bool {
+        self.stdin.is_some()
+    }
+
+    /// Read from the child's standard output, waiting no longer than
+    /// `timeout` for data to arrive.
+    ///
+    /// Returns `Ok(Some(n))` after reading `n` bytes into `buf`, and
+    /// `Ok(None)` if no data arrived within `timeout`.  As with `Read`,
+    /// `Ok(Some(0))` means end-of-file.  This is useful for interactive
+    /// drivers that read `self.stdout` themselves, but don't want to
+    /// wait indefinitely for the next response.
+    ///
+    /// On Unix-like systems this waits with `poll()` and then reads with
+    /// the pipe temporarily switched to non-blocking mode.  The pipe is
+    /// switched back to blocking mode before returning, so subsequent
+    /// reads from `self.stdout` are not affected.  On Windows the read
+    /// is started as an overlapped operation, which is cancelled if no
+    /// data arrives within `timeout`.  This requires `self.stdout` to be
+    /// the pipe created by `Popen`, or one created by `std::process` if
+    /// the `Popen` was obtained with [`from_std`].
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `io::ErrorKind::InvalidInput` is returned if
+    /// standard output was not redirected to a pipe or was closed.
+    ///
+    /// [`from_std`]: struct.Popen.html#method.from_std
+    pub fn read_stdout_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Option<usize>> {
+        if !self.stdout_buffer.is_empty() {
+            let n = buf.len().min(self.stdout_buffer.len());
+            buf[..n].copy_from_slice(&self.stdout_buffer[..n]);
+            self.stdout_buffer.drain(..n);
+            return Ok(Some(n));
+        }
+        match self.stdout {
+            Some(ref stdout) => os::read_timeout(stdout, buf, timeout),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdout is not a pipe",
+            )),
+        }
+    }
+
+    /// Read the child's standard output until `pattern` appears in it,
+    /// waiting no longer than `timeout`.
+    ///
+    /// This is meant for launching servers and similar programs that
+    /// announce their readiness by printing a message, such as
+    /// "listening on port 8080".  Returns `Ok(true)` once the output
+    /// contains `pattern`, and `Ok(false)` if the child closes its
+    /// standard output (typically by exiting) without printing it.
+    /// The pattern is found even if it arrives split across several
+    /// reads.
+    ///
+    /// The output read by this method is not lost: it is buffered in
+    /// the `Popen` and returned ahead of the remaining output by
+    /// [`communicate_start`] (and the methods based on it) and by
+    /// [`read_stdout_timeout`].  Reading directly from `self.stdout`
+    /// bypasses the buffer.  The search covers all of the buffered
+    /// output, including output read by previous calls that was not
+    /// consumed since.
+    ///
+    /// Only standard output is read.  If the child also writes a lot
+    /// to a piped standard error without anyone reading it, it can
+    /// block before printing `pattern`.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `io::ErrorKind::TimedOut` is returned if
+    /// `pattern` didn't appear within `timeout`, and one of kind
+    /// `io::ErrorKind::InvalidInput` if standard output was not
+    /// redirected to a pipe or was closed.
+    ///
+    /// [`communicate_start`]: struct.Popen.html#method.communicate_start
+    /// [`read_stdout_timeout`]: struct.Popen.html#method.read_stdout_timeout
+    pub fn wait_for_output(&mut self, pattern: &[u8], timeout: Duration) -> io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut search_from = 0;
+        let mut chunk = [0u8; 4096];
+        loop {
+            if pattern.is_empty()
+                || self.stdout_buffer[search_from..]
+                    .windows(pattern.len())
+                    .any(|w| w == pattern)
+            {
+                return Ok(true);
+            }
+            // a match could still begin in the last pattern.len() - 1 bytes
+            search_from = self.stdout_buffer.len().saturating_sub(pattern.len() - 1);
+            let stdout = match self.stdout {
+                Some(ref stdout) => stdout,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "stdout is not a pipe",
+                    ))
+                }
+            };
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match os::read_timeout(stdout, &mut chunk, timeout)? {
+                Some(0) => return Ok(false),
+                Some(n) => self.stdout_buffer.extend_from_slice(&chunk[..n]),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "pattern not found in output within timeout",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Take the pipes to the child as independent handles.
+    ///
+    /// This moves `self.stdin`, `self.stdout`, and `self.stderr` into
+    /// separate handles that implement `Write` and `Read`, respectively.
+    /// The handles don't borrow the `Popen` and can be sent to different
+    /// threads, allowing full-duplex interaction with the child, such
+    /// as driving an interactive interpreter, where [`communicate`]
+    /// would require all of the input up front.  Streams not redirected
+    /// to a pipe, or already taken, are returned as `None`.
+    ///
+    /// Dropping the [`ChildStdin`] closes the child's standard input,
+    /// which is how the child is told that no more input will arrive.
+    /// Output read by [`wait_for_output`] and not yet consumed is
+    /// returned first by reads from the [`ChildStdout`].
+    ///
+    /// As with any concurrent use of the pipes, the caller must keep
+    /// reading the output while writing the input, or the child can
+    /// block writing output that nobody reads while the parent blocks
+    /// writing input that the child doesn't read.
+    ///
+    /// [`communicate`]: struct.Popen.html#method.communicate
+    /// [`wait_for_output`]: struct.Popen.html#method.wait_for_output
+    /// [`ChildStdin`]: struct.ChildStdin.html
+    /// [`ChildStdout`]: struct.ChildStdout.html
+    pub fn split_io(&mut self) -> (Option<ChildStdin>, Option<ChildStdout>, Option<ChildStderr>) {
+        let stdout_buffer = mem::take(&mut self.stdout_buffer);
+        (
+            self.stdin.take().map(ChildStdin),
+            self.stdout.take().map(|file| ChildStdout {
+                pending: stdout_buffer,
+                file,
+            }),
+            self.stderr.take().map(ChildStderr),
+        )
+    }
+
+    /// Stream the subprocess's standard output through a bounded
+    /// channel.
+    ///
+    /// This takes `self.stdout` and starts a thread that reads from it
+    /// and sends each chunk of data to the returned receiver, ending the
+    /// channel at EOF or on read error.  The channel holds at most
+    /// `capacity` chunks; once it's full, the thread stops reading until
+    /// the consumer catches up, the pipe fills, and the child blocks on
+    /// writing.  A slow consumer therefore slows down the child instead
+    /// of having its output accumulate in memory, as it would with
+    /// [`communicate`].  Output read by [`wait_for_output`] and not yet
+    /// consumed is sent as the first chunk.
+    ///
+    /// Dropping the receiver stops the thread, which closes the parent's
+    /// end of the pipe, so that further writes by the child fail with
+    /// `EPIPE` (or kill it with `SIGPIPE` on Unix).
+    ///
+    /// # Panics
+    ///
+    /// If `stdout` was not redirected to a pipe.
+    ///
+    /// [`communicate`]: struct.Popen.html#method.communicate
+    /// [`wait_for_output`]: struct.Popen.html#method.wait_for_output
+    pub fn stdout_channel(&mut self, capacity: usize) -> Receiver<Vec<u8>> {
+        let mut stdout = self
+            .stdout
+            .take()
+            .expect("stdout_channel called without stdout pipe");
+        let pending = mem::take(&mut self.stdout_buffer);
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        thread::spawn(move || {
+            if !pending.is_empty() && tx.send(pending).is_err() {
+                return;
+            }
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = stdout.read(&mut buf) {
+                if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    /// Return whether the parent holds a pipe to the child's standard
+    /// output, i.e. whether `self.stdout` is `Some`.
+    pub fn has_stdout_pipe(&self) -> bool {
+        self.stdout.is_some()
+    }
+
+    /// Return whether the parent holds a pipe to the child's standard
+    /// error, i.e. whether `self.stderr` is `Some`.
+    pub fn has_stderr_pipe(&self) -> bool {
+        self.stderr.is_some()
+    }
+
+    /// Return a token for checking whether the subprocess is still running.
+    ///
+    /// The token can be cloned and sent to other threads, which can
+    /// then check the liveness of the process without access to the
+    /// `Popen`.  Checking never reaps the process, which remains the
+    /// responsibility of the `Popen` owner.
+    ///
+    /// See [`LivenessToken`] for caveats.
+    ///
+    /// [`LivenessToken`]: struct.LivenessToken.html
+    pub fn liveness_token(&self) -> LivenessToken {
+        LivenessToken(self.os_liveness_token())
+    }
+
+    /// Return the time it took to start the subprocess.
+    ///
+    /// This is diagnostic information, useful to find out whether slow
+    /// process creation is due to the OS or to the program.  It is
+    /// `None` if the `Popen` was not started by this crate.
+    pub fn spawn_metrics(&self) -> Option<SpawnMetrics> {
+        self.spawn_metrics
+    }
+
+    /// Return the path of the executable file that was run.
+    ///
+    /// When the program is given by a bare name such as `"ls"`, it is
+    /// looked up in the `PATH`, so that the file actually run depends
+    /// on the environment.  This returns the file that was found, made
+    /// absolute using the working directory of the subprocess, which is
+    /// useful for audit logs and for noticing an unexpected binary
+    /// shadowing the intended one.
+    ///
+    /// The path is recorded when the process is started, from the
+    /// start itself rather than a separate search: on Unix-like
+    /// systems, the child reports which of the files in the `PATH` it
+    /// executed, and on Windows, the path of the loaded executable is
+    /// queried from the process.  The file may have been replaced or
+    /// removed since it was started, so the path doesn't necessarily
+    /// identify the program that is running.  This is `None` if the
+    /// `Popen` didn't execute a program, such as one started by
+    /// `fork_run` or adopted with `from_std`, or if the path couldn't
+    /// be determined.
+    pub fn resolved_path(&self) -> Option<&Path> {
+        self.resolved_path.as_deref()
+    }
+
     /// Return the PID of the subprocess, if it is known to be still running.
     ///
     /// Note that this method won't actually *check* whether the child
@@ -524,6 +1570,8 @@ impl Popen {
             self.stderr.take(),
             input_data,
         )
+        .stdout_prefix(mem::take(&mut self.stdout_buffer))
+        .process(self.liveness_token())
     }
 
     /// Feed the subprocess with input data and capture its output.
@@ -594,9 +1642,61 @@ impl Popen {
             .map_err(|e| e.error)
     }
 
-    /// Check whether the process is still running, without blocking or errors.
+    /// Write `data` to the subprocess's standard input from a background
+    /// thread.
     ///
-    /// This checks whether the process is still running and if it
+    /// The thread writes the whole of `data` and then closes the
+    /// standard input, so that the subprocess receives EOF.  This allows
+    /// the caller to read `self.stdout` directly, e.g. line by line,
+    /// while the input is being pushed, without the subprocess stalling
+    /// on a full input pipe.
+    ///
+    /// The returned handle can be joined to check whether writing
+    /// succeeded.
+    ///
+    /// # Panics
+    ///
+    /// If `stdin` was not redirected to a pipe.
+    pub fn feed_stdin_background(&mut self, data: Vec<u8>) -> JoinHandle<io::Result<()>> {
+        let mut stdin = self
+            .stdin
+            .take()
+            .expect("feed_stdin_background called without stdin pipe");
+        thread::spawn(move || stdin.write_all(&data))
+    }
+
+    /// Copy the contents of `source` to the subprocess's standard input
+    /// from a background thread.
+    ///
+    /// Like [`feed_stdin_background`], but the data is streamed from a
+    /// reader rather than provided in memory.  Data is copied through a
+    /// small fixed-size buffer, and the thread blocks while the child's
+    /// input pipe is full, so `source` is never read faster than the
+    /// subprocess consumes it.  Standard input is closed once `source`
+    /// reaches EOF.
+    ///
+    /// If the subprocess exits before reading all of its input, the
+    /// thread's result is an error of kind `ErrorKind::BrokenPipe`.
+    ///
+    /// # Panics
+    ///
+    /// If `stdin` was not redirected to a pipe.
+    ///
+    /// [`feed_stdin_background`]: struct.Popen.html#method.feed_stdin_background
+    pub fn feed_stdin_background_from(
+        &mut self,
+        mut source: impl Read + Send + 'static,
+    ) -> JoinHandle<io::Result<()>> {
+        let mut stdin = self
+            .stdin
+            .take()
+            .expect("feed_stdin_background_from called without stdin pipe");
+        thread::spawn(move || io::copy(&mut source, &mut stdin).map(|_| ()))
+    }
+
+    /// Check whether the process is still running, without blocking or errors.
+    ///
+    /// This checks whether the process is still running and if it
     /// is still running, `None` is returned, otherwise
     /// `Some(exit_status)`.  This method is guaranteed not to block
     /// and is exactly equivalent to
@@ -617,7 +1717,48 @@ impl Popen {
     /// Returns an `Err` if a system call fails in an unpredicted way.
     /// This should not happen in normal usage.
     pub fn wait(&mut self) -> Result<ExitStatus> {
-        self.os_wait()
+        let exit_status = self.os_wait()?;
+        self.join_helpers(None);
+        Ok(exit_status)
+    }
+
+    // Remove the temporary files once the child has been reaped, and
+    // join the helper threads, waiting for them until `deadline`, if
+    // any.  Returns false if some are still running, e.g. because a
+    // descendant of the child has inherited the stream they read.
+    fn join_helpers(&mut self, deadline: Option<Instant>) -> bool {
+        self.temp_files.clear();
+        while let Some((_, done)) = self.helper_threads.last() {
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if let Err(RecvTimeoutError::Timeout) = done.recv_timeout(remaining) {
+                    return false;
+                }
+            }
+            let (handle, _) = self.helper_threads.pop().unwrap();
+            handle.join().ok();
+        }
+        true
+    }
+
+    // Start a thread servicing the child's streams, to be joined when
+    // the child is reaped, so that its work is complete once the
+    // process is reported as finished.
+    pub(crate) fn spawn_helper_thread(&mut self, f: impl FnOnce() + Send + 'static) {
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            // disconnects the channel when the thread is done, even if
+            // by panicking
+            let _done = done_tx;
+            f()
+        });
+        self.helper_threads.push((handle, done_rx));
+    }
+
+    // Register a temporary file used by the child, to be removed when
+    // the child is reaped or on drop.
+    pub(crate) fn add_temp_file(&mut self, file: TempFile) {
+        self.temp_files.push(file);
     }
 
     /// Wait for the process to finish, timing out after the specified duration.
@@ -626,11 +1767,25 @@ impl Popen {
     /// will be blocked for roughly no longer than `dur`.  It returns
     /// `Ok(None)` if the timeout is known to have elapsed.
     ///
-    /// On Unix-like systems, timeout is implemented by calling
-    /// `waitpid(..., WNOHANG)` in a loop with adaptive sleep
-    /// intervals between iterations.
+    /// On Linux, the timeout is implemented by waiting on a pidfd of
+    /// the process, which returns as soon as the process exits.  On
+    /// other Unix-like systems, and on Linux kernels that don't support
+    /// pidfds, it is implemented by calling `waitpid(..., WNOHANG)` in
+    /// a loop with adaptive sleep intervals between iterations.
+    ///
+    /// If the process was started with threads servicing its streams,
+    /// such as the one set up by [`Exec::stderr_to_callback`], they are
+    /// also waited for until the timeout, and the process is reported
+    /// as finished only once they are done.
+    ///
+    /// [`Exec::stderr_to_callback`]: struct.Exec.html#method.stderr_to_callback
     pub fn wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>> {
-        self.os_wait_timeout(dur)
+        let deadline = Instant::now() + dur;
+        let exit_status = self.os_wait_timeout(dur)?;
+        if exit_status.is_some() && !self.join_helpers(Some(deadline)) {
+            return Ok(None);
+        }
+        Ok(exit_status)
     }
 
     /// Terminate the subprocess.
@@ -638,7 +1793,9 @@ impl Popen {
     /// On Unix-like systems, this sends the `SIGTERM` signal to the
     /// child process, which can be caught by the child in order to
     /// perform cleanup before exiting.  On Windows, it is equivalent
-    /// to `kill()`.
+    /// to [`kill`].
+    ///
+    /// [`kill`]: #method.kill
     pub fn terminate(&mut self) -> io::Result<()> {
         self.os_terminate()
     }
@@ -649,20 +1806,163 @@ impl Popen {
     /// child process, which cannot be caught.
     ///
     /// On Windows, it invokes [`TerminateProcess`] on the process
-    /// handle with equivalent semantics.
+    /// handle with equivalent semantics.  The process then reports
+    /// an exit status of `ExitStatus::Exited(u32::MAX)`, the same as
+    /// a process that called `exit(-1)`; [`PopenExt::terminated`]
+    /// tells the two apart.  If the process has already exited, this
+    /// does nothing and its actual exit status is retained.
     ///
     /// [`TerminateProcess`]: https://msdn.microsoft.com/en-us/library/windows/desktop/ms686714(v=vs.85).aspx
+    /// [`PopenExt::terminated`]: windows/trait.PopenExt.html#tymethod.terminated
     pub fn kill(&mut self) -> io::Result<()> {
         self.os_kill()
     }
 }
 
+/// Reads from the child's standard output.
+///
+/// This is a shorthand for reading from `self.stdout`, which allows
+/// passing the `Popen` to code that works with any `Read`.  Fails with
+/// `io::ErrorKind::BrokenPipe` if standard output was not redirected
+/// to a pipe or was taken from the `Popen`.
+impl Read for Popen {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.stdout_buffer.is_empty() {
+            let n = buf.len().min(self.stdout_buffer.len());
+            buf[..n].copy_from_slice(&self.stdout_buffer[..n]);
+            self.stdout_buffer.drain(..n);
+            return Ok(n);
+        }
+        match self.stdout {
+            Some(ref mut stdout) => stdout.read(buf),
+            None => Err(not_a_pipe("stdout")),
+        }
+    }
+}
+
+/// Writes to the child's standard input.
+///
+/// This is a shorthand for writing to `self.stdin`, which allows
+/// passing the `Popen` to code that works with any `Write`.  Fails with
+/// `io::ErrorKind::BrokenPipe` if standard input was not redirected to
+/// a pipe or was taken from the `Popen`.  To signal end-of-file to the
+/// child, close its standard input by setting `self.stdin` to `None`.
+impl Write for Popen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin {
+            Some(ref mut stdin) => stdin.write(buf),
+            None => Err(not_a_pipe("stdin")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin {
+            Some(ref mut stdin) => stdin.flush(),
+            None => Err(not_a_pipe("stdin")),
+        }
+    }
+}
+
+fn not_a_pipe(stream: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        format!("{} is not a pipe", stream),
+    )
+}
+
 trait PopenOs {
     fn os_start(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()>;
     fn os_wait(&mut self) -> Result<ExitStatus>;
     fn os_wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>>;
     fn os_terminate(&mut self) -> io::Result<()>;
     fn os_kill(&mut self) -> io::Result<()>;
+    fn os_request_exit(&mut self, signal: i32) -> io::Result<()>;
+    fn os_liveness_token(&self) -> os::LivenessToken;
+}
+
+/// A thread-safe handle for checking whether a subprocess is still
+/// running, obtained with [`Popen::liveness_token`].
+///
+/// On Unix-like systems, the token refers to the process by its PID,
+/// and checks it with `waitid()` without reaping it.  Once the `Popen`
+/// owner reaps the process, the token reports it as no longer alive.
+/// However, if the system later reuses the PID for another child of
+/// the current process, the token will report on that child instead.
+/// On Windows, the token shares the process handle, which prevents the
+/// PID from being reused while the token exists.
+///
+/// [`Popen::liveness_token`]: struct.Popen.html#method.liveness_token
+#[derive(Debug, Clone)]
+pub struct LivenessToken(os::LivenessToken);
+
+impl LivenessToken {
+    /// Return true if the subprocess is still running.
+    ///
+    /// This returns false if the process has exited, even if it hasn't
+    /// been reaped yet, and if its status could not be determined.
+    pub fn is_alive(&self) -> bool {
+        self.0.is_alive()
+    }
+
+    // Kill the subprocess if it is still running.
+    pub(crate) fn kill(&self) -> io::Result<()> {
+        self.0.kill()
+    }
+}
+
+/// Handle to the child's standard input, obtained with
+/// [`Popen::split_io`].
+///
+/// Dropping the handle closes the pipe, signaling end-of-file to the
+/// child.
+///
+/// [`Popen::split_io`]: struct.Popen.html#method.split_io
+#[derive(Debug)]
+pub struct ChildStdin(File);
+
+impl Write for ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Handle to the child's standard output, obtained with
+/// [`Popen::split_io`].
+///
+/// [`Popen::split_io`]: struct.Popen.html#method.split_io
+#[derive(Debug)]
+pub struct ChildStdout {
+    pending: Vec<u8>,
+    file: File,
+}
+
+impl Read for ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.file.read(buf);
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Handle to the child's standard error, obtained with
+/// [`Popen::split_io`].
+///
+/// [`Popen::split_io`]: struct.Popen.html#method.split_io
+#[derive(Debug)]
+pub struct ChildStderr(File);
+
+impl Read for ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
 }
 
 #[cfg(unix)]
@@ -674,7 +1974,9 @@ mod os {
     use std::ffi::OsString;
     use std::fs::File;
     use std::io::{self, Read, Write};
-    use std::os::unix::io::AsRawFd;
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::panic;
+    use std::process;
     use std::time::{Duration, Instant};
 
     use crate::os_common::ExitStatus;
@@ -684,69 +1986,7 @@ mod os {
 
     impl super::PopenOs for Popen {
         fn os_start(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()> {
-            let mut exec_fail_pipe = posix::pipe()?;
-            set_inheritable(&exec_fail_pipe.0, false)?;
-            set_inheritable(&exec_fail_pipe.1, false)?;
-            {
-                let child_ends = self.setup_streams(config.stdin, config.stdout, config.stderr)?;
-                let child_env = config.env.as_deref().map(format_env);
-                let cmd_to_exec = config.executable.as_ref().unwrap_or(&argv[0]);
-                let just_exec = posix::prep_exec(cmd_to_exec, &argv, child_env.as_deref())?;
-                unsafe {
-                    // unsafe because after the call to fork() the
-                    // child is not allowed to allocate
-                    match posix::fork()? {
-                        Some(child_pid) => {
-                            self.child_state = Running {
-                                pid: child_pid,
-                                ext: (),
-                            };
-                        }
-                        None => {
-                            drop(exec_fail_pipe.0);
-                            let result = Popen::do_exec(
-                                just_exec,
-                                child_ends,
-                                config.cwd.as_deref(),
-                                config.setuid,
-                                config.setgid,
-                            );
-                            // If we are here, it means that exec has failed.  Notify
-                            // the parent and exit.
-                            let error_code = match result {
-                                Ok(()) => unreachable!(),
-                                Err(e) => e.raw_os_error().unwrap_or(-1),
-                            } as u32;
-                            exec_fail_pipe
-                                .1
-                                .write_all(&[
-                                    error_code as u8,
-                                    (error_code >> 8) as u8,
-                                    (error_code >> 16) as u8,
-                                    (error_code >> 24) as u8,
-                                ])
-                                .ok();
-                            posix::_exit(127);
-                        }
-                    }
-                }
-            }
-            drop(exec_fail_pipe.1);
-            let mut error_buf = [0u8; 4];
-            let read_cnt = exec_fail_pipe.0.read(&mut error_buf)?;
-            if read_cnt == 0 {
-                Ok(())
-            } else if read_cnt == 4 {
-                let error_code: u32 = error_buf[0] as u32
-                    | (error_buf[1] as u32) << 8
-                    | (error_buf[2] as u32) << 16
-                    | (error_buf[3] as u32) << 24;
-                Err(PopenError::from(io::Error::from_raw_os_error(
-                    error_code as i32,
-                )))
-            } else {
-                Err(PopenError::LogicError("invalid read_count from exec pipe"))
-            }
+            self.start_child(ChildMain::<fn() -> i32>::Exec(argv), config)
         }
 
         fn os_wait(&mut self) -> Result<ExitStatus> {
@@ -764,8 +2004,19 @@ mod os {
             }
 
             let deadline = Instant::now() + dur;
-            // double delay at every iteration, maxing at 100ms
-            let mut delay = Duration::from_millis(1);
+
+            // On Linux, wait for the child's pidfd to become readable,
+            // which happens as soon as it exits.  If pidfds aren't
+            // supported, fall back to polling.
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(exit_status) = self.wait_pidfd(deadline)? {
+                    return Ok(exit_status);
+                }
+            }
+
+            // double delay at every iteration, maxing at max_poll_interval
+            let mut delay = min(Duration::from_millis(1), self.max_poll_interval);
 
             loop {
                 self.waitpid(false)?;
@@ -778,7 +2029,7 @@ mod os {
                 }
                 let remaining = deadline.duration_since(now);
                 ::std::thread::sleep(min(delay, remaining));
-                delay = min(delay * 2, Duration::from_millis(100));
+                delay = min(delay * 2, self.max_poll_interval);
             }
         }
 
@@ -789,6 +2040,37 @@ mod os {
         fn os_kill(&mut self) -> io::Result<()> {
             self.send_signal(posix::SIGKILL)
         }
+
+        fn os_request_exit(&mut self, signal: i32) -> io::Result<()> {
+            self.send_signal(signal)
+        }
+
+        fn os_liveness_token(&self) -> LivenessToken {
+            LivenessToken { pid: self.pid() }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct LivenessToken {
+        pid: Option<u32>,
+    }
+
+    impl LivenessToken {
+        pub fn is_alive(&self) -> bool {
+            match self.pid {
+                Some(pid) => matches!(posix::child_exited(pid), Ok(false)),
+                None => false,
+            }
+        }
+
+        pub fn kill(&self) -> io::Result<()> {
+            match self.pid {
+                // Until the child is reaped its PID cannot be reused, so
+                // only signal it while waitid() still reports it.
+                Some(pid) if posix::child_exited(pid).is_ok() => posix::kill(pid, posix::SIGKILL),
+                _ => Ok(()),
+            }
+        }
     }
 
     fn format_env(env: &[(OsString, OsString)]) -> Vec<OsString> {
@@ -810,30 +2092,452 @@ mod os {
         formatted
     }
 
+    // Sent by the child through the exec failure pipe along with the
+    // errno, telling the parent whether it failed while setting up the
+    // child or in exec itself.
+    const PHASE_SETUP: u8 = 0;
+    const PHASE_EXEC: u8 = 1;
+    // Sent along with the index of the candidate the child is about to
+    // try executing.
+    const PHASE_ATTEMPT: u8 = 2;
+
+    // The files the child may execute, for Popen::resolved_path, along
+    // with the directory that relative ones are resolved in.
+    #[derive(Debug)]
+    struct ExecTarget {
+        candidates: posix::ExecCandidates,
+        base: Option<PathBuf>,
+    }
+
+    impl ExecTarget {
+        fn resolve(&self, index: usize) -> Option<PathBuf> {
+            let path = self.candidates.get(index)?;
+            match self.base {
+                Some(ref base) if path.is_relative() => Some(base.join(path)),
+                _ => Some(path),
+            }
+        }
+    }
+
+    // A child waiting at the barrier requested by PopenConfig::exec_barrier,
+    // with what's needed to complete its creation once it's released.
+    #[derive(Debug)]
+    pub struct PendingExec {
+        barrier: File,
+        exec_fail: File,
+        program: OsString,
+        target: Option<ExecTarget>,
+        spawn_start: Instant,
+        spawn_time: Duration,
+    }
+
+    fn is_transient_spawn_error(error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error(),
+            Some(posix::EAGAIN) | Some(posix::EINTR)
+        )
+    }
+
+    fn exec_error_kind(errno: i32, program: &OsStr) -> ExecErrorKind {
+        match errno {
+            posix::ENOENT | posix::ENOTDIR => ExecErrorKind::NotFound,
+            posix::EISDIR => ExecErrorKind::IsDirectory,
+            // execve() reports EACCES for directories
+            posix::EACCES if is_directory_path(program) => ExecErrorKind::IsDirectory,
+            posix::EACCES | posix::EPERM => ExecErrorKind::PermissionDenied,
+            posix::ENOEXEC => ExecErrorKind::BadExecutable,
+            _ => ExecErrorKind::Other,
+        }
+    }
+
+    // What the child does once it's set up.
+    enum ChildMain<F> {
+        // Execute the program with the given argv.
+        Exec(Vec<OsString>),
+        // Run the closure and exit with its return value, see
+        // Popen::fork_run.
+        Run(F),
+    }
+
+    impl Popen {
+        /// Run a closure in a new process created with `fork()`, without
+        /// executing a program.
+        ///
+        /// The child process runs `f` and exits with its return value
+        /// (truncated to 8 bits by the OS), or with 101 if `f` panics.
+        /// The parent receives a `Popen` that can be used like one
+        /// created by [`create`], e.g. to wait for the child or to
+        /// terminate it.  This is useful for fork-based worker processes.
+        ///
+        /// The child is set up according to `config`, including
+        /// redirections, so that e.g. `stdout: Redirection::Pipe` makes
+        /// what `f` writes to the standard output available through
+        /// `self.stdout`.  Since no program is executed, specifying
+        /// `executable` or `env` is a `LogicError`.
+        ///
+        /// # Safety
+        ///
+        /// The child is a copy of the parent that contains only the
+        /// calling thread.  If the parent has other threads, locks they
+        /// held at the time of the fork (including the allocator's lock
+        /// and the lock of `std::io::stdout()`) remain locked in the
+        /// child forever, so `f` can deadlock if it allocates, prints,
+        /// panics, or otherwise uses such locks.  The caller must ensure
+        /// that either the parent is single-threaded, or `f` restricts
+        /// itself to async-signal-safe operations, such as raw system
+        /// calls on preallocated data, as with std's
+        /// [`CommandExt::pre_exec`].  The child also inherits copies of
+        /// all other resources of the parent, such as open files and
+        /// buffered but unflushed output.  When `f` returns, the child
+        /// exits with `_exit()`, so destructors of values owned by the
+        /// parent are not run in the child.
+        ///
+        /// [`create`]: struct.Popen.html#method.create
+        /// [`CommandExt::pre_exec`]: https://doc.rust-lang.org/std/os/unix/process/trait.CommandExt.html#tymethod.pre_exec
+        pub unsafe fn fork_run(f: impl FnOnce() -> i32, config: PopenConfig) -> Result<Popen> {
+            if config.executable.is_some() || config.env.is_some() {
+                return Err(PopenError::LogicError(
+                    "executable and env are not valid for fork_run",
+                ));
+            }
+            let mut inst = Popen::new(&config);
+            inst.start_child(ChildMain::Run(f), config)?;
+            Ok(inst)
+        }
+
+        // Fork the child and set it up according to `config`, then exec
+        // the program or run the closure given by `main`.
+        fn start_child<F: FnOnce() -> i32>(
+            &mut self,
+            main: ChildMain<F>,
+            mut config: PopenConfig,
+        ) -> Result<()> {
+            if config.cwd.is_some() && config.cwd_fd.is_some() {
+                return Err(PopenError::LogicError(
+                    "cwd and cwd_fd must not both be specified",
+                ));
+            }
+            // If the parent has standard descriptors closed, the pipes
+            // internal to the spawn could land on them, only to be
+            // overwritten by the child's redirections.
+            let above_std = |(r, w): (File, File)| -> Result<(File, File)> {
+                let above = |f: File| match f.as_raw_fd() {
+                    0..=2 => posix::dup_cloexec_from(&f, 3),
+                    _ => Ok(f),
+                };
+                Ok((above(r)?, above(w)?))
+            };
+            let mut exec_fail_pipe = above_std(posix::pipe_cloexec()?)?;
+            let mut barrier = if config.exec_barrier {
+                Some(above_std(posix::pipe_cloexec()?)?)
+            } else {
+                None
+            };
+            // The first standard stream connected to the pseudo-terminal,
+            // which the child uses to make it its controlling terminal.
+            let pty_fd = [&config.stdin, &config.stdout, &config.stderr]
+                .iter()
+                .position(|r| matches!(r, Redirection::Pty))
+                .map(|fd| fd as i32);
+            let mut program = OsString::new();
+            let mut target = None;
+            let spawn_start;
+            let spawn_time;
+            {
+                let child_ends = self.setup_streams(
+                    mem::replace(&mut config.stdin, Redirection::None),
+                    mem::replace(&mut config.stdout, Redirection::None),
+                    mem::replace(&mut config.stderr, Redirection::None),
+                )?;
+                let extra_ends = self.setup_extra_fds(mem::take(&mut config.extra_fds))?;
+                if let Some(max_fd) = extra_ends.iter().map(|&(fd, _)| fd).max() {
+                    // Don't let the child clobber the failure pipe when
+                    // setting up the extra descriptors.
+                    if exec_fail_pipe.1.as_raw_fd() <= max_fd {
+                        exec_fail_pipe.1 = posix::dup_cloexec_from(&exec_fail_pipe.1, max_fd + 1)?;
+                    }
+                    if let Some((ref mut barrier_read, _)) = barrier {
+                        if barrier_read.as_raw_fd() <= max_fd {
+                            *barrier_read = posix::dup_cloexec_from(barrier_read, max_fd + 1)?;
+                        }
+                    }
+                }
+                let (just_exec, run) = match main {
+                    ChildMain::Exec(argv) => {
+                        let child_env = config.env.as_deref().map(format_env);
+                        program = config.executable.clone().unwrap_or_else(|| argv[0].clone());
+                        let (just_exec, candidates) =
+                            posix::prep_exec(&program, &argv, child_env.as_deref())?;
+                        // relative paths are resolved in the working
+                        // directory of the child, which can't be determined
+                        // from cwd_fd
+                        let base = match config.cwd_fd {
+                            Some(_) => None,
+                            None => env::current_dir().ok().map(|mut dir| {
+                                if let Some(ref cwd) = config.cwd {
+                                    dir.push(cwd);
+                                }
+                                dir
+                            }),
+                        };
+                        target = Some(ExecTarget { candidates, base });
+                        (Some(just_exec), None)
+                    }
+                    ChildMain::Run(f) => (None, Some(f)),
+                };
+                unsafe {
+                    // unsafe because after the call to fork() the
+                    // child is not allowed to allocate
+                    let fork = || {
+                        #[cfg(target_os = "linux")]
+                        {
+                            if config.namespaces != 0 {
+                                return posix::fork_into_namespaces(config.namespaces);
+                            }
+                        }
+                        posix::fork()
+                    };
+                    spawn_start = Instant::now();
+                    match retry_spawn(config.spawn_retry, is_transient_spawn_error, fork)
+                        .map_err(PopenError::SpawnError)?
+                    {
+                        Some(child_pid) => {
+                            spawn_time = spawn_start.elapsed();
+                            self.child_state = Running {
+                                pid: child_pid,
+                                ext: (),
+                            };
+                        }
+                        None => {
+                            drop(exec_fail_pipe.0);
+                            let barrier = barrier.map(|(read_end, _)| read_end);
+                            let (error, phase) = match Popen::setup_child(
+                                child_ends,
+                                &extra_ends,
+                                pty_fd,
+                                &config,
+                            ) {
+                                Err(e) => (e, PHASE_SETUP),
+                                Ok(()) => {
+                                    if let Some(ref barrier) = barrier {
+                                        if !Popen::wait_for_release(barrier) {
+                                            // dropped without release
+                                            posix::_exit(127);
+                                        }
+                                    }
+                                    match (just_exec, run) {
+                                        (Some(just_exec), _) => {
+                                            let mut exec_fail = &exec_fail_pipe.1;
+                                            let mut attempt = |index: usize| {
+                                                let index = (index as u32).to_le_bytes();
+                                                let mut record = [PHASE_ATTEMPT; 5];
+                                                record[..4].copy_from_slice(&index);
+                                                exec_fail.write_all(&record).ok();
+                                            };
+                                            match just_exec(&mut attempt) {
+                                                Ok(()) => unreachable!(),
+                                                Err(e) => (e, PHASE_EXEC),
+                                            }
+                                        }
+                                        (None, Some(run)) => {
+                                            // Setup succeeded, let the
+                                            // parent return.
+                                            drop(exec_fail_pipe.1);
+                                            let status =
+                                                panic::catch_unwind(panic::AssertUnwindSafe(run))
+                                                    .unwrap_or(101);
+                                            posix::_exit(status as u8);
+                                        }
+                                        (None, None) => unreachable!(),
+                                    }
+                                }
+                            };
+                            // If we are here, it means that exec has failed.  Notify
+                            // the parent and exit.
+                            let error_code = error.raw_os_error().unwrap_or(-1).to_le_bytes();
+                            let mut error_buf = [phase; 5];
+                            error_buf[..4].copy_from_slice(&error_code);
+                            exec_fail_pipe.1.write_all(&error_buf).ok();
+                            posix::_exit(127);
+                        }
+                    }
+                }
+            }
+            drop(exec_fail_pipe.1);
+            if let Some((_, barrier_write)) = barrier {
+                self.pending_exec = Some(PendingExec {
+                    barrier: barrier_write,
+                    exec_fail: exec_fail_pipe.0,
+                    program,
+                    target,
+                    spawn_start,
+                    spawn_time,
+                });
+                return Ok(());
+            }
+            self.finish_exec(exec_fail_pipe.0, program, target, spawn_start, spawn_time)
+        }
+
+        // Block until the parent writes to the exec barrier, returning
+        // false if it closed the barrier instead.  Safe to call between
+        // fork() and exec().
+        fn wait_for_release(mut barrier: &File) -> bool {
+            let mut byte = [0u8];
+            loop {
+                match barrier.read(&mut byte) {
+                    Ok(n) => return n == 1,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return false,
+                }
+            }
+        }
+
+        // Wait for the child to exec, reporting setup and exec errors
+        // sent through the exec failure pipe, and recording the file
+        // executed.
+        fn finish_exec(
+            &mut self,
+            mut exec_fail: File,
+            program: OsString,
+            target: Option<ExecTarget>,
+            spawn_start: Instant,
+            spawn_time: Duration,
+        ) -> Result<()> {
+            let mut attempted = None;
+            loop {
+                // The records are written atomically, so they are read
+                // whole.
+                let mut record = [0u8; 5];
+                let read_cnt = exec_fail.read(&mut record)?;
+                if read_cnt == 0 {
+                    break;
+                } else if read_cnt != 5 {
+                    return Err(PopenError::LogicError("invalid read_count from exec pipe"));
+                }
+                let value = [record[0], record[1], record[2], record[3]];
+                if record[4] == PHASE_ATTEMPT {
+                    attempted = Some(u32::from_le_bytes(value) as usize);
+                    continue;
+                }
+                let error_code = i32::from_le_bytes(value);
+                let error = io::Error::from_raw_os_error(error_code);
+                if record[4] == PHASE_EXEC {
+                    return Err(PopenError::ExecError {
+                        kind: exec_error_kind(error_code, &program),
+                        program,
+                        error,
+                    });
+                } else {
+                    return Err(PopenError::from(error));
+                }
+            }
+            // the pipe was closed by a successful exec of the last file
+            // attempted
+            self.resolved_path = match (target, attempted) {
+                (Some(target), Some(index)) => target.resolve(index),
+                _ => None,
+            };
+            self.spawn_metrics = Some(SpawnMetrics {
+                spawn: spawn_time,
+                exec: spawn_start.elapsed(),
+            });
+            Ok(())
+        }
+    }
+
     trait PopenOsImpl: super::PopenOs {
-        fn do_exec(
-            just_exec: impl FnOnce() -> io::Result<()>,
+        fn setup_extra_fds(
+            &mut self,
+            extra_fds: Vec<(i32, Redirection)>,
+        ) -> Result<Vec<(i32, File)>>;
+        fn setup_child(
             child_ends: (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>),
-            cwd: Option<&OsStr>,
-            setuid: Option<u32>,
-            setgid: Option<u32>,
+            extra_ends: &[(i32, File)],
+            pty_fd: Option<i32>,
+            config: &PopenConfig,
         ) -> io::Result<()>;
         fn waitpid(&mut self, block: bool) -> io::Result<()>;
+        #[cfg(target_os = "linux")]
+        fn wait_pidfd(&mut self, deadline: Instant) -> io::Result<Option<Option<ExitStatus>>>;
     }
 
     impl PopenOsImpl for Popen {
-        fn do_exec(
-            just_exec: impl FnOnce() -> io::Result<()>,
+        fn setup_extra_fds(
+            &mut self,
+            extra_fds: Vec<(i32, Redirection)>,
+        ) -> Result<Vec<(i32, File)>> {
+            // The child ends are duplicated above the highest requested
+            // descriptor, so that the child can dup2() them into place
+            // without one target clobbering the source of another.
+            let min_fd = extra_fds.iter().map(|&(fd, _)| fd).max().unwrap_or(0) + 1;
+            let mut child_ends = vec![];
+            for (fd, redirection) in extra_fds {
+                if fd < 3 {
+                    return Err(PopenError::LogicError(
+                        "extra_fds must not include standard streams",
+                    ));
+                }
+                let child_end = match redirection {
+                    Redirection::None => continue,
+                    Redirection::Pipe => {
+                        let (parent_end, child_end) = posix::socketpair_cloexec()?;
+                        self.extra_fds.push((fd, parent_end));
+                        child_end
+                    }
+                    Redirection::File(file) => file,
+                    Redirection::RcFile(file) => posix::dup_cloexec_from(&file, min_fd)?,
+                    Redirection::RawFd(fd) => dup_raw(fd)?,
+                    Redirection::Merge => {
+                        return Err(PopenError::LogicError(
+                            "Redirection::Merge not valid for extra_fds",
+                        ));
+                    }
+                    Redirection::Pty => {
+                        return Err(PopenError::LogicError(
+                            "Redirection::Pty not valid for extra_fds",
+                        ));
+                    }
+                };
+                let child_end = if child_end.as_raw_fd() < min_fd {
+                    posix::dup_cloexec_from(&child_end, min_fd)?
+                } else {
+                    child_end
+                };
+                child_ends.push((fd, child_end));
+            }
+            Ok(child_ends)
+        }
+
+        fn setup_child(
             child_ends: (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>),
-            cwd: Option<&OsStr>,
-            setuid: Option<u32>,
-            setgid: Option<u32>,
+            extra_ends: &[(i32, File)],
+            pty_fd: Option<i32>,
+            config: &PopenConfig,
         ) -> io::Result<()> {
-            if let Some(cwd) = cwd {
+            if pty_fd.is_some() {
+                // also makes the child the leader of a new process group
+                posix::setsid()?;
+            } else if config.setpgid {
+                posix::setpgid(0, 0)?;
+            }
+            if let Some(ref cwd) = config.cwd {
                 env::set_current_dir(cwd)?;
             }
+            if let Some(fd) = config.cwd_fd {
+                posix::fchdir(fd)?;
+            }
+
+            // Done before dup2(), which clears the flag on the descriptors
+            // set up for the child.
+            if config.close_fds {
+                posix::set_cloexec_from(3)?;
+            }
+            for &fd in &config.close_fds_list {
+                posix::set_cloexec_if_open(fd)?;
+            }
 
             let (stdin, stdout, stderr) = child_ends;
+            let redirected = [stdin.is_some(), stdout.is_some(), stderr.is_some()];
             if let Some(stdin) = stdin {
                 if stdin.as_raw_fd() != 0 {
                     posix::dup2(stdin.as_raw_fd(), 0)?;
@@ -849,16 +2553,50 @@ mod os {
                     posix::dup2(stderr.as_raw_fd(), 2)?;
                 }
             }
+            for (fd, file) in extra_ends {
+                posix::dup2(file.as_raw_fd(), *fd)?;
+            }
+            if let Some(fd) = pty_fd {
+                posix::set_controlling_terminal(fd)?;
+            }
+            if config.null_closed_std {
+                // Descriptors the parent doesn't have open may have been
+                // taken by our own close-on-exec pipes, so this checks
+                // whether they'd survive exec, not just whether they're
+                // open.  It must come after the dup2() calls above, which
+                // may still need to read from those pipes.
+                for (fd, &is_redirected) in redirected.iter().enumerate() {
+                    if !is_redirected {
+                        posix::reopen_null_if_closed(fd as i32)?;
+                    }
+                }
+            }
             posix::reset_sigpipe()?;
+            if let Some(ref signals) = config.block_signals {
+                posix::block_signals(signals)?;
+            }
 
-            if let Some(uid) = setuid {
+            #[cfg(target_os = "linux")]
+            {
+                // before setuid(), which might drop the privilege to
+                // lower the score
+                if let Some(score) = config.oom_score_adj {
+                    posix::set_oom_score_adj(score)?;
+                }
+            }
+            if let Some(uid) = config.setuid {
                 posix::setuid(uid)?;
             }
-            if let Some(gid) = setgid {
+            if let Some(gid) = config.setgid {
                 posix::setgid(gid)?;
             }
-            just_exec()?;
-            unreachable!();
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(ref cpus) = config.cpu_affinity {
+                    posix::sched_setaffinity(cpus)?;
+                }
+            }
+            Ok(())
         }
 
         fn waitpid(&mut self, block: bool) -> io::Result<()> {
@@ -879,9 +2617,10 @@ mod os {
                             }
                             return Err(e);
                         }
-                        Ok((pid_out, exit_status)) => {
+                        Ok((pid_out, exit_status, raw_status)) => {
                             if pid_out == pid {
                                 self.child_state = Finished(exit_status);
+                                self.raw_wait_status = Some(raw_status);
                             }
                         }
                     }
@@ -890,6 +2629,30 @@ mod os {
             }
             Ok(())
         }
+
+        // Wait until the child exits or the deadline passes, returning
+        // the outcome of the wait, or None if pidfds are unavailable.
+        #[cfg(target_os = "linux")]
+        fn wait_pidfd(&mut self, deadline: Instant) -> io::Result<Option<Option<ExitStatus>>> {
+            let pidfd = match self.child_state {
+                Running { pid, .. } => match posix::pidfd_open(pid) {
+                    Ok(pidfd) => pidfd,
+                    Err(_) => return Ok(None),
+                },
+                _ => return Ok(None),
+            };
+            let mut fds = [posix::PollFd::new(Some(&pidfd), posix::POLLIN)];
+            loop {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match posix::poll(&mut fds, Some(timeout)) {
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                    Ok(_) => break,
+                }
+            }
+            self.waitpid(false)?;
+            Ok(Some(self.exit_status()))
+        }
     }
 
     pub fn set_inheritable(f: &File, inheritable: bool) -> io::Result<()> {
@@ -912,11 +2675,112 @@ mod os {
         posix::pipe()
     }
 
+    // Create a pipe whose read end is kept by the parent.  Any pipe can
+    // be read with a timeout on Unix, so this is just a pipe.
+    pub fn make_parent_read_pipe() -> io::Result<(File, File)> {
+        posix::pipe()
+    }
+
+    pub fn resolve_program(program: &OsStr) -> Option<PathBuf> {
+        posix::resolve_exec(program)
+    }
+
+    // Take over a child spawned by std::process, returning its PID and
+    // the OS-specific part of the child state.
+    pub fn adopt_std_child(child: process::Child) -> (u32, ExtChildState) {
+        (child.id(), ())
+    }
+
+    pub fn std_stream_into_file(stream: impl IntoRawFd) -> File {
+        unsafe { File::from_raw_fd(stream.into_raw_fd()) }
+    }
+
+    pub fn is_terminal(which: StandardStream) -> bool {
+        posix::isatty(which)
+    }
+
+    pub fn format_response_file(args: &[OsString]) -> Vec<u8> {
+        use std::os::unix::ffi::OsStrExt;
+        // One argument per line, quoted as understood by GCC's
+        // buildargv(): backslash escapes whitespace and quotes.
+        let mut contents = vec![];
+        for arg in args {
+            if arg.is_empty() {
+                contents.extend_from_slice(b"''");
+            }
+            for &b in arg.as_bytes() {
+                if matches!(
+                    b,
+                    b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r' | b'\'' | b'"' | b'\\'
+                ) {
+                    contents.push(b'\\');
+                }
+                contents.push(b);
+            }
+            contents.push(b'\n');
+        }
+        contents
+    }
+
+    pub fn read_timeout(f: &File, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        posix::read_timeout(f, buf, timeout)
+    }
+
+    pub fn bytes_available(f: &File) -> io::Result<usize> {
+        posix::bytes_available(f)
+    }
+
     pub mod ext {
+        use crate::os_common::ExitStatus;
         use crate::popen::ChildState::*;
-        use crate::popen::Popen;
+        use crate::popen::{Popen, PopenError, Result};
         use crate::posix;
-        use std::io;
+        use std::fs::File;
+        use std::io::{self, Write};
+        use std::time::Duration;
+
+        /// Resource usage of a reaped child process.
+        ///
+        /// Obtained from [`PopenExt::wait_with_usage`], which collects it
+        /// with `wait4()` in the same system call that reaps the child.
+        ///
+        /// [`PopenExt::wait_with_usage`]: trait.PopenExt.html#tymethod.wait_with_usage
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct ResourceUsage {
+            /// CPU time spent executing in user mode.
+            pub user_time: Duration,
+            /// CPU time spent executing in kernel mode.
+            pub system_time: Duration,
+            /// Maximum resident set size, in kilobytes on Linux and in
+            /// bytes on macOS.
+            pub max_rss: u64,
+            /// Number of page faults serviced without any I/O activity.
+            pub minor_faults: u64,
+            /// Number of page faults that required I/O activity.
+            pub major_faults: u64,
+            /// Number of times the process gave up the CPU voluntarily,
+            /// typically while waiting for a resource.
+            pub voluntary_context_switches: u64,
+            /// Number of times the process was preempted by the scheduler.
+            pub involuntary_context_switches: u64,
+        }
+
+        impl ResourceUsage {
+            fn from_rusage(ru: &libc::rusage) -> ResourceUsage {
+                fn duration(tv: &libc::timeval) -> Duration {
+                    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+                }
+                ResourceUsage {
+                    user_time: duration(&ru.ru_utime),
+                    system_time: duration(&ru.ru_stime),
+                    max_rss: ru.ru_maxrss as u64,
+                    minor_faults: ru.ru_minflt as u64,
+                    major_faults: ru.ru_majflt as u64,
+                    voluntary_context_switches: ru.ru_nvcsw as u64,
+                    involuntary_context_switches: ru.ru_nivcsw as u64,
+                }
+            }
+        }
 
         /// Unix-specific extension methods for `Popen`
         pub trait PopenExt {
@@ -929,11 +2793,252 @@ mod os {
             /// a previous call to [`wait`] or [`poll`]), this will do
             /// nothing and return `Ok`.
             ///
+            /// The child is signaled by its PID, which remains reserved
+            /// until the child is waited for.  However, a child that has
+            /// finished without being waited for by this `Popen` (for
+            /// example, one reaped by a `SIGCHLD` handler or by another
+            /// call to `waitpid()` in the same process) no longer owns
+            /// its PID, and the signal may be delivered to an unrelated
+            /// process that has reused it.  This race is inherent to
+            /// PID-based signaling.
+            ///
             /// [`poll`]: ../struct.Popen.html#method.poll
             /// [`wait`]: ../struct.Popen.html#method.wait
             /// [`libc`]: https://docs.rs/libc/
             fn send_signal(&self, signal: i32) -> io::Result<()>;
+
+            /// Wait for the child process to finish, returning its exit
+            /// status along with its resource usage.
+            ///
+            /// The child is reaped with `wait4()`, which collects the CPU
+            /// time, memory and context switch statistics atomically with
+            /// the exit status, so even very short-lived children are
+            /// measured accurately.
+            ///
+            /// The resource usage is only available to the call that reaps
+            /// the child.  If the child is known to have finished (due to
+            /// e.g. a previous call to [`wait`] or [`poll`]), this returns
+            /// `PopenError::LogicError`.
+            ///
+            /// [`poll`]: ../struct.Popen.html#method.poll
+            /// [`wait`]: ../struct.Popen.html#method.wait
+            fn wait_with_usage(&mut self) -> Result<(ExitStatus, ResourceUsage)>;
+
+            /// Suspend the child process by sending it `SIGSTOP`.
+            ///
+            /// A stopped process is not considered finished, so [`poll`]
+            /// and [`wait_timeout`] keep reporting it as running until it
+            /// is resumed and exits.  Like with [`send_signal`], this does
+            /// nothing if the child process is known to have finished.
+            ///
+            /// This is only available on Unix; Windows has no public API
+            /// for suspending a whole process.
+            ///
+            /// [`poll`]: ../struct.Popen.html#method.poll
+            /// [`wait_timeout`]: ../struct.Popen.html#method.wait_timeout
+            /// [`send_signal`]: #tymethod.send_signal
+            fn suspend(&self) -> io::Result<()>;
+
+            /// Resume a child process suspended by [`suspend`] by sending
+            /// it `SIGCONT`.
+            ///
+            /// [`suspend`]: #tymethod.suspend
+            fn resume(&self) -> io::Result<()>;
+
+            /// Returns the ID of the process group of the child process.
+            ///
+            /// Unless [`PopenConfig::setpgid`] is false, children are
+            /// started in a process group of their own, whose ID is equal
+            /// to the child's PID, but the child may have moved to another
+            /// group since.  If the child process is known to have
+            /// finished, this returns an error with `ESRCH`.
+            ///
+            /// [`PopenConfig::setpgid`]: ../struct.PopenConfig.html#structfield.setpgid
+            fn pgid(&self) -> io::Result<u32>;
+
+            /// Move the child process into the process group `pgid`.
+            ///
+            /// This calls `setpgid()` from the parent, e.g. to let a
+            /// job-control shell gather several children into a single
+            /// job.  The group must belong to the same session as the
+            /// child; passing the child's PID makes it the leader of a
+            /// new group.
+            ///
+            /// POSIX doesn't allow changing the process group of a child
+            /// once it has executed a new program, which is always the
+            /// case for children started by [`Popen::create`], as it
+            /// returns only after a successful exec.  For such children
+            /// this fails with `EACCES`, and the process group should
+            /// instead be chosen before the exec, with
+            /// [`PopenConfig::setpgid`].  This method is meant for
+            /// adjusting children that don't exec, such as those started
+            /// with [`Popen::fork_run`].  If the child process is known
+            /// to have finished, this returns an error with `ESRCH`.
+            ///
+            /// [`Popen::create`]: ../struct.Popen.html#method.create
+            /// [`Popen::fork_run`]: ../struct.Popen.html#method.fork_run
+            /// [`PopenConfig::setpgid`]: ../struct.PopenConfig.html#structfield.setpgid
+            fn set_pgid(&self, pgid: u32) -> io::Result<()>;
+
+            /// Send the specified signal to the process group led by the
+            /// child process.
+            ///
+            /// This signals every process in the group whose ID is the
+            /// child's PID, which includes the descendants of the child
+            /// that haven't moved to another group.  Such a group exists
+            /// only if the child was started with
+            /// [`PopenConfig::setpgid`], the default; otherwise this fails
+            /// with `ESRCH`.
+            ///
+            /// If the child process is known to have finished, this does
+            /// nothing and returns `Ok`, even if other members of the
+            /// group are still running, because the group ID may have
+            /// been reused since.
+            ///
+            /// [`PopenConfig::setpgid`]: ../struct.PopenConfig.html#structfield.setpgid
+            fn send_signal_group(&self, signal: i32) -> io::Result<()>;
+
+            /// Returns the parent's end of the socket connected to the
+            /// extra descriptor `fd` of the child process.
+            ///
+            /// This is `None` unless `fd` was specified in
+            /// [`PopenConfig::extra_fds`] with `Redirection::Pipe`.
+            ///
+            /// [`PopenConfig::extra_fds`]: ../struct.PopenConfig.html#structfield.extra_fds
+            fn extra_fd(&mut self, fd: i32) -> Option<&mut File>;
+
+            /// Receive a file descriptor sent by the child process over
+            /// its extra descriptor `fd`.
+            ///
+            /// `fd` must have been specified in
+            /// [`PopenConfig::extra_fds`] with `Redirection::Pipe`, which
+            /// connects it to a Unix domain socket.  The child sends the
+            /// descriptor with `sendmsg()` as `SCM_RIGHTS` ancillary data,
+            /// which must be attached to at least one byte of regular data.
+            /// Each call consumes exactly one byte of regular data along
+            /// with the descriptor attached to it, so any further data sent
+            /// by the child remains available for reading through
+            /// [`extra_fd`] or for another `recv_fd()`.  If the child sends
+            /// several descriptors in one message, only the first one is
+            /// received and the kernel closes the rest.
+            ///
+            /// The received descriptor is close-on-exec, so it doesn't leak
+            /// into other subprocesses.  Where supported, it's received
+            /// with `MSG_CMSG_CLOEXEC`, which sets the flag atomically.
+            ///
+            /// Returns `Ok(None)` if the child closed its end of the socket.
+            /// If the byte read doesn't carry a descriptor, an error of
+            /// kind `io::ErrorKind::InvalidData` is returned.
+            ///
+            /// [`PopenConfig::extra_fds`]: ../struct.PopenConfig.html#structfield.extra_fds
+            /// [`extra_fd`]: #tymethod.extra_fd
+            fn recv_fd(&mut self, fd: i32) -> Result<Option<File>>;
+
+            /// Wait for the child process to finish, and then for all of
+            /// the current process's remaining children, returning the
+            /// exit status of the child.
+            ///
+            /// This is meant for supervisors that must not proceed until
+            /// a whole tree of processes is gone, even if the child
+            /// started background processes (such as a daemon that
+            /// double-forks) and exited without waiting for them.  Such
+            /// orphaned descendants are normally adopted by `init`, and
+            /// the parent has no way to wait for them.  After calling
+            /// [`set_child_subreaper`], they are adopted by the current
+            /// process instead, and `wait_tree` reaps them until no
+            /// children remain.
+            ///
+            /// Orphaned descendants can't be told apart from other
+            /// children of the current process, so `wait_tree` reaps
+            /// those as well, and waits for them to finish.  It should
+            /// only be used in a process with no other children, such as
+            /// a dedicated supervisor process.  Otherwise, other `Popen`
+            /// instances will find their child reaped and report its
+            /// exit status as `ExitStatus::Undetermined`.
+            ///
+            /// Alternatives that don't require a dedicated process are to
+            /// start the child in its own process group and wait until
+            /// `kill(-pgid, 0)` reports the group as gone, which misses
+            /// descendants that move to another process group or session,
+            /// or, on Windows, to assign the child to a job object.
+            ///
+            /// This is only available on Linux.
+            ///
+            /// [`set_child_subreaper`]: fn.set_child_subreaper.html
+            #[cfg(target_os = "linux")]
+            fn wait_tree(&mut self) -> Result<ExitStatus>;
+
+            /// Returns the raw status reported by `waitpid()` when the
+            /// child process was reaped.
+            ///
+            /// [`ExitStatus`] decodes the status into an exit code or a
+            /// terminating signal, dropping the remaining bits, such as
+            /// the core dump flag.  This gives access to the undecoded
+            /// value, which can be examined with the `WIF*` and related
+            /// macros from `libc`, e.g. `libc::WCOREDUMP`.
+            ///
+            /// This is `None` until the child has been reaped by this
+            /// `Popen`, such as by [`wait`], [`poll`] or
+            /// [`wait_with_usage`], and also if the exit status was lost
+            /// because the child was reaped by someone else.
+            ///
+            /// [`ExitStatus`]: ../enum.ExitStatus.html
+            /// [`poll`]: ../struct.Popen.html#method.poll
+            /// [`wait`]: ../struct.Popen.html#method.wait
+            /// [`wait_with_usage`]: #tymethod.wait_with_usage
+            fn raw_wait_status(&self) -> Option<i32>;
+
+            /// Let a child stopped at the exec barrier execute the
+            /// program.
+            ///
+            /// The child must have been created with
+            /// [`PopenConfig::exec_barrier`] set, and not released yet;
+            /// otherwise this returns `PopenError::LogicError`.  Like
+            /// `Popen::create` without the barrier, this returns once the
+            /// program has been executed, or with the error that
+            /// prevented setting up the child or executing the program.
+            /// To release a number of children as simultaneously as
+            /// possible, they should be released in a tight loop, which
+            /// may report errors afterwards.
+            ///
+            /// [`PopenConfig::exec_barrier`]: ../struct.PopenConfig.html#structfield.exec_barrier
+            fn release(&mut self) -> Result<()>;
+
+            /// Set the size of the child's pseudo-terminal to `rows` and
+            /// `cols`.
+            ///
+            /// This calls `ioctl(TIOCSWINSZ)` on the terminal, which makes
+            /// the kernel send `SIGWINCH` to the terminal's foreground
+            /// process group if the size has changed, so that full-screen
+            /// programs can redraw themselves to fit.  The new size is
+            /// seen by programs started afterwards, which may query it
+            /// with `ioctl(TIOCGWINSZ)`, e.g. by running `stty size`.
+            ///
+            /// The child must have been created with at least one of its
+            /// standard streams specified as [`Redirection::Pty`];
+            /// otherwise this returns `PopenError::LogicError`.
+            ///
+            /// [`Redirection::Pty`]: ../enum.Redirection.html#variant.Pty
+            fn set_pty_size(&mut self, rows: u16, cols: u16) -> Result<()>;
         }
+
+        /// Make the current process the subreaper of its descendants.
+        ///
+        /// This calls `prctl(PR_SET_CHILD_SUBREAPER)`, after which
+        /// orphaned descendants of the current process are adopted by it
+        /// rather than by `init`, so that they can be waited for with
+        /// [`PopenExt::wait_tree`].  The setting applies to the whole
+        /// process, not just to a particular `Popen`, and remains in
+        /// effect until it is disabled by passing `false`.
+        ///
+        /// This is only available on Linux.
+        ///
+        /// [`PopenExt::wait_tree`]: trait.PopenExt.html#tymethod.wait_tree
+        #[cfg(target_os = "linux")]
+        pub fn set_child_subreaper(enable: bool) -> io::Result<()> {
+            posix::set_child_subreaper(enable)
+        }
+
         impl PopenExt for Popen {
             fn send_signal(&self, signal: i32) -> io::Result<()> {
                 match self.child_state {
@@ -942,6 +3047,121 @@ mod os {
                     Finished(..) => Ok(()),
                 }
             }
+
+            fn wait_with_usage(&mut self) -> Result<(ExitStatus, ResourceUsage)> {
+                match self.child_state {
+                    Preparing => panic!("child_state == Preparing"),
+                    Running { pid, .. } => {
+                        match posix::wait4(pid, 0) {
+                            Err(e) => {
+                                if e.raw_os_error() == Some(posix::ECHILD) {
+                                    // Someone else has reaped the child, its
+                                    // exit status is lost.
+                                    self.child_state = Finished(ExitStatus::Undetermined);
+                                }
+                                Err(e.into())
+                            }
+                            Ok((_, exit_status, raw_status, rusage)) => {
+                                self.child_state = Finished(exit_status);
+                                self.raw_wait_status = Some(raw_status);
+                                self.join_helpers(None);
+                                Ok((exit_status, ResourceUsage::from_rusage(&rusage)))
+                            }
+                        }
+                    }
+                    Finished(..) => Err(PopenError::LogicError(
+                        "child already reaped, resource usage unavailable",
+                    )),
+                }
+            }
+
+            fn suspend(&self) -> io::Result<()> {
+                self.send_signal(posix::SIGSTOP)
+            }
+
+            fn resume(&self) -> io::Result<()> {
+                self.send_signal(posix::SIGCONT)
+            }
+
+            fn pgid(&self) -> io::Result<u32> {
+                match self.child_state {
+                    Preparing => panic!("child_state == Preparing"),
+                    Running { pid, .. } => posix::getpgid(pid),
+                    Finished(..) => Err(io::Error::from_raw_os_error(posix::ESRCH)),
+                }
+            }
+
+            fn set_pgid(&self, pgid: u32) -> io::Result<()> {
+                match self.child_state {
+                    Preparing => panic!("child_state == Preparing"),
+                    Running { pid, .. } => posix::setpgid(pid, pgid),
+                    Finished(..) => Err(io::Error::from_raw_os_error(posix::ESRCH)),
+                }
+            }
+
+            fn send_signal_group(&self, signal: i32) -> io::Result<()> {
+                match self.child_state {
+                    Preparing => panic!("child_state == Preparing"),
+                    Running { pid, .. } => posix::killpg(pid, signal),
+                    Finished(..) => Ok(()),
+                }
+            }
+
+            fn extra_fd(&mut self, fd: i32) -> Option<&mut File> {
+                self.extra_fds
+                    .iter_mut()
+                    .find(|(n, _)| *n == fd)
+                    .map(|(_, file)| file)
+            }
+
+            fn recv_fd(&mut self, fd: i32) -> Result<Option<File>> {
+                match self.extra_fd(fd) {
+                    Some(sock) => Ok(posix::recv_fd(sock)?),
+                    None => Err(PopenError::LogicError(
+                        "recv_fd requires a Redirection::Pipe extra fd",
+                    )),
+                }
+            }
+
+            #[cfg(target_os = "linux")]
+            fn wait_tree(&mut self) -> Result<ExitStatus> {
+                let exit_status = self.wait()?;
+                while posix::wait_any()?.is_some() {}
+                Ok(exit_status)
+            }
+
+            fn raw_wait_status(&self) -> Option<i32> {
+                self.raw_wait_status
+            }
+
+            fn release(&mut self) -> Result<()> {
+                let pending = match self.pending_exec.take() {
+                    Some(pending) => pending,
+                    None => {
+                        return Err(PopenError::LogicError(
+                            "child not waiting at the exec barrier",
+                        ))
+                    }
+                };
+                // This fails if the child has already exited due to a
+                // setup error, which is then read from exec_fail.
+                let _ = (&pending.barrier).write_all(&[0]);
+                drop(pending.barrier);
+                self.finish_exec(
+                    pending.exec_fail,
+                    pending.program,
+                    pending.target,
+                    pending.spawn_start,
+                    pending.spawn_time,
+                )
+            }
+
+            fn set_pty_size(&mut self, rows: u16, cols: u16) -> Result<()> {
+                match self.pty {
+                    Some(ref pty) => Ok(posix::set_window_size(pty, rows, cols)?),
+                    None => Err(PopenError::LogicError("child not connected to a pty")),
+                }
+            }
         }
     }
 }
@@ -950,20 +3170,35 @@ mod os {
 mod os {
     use super::*;
 
-    use std::collections::HashSet;
+    use std::cmp::Ordering;
     use std::env;
     use std::ffi::{OsStr, OsString};
     use std::fs::{self, File};
     use std::io;
     use std::os::windows::ffi::{OsStrExt, OsStringExt};
-    use std::os::windows::io::{AsRawHandle, RawHandle};
-    use std::time::Duration;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle, RawHandle};
+    use std::process;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     use crate::os_common::{ExitStatus, StandardStream};
     use crate::win32;
 
     #[derive(Debug)]
-    pub struct ExtChildState(win32::Handle);
+    pub struct ExtChildState(Arc<win32::Handle>);
+
+    // A child created suspended, by PopenConfig::exec_barrier or by
+    // CREATE_SUSPENDED in creation_flags, with its main thread to resume
+    // once it's released.
+    #[derive(Debug)]
+    pub struct PendingExec {
+        thread: win32::Handle,
+    }
+
+    // Exit code of a process terminated by terminate() or kill(), unlikely
+    // to be used by normal exits.  It is the same as that of exit(-1),
+    // though, so PopenExt::terminated() is what tells them apart.
+    const TERMINATED_EXIT_CODE: u32 = u32::MAX;
 
     impl super::PopenOs for Popen {
         fn os_start(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()> {
@@ -975,27 +3210,62 @@ mod os {
             ensure_child_stream(&mut child_stdin, StandardStream::Input)?;
             ensure_child_stream(&mut child_stdout, StandardStream::Output)?;
             ensure_child_stream(&mut child_stderr, StandardStream::Error)?;
+            let program = config.executable.clone().unwrap_or_else(|| argv[0].clone());
             let cmdline = assemble_cmdline(argv)?;
             let env_block = config.env.map(|env| format_env_block(&env));
+            let mut creation_flags = config.console_mode.creation_flags() | config.creation_flags;
+            if config.exec_barrier {
+                creation_flags |= win32::CREATE_SUSPENDED;
+            }
             // CreateProcess doesn't search for appname in the PATH.
             // We do it ourselves to match the Unix behavior.
             let executable = config.executable.map(locate_in_path);
-            let (handle, pid) = win32::CreateProcess(
-                executable.as_ref().map(OsString::as_ref),
-                &cmdline,
-                &env_block,
-                &config.cwd.as_deref(),
-                true,
-                0,
-                raw(&child_stdin),
-                raw(&child_stdout),
-                raw(&child_stderr),
-                win32::STARTF_USESTDHANDLES,
-            )?;
+            let create = || {
+                win32::CreateProcess(
+                    executable.as_ref().map(OsString::as_ref),
+                    &cmdline,
+                    &env_block,
+                    &config.cwd.as_deref(),
+                    true,
+                    creation_flags,
+                    raw(&child_stdin),
+                    raw(&child_stdout),
+                    raw(&child_stderr),
+                    win32::STARTF_USESTDHANDLES,
+                )
+            };
+            let spawn_start = Instant::now();
+            let (handle, thread, pid) =
+                retry_spawn(config.spawn_retry, is_transient_spawn_error, create).map_err(
+                    |error| {
+                        if is_transient_spawn_error(&error) {
+                            PopenError::SpawnError(error)
+                        } else {
+                            PopenError::ExecError {
+                                kind: exec_error_kind(&error, &program),
+                                program,
+                                error,
+                            }
+                        }
+                    },
+                )?;
+            // CreateProcess both creates the process and loads the program.
+            let spawn_time = spawn_start.elapsed();
+            self.spawn_metrics = Some(SpawnMetrics {
+                spawn: spawn_time,
+                exec: spawn_time,
+            });
+            self.resolved_path = win32::QueryFullProcessImageName(&handle)
+                .ok()
+                .map(PathBuf::from);
             self.child_state = Running {
                 pid: pid as u32,
-                ext: ExtChildState(handle),
+                ext: ExtChildState(Arc::new(handle)),
             };
+            if creation_flags & win32::CREATE_SUSPENDED != 0 {
+                self.pending_exec = Some(PendingExec { thread });
+            }
+            self.new_process_group = config.creation_flags & win32::CREATE_NEW_PROCESS_GROUP != 0;
             Ok(())
         }
 
@@ -1028,7 +3298,7 @@ mod os {
                 ..
             } = self.child_state
             {
-                match win32::TerminateProcess(handle, 1) {
+                match win32::TerminateProcess(handle, TERMINATED_EXIT_CODE) {
                     Err(err) => {
                         if err.raw_os_error() != Some(win32::ERROR_ACCESS_DENIED as i32) {
                             return Err(err);
@@ -1039,7 +3309,7 @@ mod os {
                         }
                         new_child_state = Some(Finished(ExitStatus::Exited(rc)));
                     }
-                    Ok(_) => (),
+                    Ok(_) => self.terminate_requested = true,
                 }
             }
             if let Some(new_child_state) = new_child_state {
@@ -1051,30 +3321,91 @@ mod os {
         fn os_kill(&mut self) -> io::Result<()> {
             self.terminate()
         }
+
+        fn os_request_exit(&mut self, _signal: i32) -> io::Result<()> {
+            // Only a process group can be sent a console control event;
+            // sending one to any other PID affects unrelated processes.
+            match self.child_state {
+                Running { pid, .. } if self.new_process_group => {
+                    win32::GenerateConsoleCtrlEvent(win32::CTRL_BREAK_EVENT, pid)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn os_liveness_token(&self) -> LivenessToken {
+            match self.child_state {
+                Running {
+                    ext: ExtChildState(ref handle),
+                    ..
+                } => LivenessToken(Some(Arc::clone(handle))),
+                _ => LivenessToken(None),
+            }
+        }
     }
 
-    fn format_env_block(env: &[(OsString, OsString)]) -> Vec<u16> {
-        fn to_uppercase(s: &OsStr) -> OsString {
-            OsString::from_wide(
-                &s.encode_wide()
-                    .map(|c| {
-                        if c < 128 {
-                            (c as u8 as char).to_ascii_uppercase() as u16
-                        } else {
-                            c
+    #[derive(Debug, Clone)]
+    pub struct LivenessToken(Option<Arc<win32::Handle>>);
+
+    impl LivenessToken {
+        pub fn is_alive(&self) -> bool {
+            match self.0 {
+                Some(ref handle) => matches!(
+                    win32::WaitForSingleObject(handle, Some(Duration::from_secs(0))),
+                    Ok(win32::WaitEvent::TIMEOUT)
+                ),
+                None => false,
+            }
+        }
+
+        pub fn kill(&self) -> io::Result<()> {
+            match self.0 {
+                Some(ref handle) if self.is_alive() => {
+                    match win32::TerminateProcess(handle, TERMINATED_EXIT_CODE) {
+                        // the process has exited in the meantime
+                        Err(ref e)
+                            if e.raw_os_error() == Some(win32::ERROR_ACCESS_DENIED as i32) =>
+                        {
+                            Ok(())
                         }
-                    })
-                    .collect::<Vec<_>>(),
-            )
-        }
-        let mut pruned: Vec<_> = {
-            let mut seen = HashSet::<OsString>::new();
-            env.iter()
-                .rev()
-                .filter(|&(k, _)| seen.insert(to_uppercase(k)))
-                .collect()
-        };
-        pruned.reverse();
+                        result => result,
+                    }
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn is_transient_spawn_error(error: &io::Error) -> bool {
+        matches!(
+            error.raw_os_error().map(|code| code as u32),
+            Some(win32::ERROR_NOT_ENOUGH_MEMORY) | Some(win32::ERROR_NO_SYSTEM_RESOURCES)
+        )
+    }
+
+    fn exec_error_kind(error: &io::Error, program: &OsStr) -> ExecErrorKind {
+        match error.raw_os_error().map(|code| code as u32) {
+            Some(win32::ERROR_FILE_NOT_FOUND) | Some(win32::ERROR_PATH_NOT_FOUND) => {
+                ExecErrorKind::NotFound
+            }
+            // CreateProcess reports ERROR_ACCESS_DENIED for directories
+            Some(win32::ERROR_ACCESS_DENIED) if is_directory_path(program) => {
+                ExecErrorKind::IsDirectory
+            }
+            Some(win32::ERROR_ACCESS_DENIED) => ExecErrorKind::PermissionDenied,
+            Some(win32::ERROR_BAD_EXE_FORMAT) => ExecErrorKind::BadExecutable,
+            _ => ExecErrorKind::Other,
+        }
+    }
+
+    fn format_env_block(env: &[(OsString, OsString)]) -> Vec<u16> {
+        // Windows requires the block to be sorted by name,
+        // case-insensitively.  The sort is stable, so among entries for
+        // the same variable, the later-appearing one, which takes
+        // precedence, comes first and survives the dedup.
+        let mut pruned: Vec<_> = env.iter().rev().collect();
+        pruned.sort_by(|a, b| win32::compare_env_keys(&a.0, &b.0));
+        pruned.dedup_by(|a, b| win32::compare_env_keys(&a.0, &b.0) == Ordering::Equal);
         let mut block = vec![];
         for (k, v) in pruned {
             block.extend(k.encode_wide());
@@ -1082,6 +3413,11 @@ mod os {
             block.extend(v.encode_wide());
             block.push(0);
         }
+        if block.is_empty() {
+            // an empty block still needs two terminating NULs, or
+            // CreateProcess reads past its end
+            block.push(0);
+        }
         block.push(0);
         block
     }
@@ -1141,6 +3477,59 @@ mod os {
         win32::CreatePipe(true)
     }
 
+    // Create a pipe whose read end is kept by the parent.  The read end
+    // supports overlapped IO, which read_timeout() relies on.
+    pub fn make_parent_read_pipe() -> io::Result<(File, File)> {
+        win32::CreateOverlappedPipe()
+    }
+
+    // Take over a child spawned by std::process, returning its PID and
+    // the OS-specific part of the child state.
+    pub fn adopt_std_child(child: process::Child) -> (u32, ExtChildState) {
+        let pid = child.id();
+        let handle = unsafe { win32::Handle::from_raw_handle(child.into_raw_handle()) };
+        (pid, ExtChildState(Arc::new(handle)))
+    }
+
+    pub fn std_stream_into_file(stream: impl IntoRawHandle) -> File {
+        unsafe { File::from_raw_handle(stream.into_raw_handle()) }
+    }
+
+    pub fn read_timeout(f: &File, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        win32::ReadPipeTimeout(f, buf, timeout)
+    }
+
+    pub fn format_response_file(args: &[OsString]) -> Vec<u8> {
+        // One argument per line, quoted as on the command line.
+        let mut contents = String::new();
+        for arg in args {
+            let mut quoted = vec![];
+            append_quoted(arg, &mut quoted);
+            contents.push_str(&String::from_utf16_lossy(&quoted));
+            contents.push_str("\r\n");
+        }
+        contents.into_bytes()
+    }
+
+    pub fn is_terminal(which: StandardStream) -> bool {
+        win32::IsConsole(which)
+    }
+
+    pub fn resolve_program(program: &OsStr) -> Option<PathBuf> {
+        // Approximates the search done by CreateProcess, which also looks
+        // in the application and system directories.
+        let located = locate_in_path(program.to_owned());
+        if fs::metadata(&located).is_ok() {
+            Some(located.into())
+        } else {
+            None
+        }
+    }
+
+    pub fn bytes_available(f: &File) -> io::Result<usize> {
+        win32::PeekNamedPipe(f)
+    }
+
     fn locate_in_path(executable: OsString) -> OsString {
         if let Some(path) = env::var_os("PATH") {
             for path in env::split_paths(&path) {
@@ -1220,20 +3609,126 @@ mod os {
         cmdline.push('"' as u16);
     }
 
-    pub mod ext {}
+    pub mod ext {
+        use crate::popen::{Popen, PopenError, Result};
+        use crate::win32;
+
+        /// Windows-specific extension methods for `Popen`
+        pub trait PopenExt {
+            /// Return true if the process was ended by [`terminate`] or
+            /// [`kill`].
+            ///
+            /// Windows has no signals, so a terminated process reports
+            /// `ExitStatus::Exited` with the exit code given to
+            /// `TerminateProcess`, which the process could also have
+            /// exited with on its own.  This tells the two apart: it
+            /// returns true once `TerminateProcess` has succeeded.  If the
+            /// process had already exited when [`terminate`] was called,
+            /// it reports its real exit code and this returns false.
+            ///
+            /// Processes killed by a [`Communicator`] whose time limit
+            /// expired are not tracked.
+            ///
+            /// [`terminate`]: ../struct.Popen.html#method.terminate
+            /// [`kill`]: ../struct.Popen.html#method.kill
+            /// [`Communicator`]: ../struct.Communicator.html
+            fn terminated(&self) -> bool;
+
+            /// Resume the main thread of a child created suspended.
+            ///
+            /// The child must have been created with
+            /// [`PopenConfig::exec_barrier`] set or with `CREATE_SUSPENDED`
+            /// in [`PopenConfig::creation_flags`], and not released yet;
+            /// otherwise this returns `PopenError::LogicError`.  The
+            /// program has already been loaded by the time the `Popen`
+            /// is created, so errors in executing it are reported then,
+            /// and this only fails if the thread cannot be resumed.
+            ///
+            /// [`PopenConfig::exec_barrier`]: ../struct.PopenConfig.html#structfield.exec_barrier
+            /// [`PopenConfig::creation_flags`]: ../struct.PopenConfig.html#structfield.creation_flags
+            fn release(&mut self) -> Result<()>;
+        }
+
+        impl PopenExt for Popen {
+            fn terminated(&self) -> bool {
+                self.terminate_requested
+            }
+
+            fn release(&mut self) -> Result<()> {
+                let pending = match self.pending_exec.take() {
+                    Some(pending) => pending,
+                    None => return Err(PopenError::LogicError("child not suspended")),
+                };
+                win32::ResumeThread(&pending.thread)?;
+                Ok(())
+            }
+        }
+    }
 }
 
 impl Drop for Popen {
     // Wait for the process to exit.  To avoid the wait, call
     // detach().
     fn drop(&mut self) {
+        if let Some(warn) = self.warn_unread_output {
+            self.check_unread_output(warn);
+        }
+        // A child waiting at the exec barrier exits once the barrier is
+        // closed, instead of blocking the wait below.  On Windows, a
+        // child created suspended is terminated instead.
+        #[cfg(unix)]
+        self.pending_exec.take();
+        #[cfg(windows)]
+        {
+            if self.pending_exec.take().is_some() {
+                self.kill().ok();
+            }
+        }
         if let (false, &Running { .. }) = (self.detached, &self.child_state) {
+            if let Some(t) = self.terminate_on_drop {
+                if self.os_request_exit(t.signal).is_ok() {
+                    if let Ok(Some(_)) = self.wait_timeout(t.grace) {
+                        return;
+                    }
+                }
+                self.kill().ok();
+            }
             // Should we log error if one occurs during drop()?
             self.wait().ok();
         }
     }
 }
 
+impl Popen {
+    // Redirecting output to a pipe and never reading it is a common
+    // mistake, so PopenConfig::warn_unread_output points it out.
+    fn check_unread_output(&self, warn: fn(&str)) {
+        for &(name, stream) in &[("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if let Some(ref f) = *stream {
+                match os::bytes_available(f) {
+                    Ok(n) if n > 0 => warn(&format!(
+                        "subprocess: Popen dropped with {} unread bytes in its {} pipe; \
+                         read them or use communicate()",
+                        n, name
+                    )),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+// The default PopenConfig::warn_unread_output of debug builds.
+#[cfg(debug_assertions)]
+fn print_unread_warning(msg: &str) {
+    if env::var_os("SUBPROCESS_NO_UNREAD_WARNING").is_none() {
+        eprintln!(
+            "{} (set SUBPROCESS_NO_UNREAD_WARNING to silence this warning)",
+            msg
+        );
+    }
+}
+
 thread_local! {
     static STREAMS: RefCell<[Option<Rc<File>>; 3]> = RefCell::default();
 }
@@ -1246,7 +3741,7 @@ use crate::win32::make_standard_stream;
 fn get_standard_stream(which: StandardStream) -> io::Result<Rc<File>> {
     STREAMS.with(|streams| {
         if let Some(ref stream) = streams.borrow()[which as usize] {
-            return Ok(Rc::clone(&stream));
+            return Ok(Rc::clone(stream));
         }
         let stream = make_standard_stream(which)?;
         streams.borrow_mut()[which as usize] = Some(Rc::clone(&stream));
@@ -1254,6 +3749,99 @@ fn get_standard_stream(which: StandardStream) -> io::Result<Rc<File>> {
     })
 }
 
+// Call spawn, retrying on transient errors as requested by
+// PopenConfig::spawn_retry.
+pub(crate) fn retry_spawn<T>(
+    retry: Option<(u32, Duration)>,
+    is_transient: fn(&io::Error) -> bool,
+    mut spawn: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let (mut attempts_left, delay) = retry.unwrap_or((0, Duration::from_secs(0)));
+    loop {
+        match spawn() {
+            Err(ref e) if attempts_left > 0 && is_transient(e) => {
+                attempts_left -= 1;
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+// Find the file that would be executed for `program`, see Exec::plan.
+pub(crate) fn resolve_program(program: &OsStr) -> Option<PathBuf> {
+    os::resolve_program(program)
+}
+
+// Whether the standard stream of the current process is a terminal, see
+// Exec::stdout_auto.
+pub(crate) fn is_terminal(which: StandardStream) -> bool {
+    os::is_terminal(which)
+}
+
+// Write `args` to a response file for Exec::args_via_file.
+pub(crate) fn format_response_file(args: &[OsString]) -> Vec<u8> {
+    os::format_response_file(args)
+}
+
+// Whether a program that failed to execute is given by a path naming a
+// directory.  This is only checked after the fact to improve the error
+// message, so the inherent race with the filesystem doesn't matter.  Bare
+// names are looked up in PATH by the child, so they're not checked.
+fn is_directory_path(program: &OsStr) -> bool {
+    let path = Path::new(program);
+    matches!(path.parent(), Some(p) if !p.as_os_str().is_empty()) && path.is_dir()
+}
+
+/// Timing of the steps taken to start a subprocess, see
+/// [`Popen::spawn_metrics`].
+///
+/// [`Popen::spawn_metrics`]: struct.Popen.html#method.spawn_metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SpawnMetrics {
+    /// Time spent creating the process, including any retries
+    /// requested with `PopenConfig::spawn_retry`.  This is the time
+    /// taken by `fork()` on Unix-like systems, and by `CreateProcess` on
+    /// Windows.
+    pub spawn: Duration,
+    /// Time from the start of process creation until the program was
+    /// executed.  On Unix-like systems this additionally includes the
+    /// setup done in the child and the `exec()` call.  On Windows,
+    /// where `CreateProcess` does both, it's the same as `spawn`.
+    pub exec: Duration,
+}
+
+/// How to stop a running process when its [`Popen`] is dropped, see
+/// [`PopenConfig::terminate_on_drop`].
+///
+/// [`Popen`]: struct.Popen.html
+/// [`PopenConfig::terminate_on_drop`]: struct.PopenConfig.html#structfield.terminate_on_drop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminateOnDrop {
+    /// The signal sent to ask the process to exit.
+    ///
+    /// This is only used on Unix-like systems.  On Windows, a process
+    /// started in a new process group, i.e. with
+    /// `CREATE_NEW_PROCESS_GROUP` among its creation flags, is instead
+    /// sent a Ctrl+Break event.  Other processes cannot be asked to
+    /// exit, and are only given the grace period to finish.
+    pub signal: i32,
+    /// How long to wait for the process to exit before killing it.
+    pub grace: Duration,
+}
+
+impl TerminateOnDrop {
+    /// Ask the process to exit with `SIGTERM`, and kill it if it
+    /// doesn't exit within `grace`.
+    pub fn new(grace: Duration) -> TerminateOnDrop {
+        TerminateOnDrop {
+            signal: libc::SIGTERM,
+            grace,
+        }
+    }
+}
+
 /// Error in [`Popen`] calls.
 ///
 /// [`Popen`]: struct.Popen.html
@@ -1265,6 +3853,59 @@ pub enum PopenError {
     IoError(io::Error),
     /// A logical error was made, e.g. invalid arguments detected at run-time.
     LogicError(&'static str),
+    /// The OS failed to create the process.
+    ///
+    /// This is reported when `fork()` (or `clone()`) fails on Unix-like
+    /// systems, and when `CreateProcess` fails for a transient reason
+    /// such as resource exhaustion on Windows (see
+    /// [`PopenConfig::spawn_retry`]).  Such failures concern the parent
+    /// rather than the program and can be worth retrying later.
+    ///
+    /// [`PopenConfig::spawn_retry`]: struct.PopenConfig.html#structfield.spawn_retry
+    SpawnError(io::Error),
+    /// The program could not be executed.
+    ///
+    /// This is reported instead of `IoError` when the process was
+    /// prepared successfully, but the OS refused to execute the program.
+    /// The error message includes the program name along with a
+    /// description of the problem, such as "is a directory".
+    ExecError {
+        /// The reason the program could not be executed.
+        kind: ExecErrorKind,
+        /// The program that was to be executed, i.e. the executable if
+        /// one was specified, or the first element of `argv` otherwise.
+        program: OsString,
+        /// The underlying OS error.
+        error: io::Error,
+    },
+    /// The program was executed, but exited unsuccessfully right away.
+    ///
+    /// This is only reported when requested with
+    /// [`PopenConfig::early_exit_check`].  It indicates a problem with
+    /// the program's arguments or environment rather than with starting
+    /// it.
+    ///
+    /// [`PopenConfig::early_exit_check`]: struct.PopenConfig.html#structfield.early_exit_check
+    EarlyExit(ExitStatus),
+}
+
+/// The reason why a program could not be executed, see
+/// [`PopenError::ExecError`].
+///
+/// [`PopenError::ExecError`]: enum.PopenError.html#variant.ExecError
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExecErrorKind {
+    /// The program doesn't exist.
+    NotFound,
+    /// The program exists, but the user is not allowed to execute it.
+    PermissionDenied,
+    /// The program is not in a format the OS can execute.
+    BadExecutable,
+    /// The program names a directory.
+    IsDirectory,
+    /// Any other error.
+    Other,
 }
 
 impl From<io::Error> for PopenError {
@@ -1284,6 +3925,9 @@ impl Error for PopenError {
         match *self {
             PopenError::IoError(ref err) => Some(err),
             PopenError::LogicError(_msg) => None,
+            PopenError::SpawnError(ref err) => Some(err),
+            PopenError::ExecError { ref error, .. } => Some(error),
+            PopenError::EarlyExit(_) => None,
         }
     }
 }
@@ -1293,6 +3937,23 @@ impl fmt::Display for PopenError {
         match *self {
             PopenError::IoError(ref err) => fmt::Display::fmt(err, f),
             PopenError::LogicError(desc) => f.write_str(desc),
+            PopenError::SpawnError(ref err) => write!(f, "failed to create process: {}", err),
+            PopenError::ExecError {
+                kind,
+                ref program,
+                ref error,
+            } => {
+                write!(f, "{}: ", program.to_string_lossy())?;
+                match kind {
+                    ExecErrorKind::PermissionDenied => f.write_str("permission denied"),
+                    ExecErrorKind::BadExecutable => f.write_str("not an executable format"),
+                    ExecErrorKind::IsDirectory => f.write_str("is a directory"),
+                    ExecErrorKind::NotFound | ExecErrorKind::Other => fmt::Display::fmt(error, f),
+                }
+            }
+            PopenError::EarlyExit(status) => {
+                write!(f, "process exited during startup: {}", status)
+            }
         }
     }
 }
@@ -1,21 +1,27 @@
+use std::convert::TryFrom;
 use std::env;
-use std::ffi::{CString, OsStr, OsString};
-use std::fs::File;
-use std::io::{Error, Result};
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::iter;
 use std::marker::PhantomData;
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::rc::Rc;
+use std::slice;
 use std::time::{Duration, Instant};
 
 use libc::{c_char, c_int};
 
 use crate::os_common::{ExitStatus, StandardStream};
 
-pub use libc::{ECHILD, ENOSPC};
+pub use libc::{
+    EACCES, EAGAIN, EBADF, ECHILD, EINTR, EIO, EISDIR, ENOENT, ENOEXEC, ENOTDIR, EPERM, ESRCH,
+};
 
 fn check_err<T: Ord + Default>(num: T) -> Result<T> {
     if num < T::default() {
@@ -30,17 +36,164 @@ pub fn pipe() -> Result<(File, File)> {
     Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
 }
 
+// Create a pipe whose both ends are close-on-exec.  Where pipe2() is
+// available, this is done atomically in a single system call.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub fn pipe_cloexec() -> Result<(File, File)> {
+    let mut fds = [0 as c_int; 2];
+    check_err(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })?;
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub fn pipe_cloexec() -> Result<(File, File)> {
+    let (read, write) = pipe()?;
+    for f in &[&read, &write] {
+        let fd = f.as_raw_fd();
+        let old = fcntl(fd, F_GETFD, None)?;
+        fcntl(fd, F_SETFD, Some(old | FD_CLOEXEC))?;
+    }
+    Ok((read, write))
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub fn socketpair_cloexec() -> Result<(File, File)> {
+    let mut fds = [0 as c_int; 2];
+    check_err(unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    })?;
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub fn socketpair_cloexec() -> Result<(File, File)> {
+    let mut fds = [0 as c_int; 2];
+    check_err(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) })?;
+    let (a, b) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for f in &[&a, &b] {
+        let fd = f.as_raw_fd();
+        let old = fcntl(fd, F_GETFD, None)?;
+        fcntl(fd, F_SETFD, Some(old | FD_CLOEXEC))?;
+    }
+    Ok((a, b))
+}
+
 // marked unsafe because the child must not allocate before exec-ing
 pub unsafe fn fork() -> Result<Option<u32>> {
     let pid = check_err(libc::fork())?;
     if pid == 0 {
-        check_err(libc::setpgid(std::process::id() as i32, std::process::id() as i32))?;
         Ok(None) // child
     } else {
         Ok(Some(pid as u32)) // parent
     }
 }
 
+pub fn setpgid(pid: u32, pgid: u32) -> Result<()> {
+    check_err(unsafe { libc::setpgid(pid as libc::pid_t, pgid as libc::pid_t) })?;
+    Ok(())
+}
+
+pub fn getpgid(pid: u32) -> Result<u32> {
+    let pgid = check_err(unsafe { libc::getpgid(pid as libc::pid_t) })?;
+    Ok(pgid as u32)
+}
+
+pub fn setsid() -> Result<()> {
+    check_err(unsafe { libc::setsid() })?;
+    Ok(())
+}
+
+// Open a new pseudo-terminal and return its master and slave ends.
+// Both are close-on-exec, and neither becomes the controlling
+// terminal of the current process.
+pub fn openpty() -> Result<(File, File)> {
+    let master = check_err(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) })?;
+    let master = unsafe { File::from_raw_fd(master) };
+    fcntl(master.as_raw_fd(), F_SETFD, Some(FD_CLOEXEC))?;
+    check_err(unsafe { libc::grantpt(master.as_raw_fd()) })?;
+    check_err(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+    let name = ptsname(&master)?;
+    let slave = check_err(unsafe {
+        libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    })?;
+    Ok((master, unsafe { File::from_raw_fd(slave) }))
+}
+
+#[cfg(target_os = "linux")]
+fn ptsname(master: &File) -> Result<CString> {
+    let mut buf = [0 as c_char; 128];
+    let err = unsafe { libc::ptsname_r(master.as_raw_fd(), buf.as_mut_ptr(), buf.len()) };
+    if err != 0 {
+        return Err(Error::from_raw_os_error(err));
+    }
+    Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn ptsname(master: &File) -> Result<CString> {
+    // ptsname() uses a static buffer, but ptsname_r() isn't portable
+    let name = unsafe { libc::ptsname(master.as_raw_fd()) };
+    if name.is_null() {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { CStr::from_ptr(name) }.to_owned())
+}
+
+// Make the terminal open as `fd` the controlling terminal of the
+// current process, which must be a session leader without one.  Safe
+// to call between fork() and exec().
+pub fn set_controlling_terminal(fd: i32) -> Result<()> {
+    check_err(unsafe { libc::ioctl(fd, libc::TIOCSCTTY, 0) })?;
+    Ok(())
+}
+
+pub fn set_window_size(terminal: &File, rows: u16, cols: u16) -> Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    check_err(unsafe { libc::ioctl(terminal.as_raw_fd(), libc::TIOCSWINSZ, &size) })?;
+    Ok(())
+}
+
 pub fn setuid(uid: u32) -> Result<()> {
     check_err(unsafe { libc::setuid(uid as libc::uid_t) })?;
     Ok(())
@@ -51,6 +204,59 @@ pub fn setgid(gid: u32) -> Result<()> {
     Ok(())
 }
 
+pub fn fchdir(fd: i32) -> Result<()> {
+    check_err(unsafe { libc::fchdir(fd) })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn sched_setaffinity(cpus: &[usize]) -> Result<()> {
+    // The CPU set lives on the stack, so this is safe to call between
+    // fork() and exec().
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    check_err(unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_oom_score_adj(score: i32) -> Result<()> {
+    // The value is formatted on the stack, so this is safe to call
+    // between fork() and exec().
+    if !(-1000..=1000).contains(&score) {
+        return Err(Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut buf = [0u8; 8];
+    let mut pos = buf.len();
+    let mut n = score.abs();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    if score < 0 {
+        pos -= 1;
+        buf[pos] = b'-';
+    }
+    let path = b"/proc/self/oom_score_adj\0";
+    let fd = check_err(unsafe {
+        libc::open(
+            path.as_ptr() as *const c_char,
+            libc::O_WRONLY | libc::O_CLOEXEC,
+        )
+    })?;
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(&buf[pos..])
+}
+
 fn os_to_cstring(s: &OsStr) -> Result<CString> {
     // Like CString::new, but returns an io::Result for consistency with
     // everything else.
@@ -61,6 +267,7 @@ fn os_to_cstring(s: &OsStr) -> Result<CString> {
 struct CVec {
     // Individual C strings.  Each element self.ptrs[i] points to the
     // data of self.strings[i].as_bytes_with_nul().as_ptr().
+    #[allow(dead_code)]
     strings: Vec<CString>,
 
     // nullptr-terminated vector of pointers to data inside
@@ -108,35 +315,6 @@ fn split_path(mut path: &OsStr) -> impl Iterator<Item = &OsStr> {
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::split_path;
-    use std;
-    use std::ffi::OsStr;
-    use std::os::unix::ffi::OsStrExt;
-
-    fn s(s: &str) -> Vec<&str> {
-        split_path(OsStr::new(s))
-            .map(|osstr| std::str::from_utf8(osstr.as_bytes()).unwrap())
-            .collect()
-    }
-
-    #[test]
-    fn test_split_path() {
-        let empty = Vec::<&OsStr>::new();
-
-        assert_eq!(s("a:b"), vec!["a", "b"]);
-        assert_eq!(s("one:twothree"), vec!["one", "twothree"]);
-        assert_eq!(s("a:"), vec!["a"]);
-        assert_eq!(s(""), empty);
-        assert_eq!(s(":"), empty);
-        assert_eq!(s("::"), empty);
-        assert_eq!(s(":::"), empty);
-        assert_eq!(s("a::b"), vec!["a", "b"]);
-        assert_eq!(s(":a::::b:"), vec!["a", "b"]);
-    }
-}
-
 struct PrepExec {
     cmd: OsString,
     argvec: CVec,
@@ -173,7 +351,9 @@ impl PrepExec {
         }
     }
 
-    fn exec(mut self) -> Result<()> {
+    // `attempt` is called with the index of each candidate in
+    // ExecCandidates before trying to execute it.
+    fn exec(mut self, attempt: &mut dyn FnMut(usize)) -> Result<()> {
         // Invoked after fork() - no heap allocation allowed
         let mut exe = std::mem::take(&mut self.prealloc_exe);
 
@@ -181,7 +361,8 @@ impl PrepExec {
             let mut err = Ok(());
             // POSIX requires execvp and execve, but not execvpe (although
             // glibc provides one), so we have to iterate over PATH ourselves
-            for dir in split_path(search_path.as_os_str()) {
+            for (index, dir) in split_path(search_path.as_os_str()).enumerate() {
+                attempt(index);
                 err = self.libc_exec(PrepExec::assemble_exe(
                     &mut exe,
                     &[dir.as_bytes(), b"/", self.cmd.as_bytes()],
@@ -194,6 +375,7 @@ impl PrepExec {
             return err;
         }
 
+        attempt(0);
         self.libc_exec(PrepExec::assemble_exe(&mut exe, &[self.cmd.as_bytes()]))?;
 
         // failed exec can only return Err(..)
@@ -223,17 +405,52 @@ impl PrepExec {
     }
 }
 
+// The PATH to search for `cmd`, or None if it is to be executed as-is.
+fn exec_search_path(cmd: &OsStr) -> Option<OsString> {
+    if cmd.as_bytes().contains(&b'/') {
+        return None;
+    }
+    // treat empty path as non-existent
+    env::var_os("PATH").filter(|p| !p.is_empty())
+}
+
+/// The files that the exec prepared by `prep_exec` tries to execute, in
+/// order.
+#[derive(Debug)]
+pub struct ExecCandidates {
+    cmd: OsString,
+    search_path: Option<OsString>,
+}
+
+impl ExecCandidates {
+    /// The candidate with the index passed to the exec's `attempt`
+    /// callback.
+    pub fn get(&self, index: usize) -> Option<PathBuf> {
+        match self.search_path {
+            Some(ref search_path) => split_path(search_path)
+                .nth(index)
+                .map(|dir| Path::new(dir).join(&self.cmd)),
+            None if index == 0 => Some(PathBuf::from(&self.cmd)),
+            None => None,
+        }
+    }
+}
+
 /// Prepare everything needed to `exec()` the provided `cmd` after `fork()`.
 ///
 /// Since code executed in the child after a `fork()` is not allowed to
 /// allocate (because the lock might be held), this allocates everything
-/// beforehand.
-
+/// beforehand.  The returned closure calls its argument with the index
+/// of each of the candidates before trying to execute it, which allows
+/// the parent to tell which one got executed.
 pub fn prep_exec(
     cmd: impl AsRef<OsStr>,
     args: &[impl AsRef<OsStr>],
     env: Option<&[impl AsRef<OsStr>]>,
-) -> Result<impl FnOnce() -> Result<()>> {
+) -> Result<(
+    impl FnOnce(&mut dyn FnMut(usize)) -> Result<()>,
+    ExecCandidates,
+)> {
     let cmd = cmd.as_ref().to_owned();
     let argvec = CVec::new(args)?;
     let envvec = if let Some(env) = env {
@@ -242,17 +459,36 @@ pub fn prep_exec(
         None
     };
 
-    let search_path = if !cmd.as_bytes().iter().any(|&b| b == b'/') {
-        env::var_os("PATH")
-            // treat empty path as non-existent
-            .and_then(|p| if p.len() == 0 { None } else { Some(p) })
-    } else {
-        None
+    let search_path = exec_search_path(&cmd);
+    let candidates = ExecCandidates {
+        cmd: cmd.clone(),
+        search_path: search_path.clone(),
     };
 
     // Allocate now and return a closure that just does the exec.
     let prep = PrepExec::new(cmd, argvec, envvec, search_path);
-    Ok(move || prep.exec())
+    Ok((
+        move |attempt: &mut dyn FnMut(usize)| prep.exec(attempt),
+        candidates,
+    ))
+}
+
+/// Find the file that the exec prepared by `prep_exec` would run.
+///
+/// Like `PrepExec::exec`, this searches the current `PATH` for commands
+/// that don't contain a slash, returning the first match that is an
+/// executable file, or `None` if there is no match.
+pub fn resolve_exec(cmd: &OsStr) -> Option<PathBuf> {
+    match exec_search_path(cmd) {
+        Some(ref search_path) => split_path(search_path)
+            .map(|dir| Path::new(dir).join(cmd))
+            .find(|exe| {
+                fs::metadata(exe)
+                    .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false)
+            }),
+        _ => Some(PathBuf::from(cmd)),
+    }
 }
 
 pub fn _exit(status: u8) -> ! {
@@ -261,7 +497,8 @@ pub fn _exit(status: u8) -> ! {
 
 pub const WNOHANG: i32 = libc::WNOHANG;
 
-pub fn waitpid(pid: u32, flags: i32) -> Result<(u32, ExitStatus)> {
+// Returns the PID, the decoded exit status, and the raw wait status.
+pub fn waitpid(pid: u32, flags: i32) -> Result<(u32, ExitStatus, i32)> {
     let mut status = 0 as c_int;
     let pid = check_err(unsafe {
         libc::waitpid(
@@ -270,7 +507,74 @@ pub fn waitpid(pid: u32, flags: i32) -> Result<(u32, ExitStatus)> {
             flags as c_int,
         )
     })?;
-    Ok((pid as u32, decode_exit_status(status)))
+    Ok((pid as u32, decode_exit_status(status), status))
+}
+
+pub fn wait4(pid: u32, flags: i32) -> Result<(u32, ExitStatus, i32, libc::rusage)> {
+    let mut status = 0 as c_int;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    let pid = check_err(unsafe {
+        libc::wait4(
+            pid as libc::pid_t,
+            &mut status as *mut c_int,
+            flags as c_int,
+            &mut rusage as *mut libc::rusage,
+        )
+    })?;
+    Ok((pid as u32, decode_exit_status(status), status, rusage))
+}
+
+// Reap any child of the current process, returning its PID, or None if
+// there are no children left.
+#[cfg(target_os = "linux")]
+pub fn wait_any() -> Result<Option<u32>> {
+    let mut status = 0 as c_int;
+    loop {
+        match check_err(unsafe { libc::waitpid(-1, &mut status as *mut c_int, 0) }) {
+            Ok(pid) => return Ok(Some(pid as u32)),
+            Err(e) if e.raw_os_error() == Some(ECHILD) => return Ok(None),
+            Err(e) if e.raw_os_error() == Some(EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_child_subreaper(enable: bool) -> Result<()> {
+    check_err(unsafe {
+        libc::prctl(
+            libc::PR_SET_CHILD_SUBREAPER,
+            enable as libc::c_ulong,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+        )
+    })?;
+    Ok(())
+}
+
+// Obtain a file descriptor referring to the process, which becomes
+// readable when the process exits.  Fails with ENOSYS on kernels older
+// than 5.3.
+#[cfg(target_os = "linux")]
+pub fn pidfd_open(pid: u32) -> Result<File> {
+    let fd = check_err(unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) })?;
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+// Check whether the child has exited, without reaping it.
+pub fn child_exited(pid: u32) -> Result<bool> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    check_err(unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info as *mut libc::siginfo_t,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    })?;
+    // with WNOHANG, si_pid is left zero if the child hasn't exited
+    Ok(unsafe { info.si_pid() } != 0)
 }
 
 fn decode_exit_status(status: i32) -> ExitStatus {
@@ -283,13 +587,38 @@ fn decode_exit_status(status: i32) -> ExitStatus {
     }
 }
 
-pub use libc::{SIGKILL, SIGTERM};
+pub use libc::{SIGCONT, SIGKILL, SIGSTOP, SIGTERM};
 
 pub fn kill(pid: u32, signal: i32) -> Result<()> {
     check_err(unsafe { libc::kill(pid as c_int, signal) })?;
     Ok(())
 }
 
+pub fn killpg(pgid: u32, signal: i32) -> Result<()> {
+    check_err(unsafe { libc::killpg(pgid as c_int, signal) })?;
+    Ok(())
+}
+
+// Like fork(), but the child starts in the new namespaces requested by
+// the CLONE_NEW* flags.  With a null stack, clone() duplicates the
+// caller's stack just like fork() does.
+#[cfg(target_os = "linux")]
+pub unsafe fn fork_into_namespaces(flags: i32) -> Result<Option<u32>> {
+    let pid = check_err(libc::syscall(
+        libc::SYS_clone,
+        (flags | libc::SIGCHLD) as libc::c_ulong,
+        ptr::null_mut::<libc::c_void>(),
+        ptr::null_mut::<libc::c_void>(),
+        ptr::null_mut::<libc::c_void>(),
+        0 as libc::c_ulong,
+    ))?;
+    if pid == 0 {
+        Ok(None) // child
+    } else {
+        Ok(Some(pid as u32)) // parent
+    }
+}
+
 pub const F_GETFD: i32 = libc::F_GETFD;
 pub const F_SETFD: i32 = libc::F_SETFD;
 pub const FD_CLOEXEC: i32 = libc::FD_CLOEXEC;
@@ -303,11 +632,195 @@ pub fn fcntl(fd: i32, cmd: i32, arg1: Option<i32>) -> Result<i32> {
     })
 }
 
+// Mark the descriptor close-on-exec, ignoring it if it is not open.
+pub fn set_cloexec_if_open(fd: i32) -> Result<()> {
+    match fcntl(fd, F_GETFD, None) {
+        Ok(flags) => {
+            fcntl(fd, F_SETFD, Some(flags | FD_CLOEXEC))?;
+        }
+        Err(e) if e.raw_os_error() == Some(EBADF) => (),
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+// Mark all descriptors numbered low_fd or higher close-on-exec.
+pub fn set_cloexec_from(low_fd: i32) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // Linux 5.11 and later can do it in a single call.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_close_range,
+                low_fd as libc::c_uint,
+                libc::c_uint::MAX,
+                libc::CLOSE_RANGE_CLOEXEC,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+    }
+    // Descriptors can't exceed the limit on open files, unless it was
+    // lowered after they were opened.  Cap the limit in case it's
+    // unlimited.
+    let mut limit: libc::rlimit = unsafe { mem::zeroed() };
+    check_err(unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) })?;
+    let max_fd = std::cmp::min(limit.rlim_cur, 1 << 20) as i32;
+    for fd in low_fd..max_fd {
+        set_cloexec_if_open(fd)?;
+    }
+    Ok(())
+}
+
+// Duplicate the file to a close-on-exec descriptor no lower than min_fd.
+pub fn dup_cloexec_from(file: &File, min_fd: i32) -> Result<File> {
+    let fd = fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, Some(min_fd))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+const RECV_FD_FLAGS: c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+const RECV_FD_FLAGS: c_int = 0;
+
+// Receive a file descriptor sent over a Unix domain socket with
+// SCM_RIGHTS, along with the single byte of data it's attached to.
+// Returns None on EOF.
+pub fn recv_fd(sock: &File) -> Result<Option<File>> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    // room for a single descriptor, u64 for cmsghdr alignment
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<c_int>() as u32) } as _;
+    let n = check_err(unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, RECV_FD_FLAGS) })?;
+    if n == 0 {
+        return Ok(None);
+    }
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    if cmsg.is_null()
+        || unsafe {
+            (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        }
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "no file descriptor received",
+        ));
+    }
+    let fd = unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int) };
+    let file = unsafe { File::from_raw_fd(fd) };
+    if RECV_FD_FLAGS == 0 {
+        let old = fcntl(fd, F_GETFD, None)?;
+        fcntl(fd, F_SETFD, Some(old | FD_CLOEXEC))?;
+    }
+    Ok(Some(file))
+}
+
+// Read from a pipe, giving up if no data arrives within timeout.  The
+// pipe is non-blocking only for the duration of the read, so that a
+// spurious wakeup can't block it.
+pub fn read_timeout(file: &File, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>> {
+    let mut fds = [PollFd::new(Some(file), POLLIN)];
+    if poll(&mut fds, Some(timeout))? == 0 {
+        return Ok(None);
+    }
+    let fd = file.as_raw_fd();
+    let flags = fcntl(fd, libc::F_GETFL, None)?;
+    fcntl(fd, libc::F_SETFL, Some(flags | libc::O_NONBLOCK))?;
+    let result = (&*file).read(buf);
+    fcntl(fd, libc::F_SETFL, Some(flags))?;
+    match result {
+        Ok(n) => Ok(Some(n)),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Number of bytes available for reading without blocking.
+pub fn bytes_available(file: &File) -> Result<usize> {
+    let mut n: c_int = 0;
+    check_err(unsafe { libc::ioctl(file.as_raw_fd(), libc::FIONREAD, &mut n) })?;
+    Ok(n as usize)
+}
+
+// A read-only mapping of part of a file.
+pub struct FileMapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl FileMapping {
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// Map `len` bytes of `file` starting at `offset`, which must be a
+// multiple of the page size.
+pub fn mmap_file(file: &File, offset: u64, len: usize) -> Result<FileMapping> {
+    let offset =
+        libc::off_t::try_from(offset).map_err(|_| Error::from_raw_os_error(libc::EOVERFLOW))?;
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            offset,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(Error::last_os_error());
+    }
+    Ok(FileMapping { ptr, len })
+}
+
 pub fn dup2(oldfd: i32, newfd: i32) -> Result<()> {
     check_err(unsafe { libc::dup2(oldfd, newfd) })?;
     Ok(())
 }
 
+// Make sure that `fd` remains open across exec, pointing it to
+// /dev/null if it's closed or close-on-exec.  Safe to call between
+// fork() and exec().
+pub fn reopen_null_if_closed(fd: i32) -> Result<()> {
+    match fcntl(fd, F_GETFD, None) {
+        Ok(flags) if flags & FD_CLOEXEC == 0 => return Ok(()),
+        Ok(_) => (),
+        Err(e) if e.raw_os_error() == Some(EBADF) => (),
+        Err(e) => return Err(e),
+    }
+    let path = b"/dev/null\0";
+    let null = check_err(unsafe { libc::open(path.as_ptr() as *const c_char, libc::O_RDWR) })?;
+    if null != fd {
+        let result = dup2(null, fd);
+        unsafe { libc::close(null) };
+        result?;
+    }
+    Ok(())
+}
+
+pub fn isatty(which: StandardStream) -> bool {
+    unsafe { libc::isatty(which as c_int) == 1 }
+}
+
 pub fn make_standard_stream(which: StandardStream) -> Result<Rc<File>> {
     let stream = Rc::new(unsafe { File::from_raw_fd(which as RawFd) });
     // Leak the Rc so the object we return doesn't close the underlying file
@@ -347,16 +860,37 @@ pub fn reset_sigpipe() -> Result<()> {
     Ok(())
 }
 
+pub fn block_signals(signals: &[i32]) -> Result<()> {
+    // Called after forking, so the signal set is kept on the stack.
+    unsafe {
+        let mut set: mem::MaybeUninit<libc::sigset_t> = mem::MaybeUninit::uninit();
+        check_err(libc::sigemptyset(set.as_mut_ptr()))?;
+        for &signal in signals {
+            check_err(libc::sigaddset(set.as_mut_ptr(), signal))?;
+        }
+        let set = set.assume_init();
+        check_err(libc::pthread_sigmask(
+            libc::SIG_BLOCK,
+            &set,
+            ptr::null_mut(),
+        ))?;
+    }
+    Ok(())
+}
+
 #[repr(C)]
 pub struct PollFd<'a>(libc::pollfd, PhantomData<&'a ()>);
 
 impl PollFd<'_> {
     pub fn new<'a>(file: Option<&'a File>, events: i16) -> PollFd<'a> {
-        PollFd(libc::pollfd {
-            fd: file.map(File::as_raw_fd).unwrap_or(-1),
-            events,
-            revents: 0,
-        }, PhantomData)
+        PollFd(
+            libc::pollfd {
+                fd: file.map(File::as_raw_fd).unwrap_or(-1),
+                events,
+                revents: 0,
+            },
+            PhantomData,
+        )
     }
 
     pub fn test(&self, mask: i16) -> bool {
@@ -364,7 +898,7 @@ impl PollFd<'_> {
     }
 }
 
-pub use libc::{POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLPRI};
+pub use libc::{POLLHUP, POLLIN, POLLOUT};
 
 pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usize> {
     let deadline = timeout.map(|timeout| Instant::now() + timeout);
@@ -375,10 +909,10 @@ pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usi
         let (timeout_ms, overflow) = timeout
             .map(|timeout| {
                 let timeout = timeout.as_millis();
-                if timeout <= i32::max_value() as u128 {
+                if timeout <= i32::MAX as u128 {
                     (timeout as i32, false)
                 } else {
-                    (i32::max_value(), true)
+                    (i32::MAX, true)
                 }
             })
             .unwrap_or((-1, false));
@@ -396,3 +930,31 @@ pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usi
         timeout = Some(deadline - now);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::split_path;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn s(s: &str) -> Vec<&str> {
+        split_path(OsStr::new(s))
+            .map(|osstr| std::str::from_utf8(osstr.as_bytes()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_path() {
+        let empty = Vec::<&OsStr>::new();
+
+        assert_eq!(s("a:b"), vec!["a", "b"]);
+        assert_eq!(s("one:twothree"), vec!["one", "twothree"]);
+        assert_eq!(s("a:"), vec!["a"]);
+        assert_eq!(s(""), empty);
+        assert_eq!(s(":"), empty);
+        assert_eq!(s("::"), empty);
+        assert_eq!(s(":::"), empty);
+        assert_eq!(s("a::b"), vec!["a", "b"]);
+        assert_eq!(s(":a::::b:"), vec!["a", "b"]);
+    }
+}
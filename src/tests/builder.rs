@@ -1,12 +1,14 @@
 use std::borrow::Cow;
 use std::env;
 use std::fs::File;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
 use std::sync::MutexGuard;
+use std::time::{Duration, Instant};
 
-use crate::{Exec, ExitStatus, NullFile, Redirection};
+use crate::{CaptureSink, Encoding, Exec, ExitStatus, MmapFile, NullFile, PopenError, Redirection};
 
 use lazy_static::lazy_static;
 use tempdir::TempDir;
@@ -19,6 +21,373 @@ fn exec_join() {
     assert_eq!(status, ExitStatus::Exited(0));
 }
 
+#[test]
+fn exec_capture_stdout_str_trimmed() {
+    let c = Exec::cmd("printf").arg("foo\n").capture().unwrap();
+    assert_eq!(c.stdout_str_trimmed(), "foo");
+    let c = Exec::cmd("printf").arg(" foo\r\n\n").capture().unwrap();
+    assert_eq!(c.stdout_str_trimmed(), " foo\r\n");
+    let c = Exec::cmd("printf").arg("foo").capture().unwrap();
+    assert_eq!(c.stdout_str_trimmed(), "foo");
+}
+
+#[test]
+fn exec_capture_lines() {
+    let lines = Exec::cmd("printf")
+        .arg("foo\nbar\n")
+        .capture_lines()
+        .unwrap();
+    assert_eq!(lines, vec!["foo", "bar"]);
+    let lines = Exec::cmd("printf")
+        .arg("foo\n\nbar")
+        .capture_lines_bytes()
+        .unwrap();
+    assert_eq!(lines, vec![b"foo".to_vec(), vec![], b"bar".to_vec()]);
+    assert!(Exec::cmd("true").capture_lines().unwrap().is_empty());
+}
+
+#[test]
+fn exec_path_program_and_args() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let tmpname = tmpdir.path().join("input");
+    File::create(&tmpname).unwrap().write_all(b"foo").unwrap();
+    let c = Exec::cmd(Path::new("cat")).arg(&tmpname).capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    let c = Exec::cmd(PathBuf::from("cat"))
+        .args(&[tmpname])
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+}
+
+#[test]
+fn exec_stderr_to_callback() {
+    let collected = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&collected);
+    let c = Exec::cmd("sh")
+        .args(&["-c", "echo out; echo err1 >&2; echo err2 >&2"])
+        .stdout(Redirection::Pipe)
+        .stderr_to_callback(move |data| sink.lock().unwrap().extend_from_slice(data))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "out\n");
+    assert!(c.stderr.is_empty());
+    assert_eq!(&*collected.lock().unwrap(), b"err1\nerr2\n");
+}
+
+#[test]
+fn exec_stderr_to_callback_poll() {
+    // the callback thread is joined by whichever call reaps the child
+    let collected = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&collected);
+    let mut p = Exec::cmd("sh")
+        .args(&["-c", "echo err >&2"])
+        .stderr_to_callback(move |data| sink.lock().unwrap().extend_from_slice(data))
+        .popen()
+        .unwrap();
+    while p.poll().is_none() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(&*collected.lock().unwrap(), b"err\n");
+
+    let collected = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&collected);
+    let mut p = Exec::cmd("sh")
+        .args(&["-c", "echo err >&2"])
+        .stderr_to_callback(move |data| sink.lock().unwrap().extend_from_slice(data))
+        .popen()
+        .unwrap();
+    assert!(p.wait_timeout(Duration::from_secs(5)).unwrap().is_some());
+    assert_eq!(&*collected.lock().unwrap(), b"err\n");
+}
+
+#[test]
+fn exec_stderr_to_callback_inherited() {
+    // a background process keeps the stderr pipe open after the child
+    // exits, which must not make poll() and wait_timeout() block
+    let collected = Arc::new(Mutex::new(vec![]));
+    let sink = Arc::clone(&collected);
+    let mut p = Exec::cmd("sh")
+        .args(&["-c", "echo err >&2; sleep 1 &"])
+        .stderr_to_callback(move |data| sink.lock().unwrap().extend_from_slice(data))
+        .popen()
+        .unwrap();
+    let start = Instant::now();
+    assert!(p
+        .wait_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+    assert!(p.poll().is_none());
+    assert!(start.elapsed() < Duration::from_millis(700));
+    assert!(p.wait().unwrap().success());
+    assert_eq!(&*collected.lock().unwrap(), b"err\n");
+}
+
+#[test]
+fn capture_decoded() {
+    let c = Exec::cmd("printf")
+        .arg(r"\377\376h\000i\000")
+        .stdout(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str_decoded(Encoding::Auto), "hi");
+    assert_eq!(c.stdout_str_decoded(Encoding::Utf16Le), "hi");
+    assert_eq!(Encoding::Auto.decode(b"\xef\xbb\xbfhi"), "hi");
+    assert_eq!(Encoding::Utf16Be.decode(b"\xfe\xff\0h\0i\0"), "hi\u{FFFD}");
+    assert_eq!(Encoding::Utf8.decode(b"\xff\xfe"), "\u{FFFD}\u{FFFD}");
+}
+
+#[test]
+fn exec_cwd() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let c = Exec::cmd("pwd").cwd(tmpdir.path()).capture().unwrap();
+    assert_eq!(
+        Path::new(c.stdout_str().trim_end()).canonicalize().unwrap(),
+        tmpdir.path().canonicalize().unwrap()
+    );
+
+    // chdir() failing in the child is reported by the parent
+    let err = Exec::cmd("true")
+        .cwd(tmpdir.path().join("nosuchdir"))
+        .join()
+        .unwrap_err();
+    match err {
+        PopenError::IoError(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOENT)),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn exec_stream_paths() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let out = tmpdir.path().join("out");
+    let err = tmpdir.path().join("err");
+    Exec::cmd("sh")
+        .args(&["-c", "echo foo; echo bar >&2"])
+        .stdout_path(&out)
+        .stderr_path(&err)
+        .join()
+        .unwrap();
+    assert_eq!(read_whole_file(File::open(&out).unwrap()), "foo\n");
+    assert_eq!(read_whole_file(File::open(&err).unwrap()), "bar\n");
+
+    let c = Exec::cmd("cat").stdin_path(&out).capture().unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+
+    // failing to open the file is reported when the process is started
+    let err = Exec::cmd("cat")
+        .stdin_path(tmpdir.path().join("nosuchfile"))
+        .capture()
+        .unwrap_err();
+    match err {
+        PopenError::IoError(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn exec_executable() {
+    let c = Exec::cmd("foobar")
+        .executable("sh")
+        .args(&["-c", r#"printf %s "$0""#])
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foobar");
+}
+
+#[test]
+fn exec_detached() {
+    let start = Instant::now();
+    let p = Exec::cmd("sleep").arg("5").detached().popen().unwrap();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn exec_retry() {
+    let tmpdir = TempDir::new("test").unwrap();
+    // fails on the first two attempts
+    let script =
+        r#"n=$(($(cat count 2>/dev/null || echo 0) + 1)); echo $n > count; echo $n; test $n -ge 3"#;
+    let c = Exec::cmd("sh")
+        .args(&["-c", script])
+        .cwd(tmpdir.path())
+        .retry(5, Duration::from_millis(1))
+        .capture()
+        .unwrap();
+    assert!(c.success());
+    assert_eq!(c.stdout_str(), "3\n");
+
+    let status = Exec::cmd("false")
+        .retry(1, Duration::from_millis(1))
+        .join()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn exec_retry_stdin_file() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let input = tmpdir.path().join("input");
+    std::fs::write(&input, "foo").unwrap();
+    match Exec::cmd("cat")
+        .stdin(File::open(&input).unwrap())
+        .retry(1, Duration::from_millis(1))
+        .join()
+    {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn exec_stdin_from_iter() {
+    let chunks = (0..1000).map(|i| vec![b'x'; i % 10 + 1]);
+    let c = Exec::cmd("wc")
+        .arg("-c")
+        .stdin_from_iter(chunks)
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str().trim(), "5500");
+    assert!(c.success());
+}
+
+#[test]
+fn exec_stdin_mmap() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let input = tmpdir.path().join("input");
+    let data: Vec<u8> = (0..3_000_007u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&input, &data).unwrap();
+    let c = Exec::cmd("cat")
+        .stdin(MmapFile(File::open(&input).unwrap()))
+        .capture()
+        .unwrap();
+    assert!(c.stdout == data);
+    assert!(c.success());
+}
+
+#[test]
+fn exec_stdin_mmap_empty() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let input = tmpdir.path().join("input");
+    File::create(&input).unwrap();
+    let c = Exec::cmd("wc")
+        .arg("-c")
+        .stdin(MmapFile(File::open(&input).unwrap()))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str().trim(), "0");
+}
+
+#[test]
+fn exec_stdin_mmap_unread() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let input = tmpdir.path().join("input");
+    std::fs::write(&input, vec![b'x'; 3_000_000]).unwrap();
+    let c = Exec::cmd("head")
+        .args(&["-c", "1"])
+        .stdin(MmapFile(File::open(&input).unwrap()))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "x");
+    assert!(c.success());
+}
+
+#[test]
+fn pipeline_stdin_mmap() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let input = tmpdir.path().join("input");
+    std::fs::write(&input, "foo\nbar\nfoo\n").unwrap();
+    let c = (Exec::cmd("grep").arg("foo") | Exec::cmd("wc").arg("-l"))
+        .stdin(MmapFile(File::open(&input).unwrap()))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str().trim(), "2");
+}
+
+#[test]
+fn exec_capture_with_process() {
+    let (c, p) = Exec::cmd("sh")
+        .args(&["-c", "echo foo; exit 3"])
+        .capture_with_process()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+    assert_eq!(c.exit_status, ExitStatus::Exited(3));
+    assert_eq!(p.exit_status(), Some(ExitStatus::Exited(3)));
+}
+
+#[test]
+fn pipeline_capture_with_processes() {
+    let (c, v) = (Exec::shell("echo foo; exit 2") | Exec::cmd("cat"))
+        .capture_with_processes()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+    assert!(c.success());
+    let statuses: Vec<_> = v.iter().map(|p| p.exit_status()).collect();
+    assert_eq!(
+        statuses,
+        vec![Some(ExitStatus::Exited(2)), Some(ExitStatus::Exited(0))]
+    );
+}
+
+#[test]
+fn exec_combine_output() {
+    let c = Exec::cmd("sh")
+        .args(&["-c", "echo 1; echo 2 >&2; echo 3; echo 4 >&2"])
+        .combine_output()
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "1\n2\n3\n4\n");
+    assert!(c.stderr.is_empty());
+}
+
+#[test]
+fn exec_stdout_into_shared() {
+    let shared = Arc::new(Mutex::new(vec![]));
+    let status = Exec::cmd("sh")
+        .args(&["-c", "echo foo; echo bar"])
+        .stdout_into_shared(Arc::clone(&shared))
+        .join()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(&*shared.lock().unwrap(), b"foo\nbar\n");
+}
+
+#[test]
+fn exec_args_via_file() {
+    let c = Exec::cmd("sh")
+        .args(&["-c", r#"echo "$1"; cat "${1#@}""#, "sh"])
+        .args_via_file(&["a b", "c\\d"])
+        .args_via_file(&[""])
+        .stdout(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    let out = c.stdout_str();
+    let (arg, contents) = out.split_at(out.find('\n').unwrap() + 1);
+    assert!(arg.starts_with('@'));
+    assert_eq!(contents, "a\\ b\nc\\\\d\n''\n");
+    // the response file is removed once the process has finished
+    assert!(!Path::new(arg[1..].trim_end()).exists());
+}
+
+#[test]
+fn exec_wrap_with_env() {
+    let exec = Exec::cmd("sh")
+        .args(&["-c", "echo $FOO"])
+        .wrap_with(&["env", "FOO=bar"]);
+    assert_eq!(exec.to_cmdline_lossy(), "env 'FOO=bar' sh -c 'echo $FOO'");
+    assert_eq!(exec.capture().unwrap().stdout_str(), "bar\n");
+}
+
+#[test]
+fn exec_wrap_with_nice() {
+    let niceness =
+        |exec: Exec| -> i32 { exec.capture().unwrap().stdout_str().trim().parse().unwrap() };
+    let base = niceness(Exec::cmd("nice"));
+    let wrapped = niceness(Exec::cmd("nice").wrap_with(&["nice", "-n", "10"]));
+    assert_eq!(wrapped, std::cmp::min(base + 10, 19));
+}
+
 #[test]
 fn null_file() {
     let mut p = Exec::cmd("cat")
@@ -147,6 +516,20 @@ fn pipeline_compose_pipelines() {
     assert_eq!(read_whole_file(stream).trim(), "2");
 }
 
+#[test]
+fn pipeline_compose_exec_and_pipeline() {
+    let pipe = Exec::cmd("echo").arg("foo\nbar\nfoo") | (Exec::cmd("sort") | Exec::cmd("uniq"));
+    let stream = pipe.stream_stdout().unwrap();
+    assert_eq!(read_whole_file(stream), "bar\nfoo\n");
+}
+
+#[test]
+fn pipeline_compose_pipeline_and_exec() {
+    let pipe = (Exec::cmd("echo").arg("foo\nbar\nfoo") | Exec::cmd("sort")) | Exec::cmd("uniq");
+    let stream = pipe.stream_stdout().unwrap();
+    assert_eq!(read_whole_file(stream), "bar\nfoo\n");
+}
+
 trait Crlf {
     fn to_crlf(self) -> Vec<u8>;
 }
@@ -197,6 +580,30 @@ fn pipeline_capture() {
     assert_eq!(c.stderr_str().trim(), "");
 }
 
+#[test]
+fn capture_invalid_utf8() {
+    let c = Exec::cmd("printf").arg(r"a\377b").capture().unwrap();
+    assert_eq!(c.stdout, b"a\xffb");
+    assert_eq!(c.stdout_str(), "a\u{FFFD}b");
+}
+
+#[test]
+fn capture_exit_status() {
+    let c = Exec::cmd("false").capture().unwrap();
+    assert!(!c.success());
+    assert_eq!(c.exit_status, ExitStatus::Exited(1));
+
+    // a pipeline reports the status of its last command
+    let c = { Exec::cmd("false") | Exec::cmd("true") }
+        .capture()
+        .unwrap();
+    assert!(c.success());
+    let c = { Exec::cmd("true") | Exec::shell("exit 3") }
+        .capture()
+        .unwrap();
+    assert_eq!(c.exit_status, ExitStatus::Exited(3));
+}
+
 #[test]
 fn pipeline_capture_error_1() {
     let c = {
@@ -232,6 +639,33 @@ fn pipeline_capture_error_2() {
     );
 }
 
+#[test]
+fn pipeline_capture_stderr_separately() {
+    let (c, errs) = {
+        Exec::cmd("cat")
+            | Exec::cmd("sh")
+                .arg("-c")
+                .arg("cat; echo foo >&2; printf 'four\nfive\n'")
+            | Exec::cmd("sh").arg("-c").arg("echo bar >&2; cat")
+            | Exec::shell("wc -l")
+    }
+    .stdin("one\ntwo\nthree\n")
+    .capture_stderr_separately()
+    .unwrap();
+    assert_eq!(c.stdout_str().trim(), "5");
+    assert!(c.success());
+    assert_eq!(
+        errs,
+        vec![
+            b"".to_vec(),
+            b"foo\n".to_vec(),
+            b"bar\n".to_vec(),
+            b"".to_vec()
+        ]
+    );
+    assert_eq!(c.stderr_str(), "foo\nbar\n");
+}
+
 #[test]
 fn pipeline_join() {
     let status = (Exec::cmd("true") | Exec::cmd("true")).join().unwrap();
@@ -244,6 +678,33 @@ fn pipeline_join() {
     assert_eq!(status, ExitStatus::Exited(1));
 }
 
+#[test]
+fn pipeline_join_all() {
+    let statuses = {
+        Exec::shell("printf 'foo\\n' | grep bar") | Exec::cmd("wc").arg("-l") | Exec::cmd("cat")
+    }
+    .join_all()
+    .unwrap();
+    assert_eq!(
+        statuses,
+        vec![
+            ExitStatus::Exited(1),
+            ExitStatus::Exited(0),
+            ExitStatus::Exited(0)
+        ]
+    );
+
+    // a stage that fails to start doesn't leave the earlier stages
+    // blocked on a pipe nobody reads
+    let err = (Exec::cmd("yes") | Exec::cmd("no-such-command") | Exec::cmd("wc"))
+        .join_all()
+        .unwrap_err();
+    match err {
+        PopenError::ExecError { ref program, .. } => assert_eq!(program, "no-such-command"),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
 #[test]
 fn pipeline_invalid_1() {
     let p = (Exec::cmd("echo").arg("foo") | Exec::cmd("no-such-command")).join();
@@ -306,6 +767,20 @@ fn env_extend() {
         .success());
 }
 
+#[test]
+fn env_clear() {
+    // run env directly rather than through sh, which supplies a
+    // default $PATH of its own when it finds none in the environment
+    let c = Exec::cmd("env").env_clear().capture().unwrap();
+    assert_eq!(c.stdout_str(), "");
+    let c = Exec::cmd("env")
+        .env_clear()
+        .env("VAR1", "foo")
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "VAR1=foo\n");
+}
+
 lazy_static! {
     static ref MUTATE_ENV: Mutex<()> = Mutex::new(());
 }
@@ -361,6 +836,51 @@ fn env_inherit_set() {
         .success());
 }
 
+#[test]
+fn env_remove() {
+    let varname = "TEST_ENV_REMOVE_VARNAME";
+    let _guard = tmp_env_var(varname, "inherited");
+    let check = format!(r#"test -z "${}" && test -n "$PATH""#, varname);
+    assert!(Exec::cmd("sh")
+        .args(&["-c", &check])
+        .env_remove(varname)
+        .join()
+        .unwrap()
+        .success());
+    // removing again, or removing a variable that isn't set, is a no-op
+    assert!(Exec::cmd("sh")
+        .args(&["-c", &check])
+        .env_remove(varname)
+        .env_remove(varname)
+        .env_remove("TEST_ENV_REMOVE_UNSET")
+        .join()
+        .unwrap()
+        .success());
+    // variable names are case-sensitive on Unix
+    assert!(Exec::cmd("sh")
+        .args(&["-c", &format!(r#"test "${}" = "inherited""#, varname)])
+        .env_remove(varname.to_lowercase())
+        .join()
+        .unwrap()
+        .success());
+}
+
+#[test]
+fn env_filter() {
+    let varname = "TEST_ENV_FILTER_SECRET";
+    let _guard = tmp_env_var(varname, "secret");
+    assert!(Exec::cmd("sh")
+        .args(&[
+            "-c",
+            r#"test -z "$TEST_ENV_FILTER_SECRET" && test "$TEST_ENV_FILTER_SET" = set"#,
+        ])
+        .env_filter(|name, _| !name.to_string_lossy().starts_with("TEST_ENV_FILTER_"))
+        .env("TEST_ENV_FILTER_SET", "set")
+        .join()
+        .unwrap()
+        .success());
+}
+
 // XXX move tests under the builder module so we can call
 // Exec::display_escape() instead of copying it.
 fn display_escape(s: &str) -> Cow<'_, str> {
@@ -423,3 +943,218 @@ fn pipeline_to_string() {
         "Pipeline { 'command with space' arg | wc -l }"
     )
 }
+
+#[test]
+fn pipeline_to_cmdline_lossy() {
+    let pipeline = Exec::cmd("git").arg("commit message") | Exec::cmd("wc").arg("-l");
+    assert_eq!(pipeline.to_cmdline_lossy(), "git 'commit message' | wc -l");
+}
+
+fn assert_timed_out(err: PopenError) {
+    match err {
+        PopenError::IoError(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn exec_timeout_join() {
+    let start = Instant::now();
+    let err = Exec::cmd("sleep")
+        .arg("5")
+        .timeout(Duration::from_millis(100))
+        .join()
+        .unwrap_err();
+    assert_timed_out(err);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn exec_timeout_capture() {
+    let start = Instant::now();
+    let err = Exec::cmd("sh")
+        .args(&["-c", "echo foo; sleep 5"])
+        .timeout(Duration::from_millis(100))
+        .capture()
+        .unwrap_err();
+    assert_timed_out(err);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn exec_timeout_not_reached() {
+    let c = Exec::cmd("printf")
+        .arg("foo")
+        .timeout(Duration::from_secs(5))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    assert!(c.success());
+}
+
+#[test]
+fn exec_timeout_communicate_override() {
+    let mut comm = Exec::cmd("sleep")
+        .arg("5")
+        .timeout(Duration::from_secs(60))
+        .communicate()
+        .unwrap()
+        .limit_time(Duration::from_millis(100));
+    let start = Instant::now();
+    assert_eq!(comm.read().unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn exec_fingerprint() {
+    let base = || Exec::cmd("cc").args(&["-c", "foo.c"]).cwd("/src");
+    assert_eq!(base().fingerprint(), base().fingerprint());
+    assert_ne!(
+        base().fingerprint(),
+        Exec::cmd("cc")
+            .args(&["foo.c", "-c"])
+            .cwd("/src")
+            .fingerprint()
+    );
+    assert_ne!(base().fingerprint(), base().cwd("/other").fingerprint());
+    assert_ne!(
+        Exec::cmd("ab").arg("c").fingerprint(),
+        Exec::cmd("a").arg("bc").fingerprint()
+    );
+    let env1 = base().env_clear().env("A", "1").env("B", "2");
+    let env2 = base().env_clear().env("B", "2").env("A", "1");
+    assert_eq!(env1.fingerprint(), env2.fingerprint());
+    assert_ne!(
+        env1.fingerprint(),
+        base().env_clear().env("A", "1").fingerprint()
+    );
+    // only changes to an inherited environment count
+    assert_ne!(base().fingerprint(), base().env("A", "1").fingerprint());
+    assert_eq!(
+        base().fingerprint(),
+        base()
+            .env("SUBPROCESS_FINGERPRINT_TEST", "1")
+            .env_remove("SUBPROCESS_FINGERPRINT_TEST")
+            .fingerprint()
+    );
+    assert_ne!(
+        base().env("A", "1").fingerprint(),
+        base().env_clear().env("A", "1").fingerprint()
+    );
+}
+
+#[test]
+fn capture_spooled() {
+    let c = Exec::cmd("printf")
+        .arg("%s")
+        .arg("x".repeat(10_000))
+        .capture_spooled(1000)
+        .unwrap();
+    assert!(c.exit_status.success());
+    assert_eq!(c.stdout.len(), 10_000);
+    assert!(c.stdout.is_spilled());
+    assert!(!c.stderr.is_spilled());
+    let mut data = String::new();
+    c.stdout
+        .into_reader()
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "x".repeat(10_000));
+}
+
+#[test]
+fn capture_sink_small() {
+    let mut sink = CaptureSink::new(10);
+    sink.write_all(b"foo").unwrap();
+    sink.write_all(b"bar").unwrap();
+    assert!(!sink.is_spilled());
+    let mut data = vec![];
+    sink.into_reader().unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"foobar");
+}
+
+#[test]
+fn pipeline_split() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let out1 = tmpdir.path().join("out1");
+    let out2 = tmpdir.path().join("out2");
+    let (procs, copier) = (Exec::cmd("printf").arg("foo\\nbar\\n") | Exec::cmd("cat"))
+        .split(vec![
+            Exec::cmd("cat").stdout(File::create(&out1).unwrap()),
+            Exec::cmd("wc")
+                .arg("-l")
+                .stdout(File::create(&out2).unwrap()),
+        ])
+        .unwrap();
+    assert_eq!(procs.len(), 4);
+    for mut p in procs {
+        assert!(p.wait().unwrap().success());
+    }
+    copier.join().unwrap().unwrap();
+    assert_eq!(read_whole_file(File::open(&out1).unwrap()), "foo\nbar\n");
+    assert_eq!(read_whole_file(File::open(&out2).unwrap()).trim(), "2");
+}
+
+#[test]
+fn pipeline_split_consumer_exits_early() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let out = tmpdir.path().join("out");
+    let (procs, copier) = (Exec::cmd("head").arg("-c").arg("1000000") | Exec::cmd("cat"))
+        .stdin(File::open("/dev/zero").unwrap())
+        .split(vec![
+            Exec::cmd("head").arg("-c").arg("1").stdout(NullFile),
+            Exec::cmd("wc")
+                .arg("-c")
+                .stdout(File::create(&out).unwrap()),
+        ])
+        .unwrap();
+    for mut p in procs {
+        assert!(p.wait().unwrap().success());
+    }
+    copier.join().unwrap().unwrap();
+    assert_eq!(read_whole_file(File::open(&out).unwrap()).trim(), "1000000");
+}
+
+#[test]
+fn capture_unread_input() {
+    let c = Exec::shell("echo done")
+        .stdin(vec![b'x'; 1_000_000])
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "done\n");
+    assert!(c.exit_status.success());
+}
+
+#[test]
+fn exec_plan() {
+    let plan = Exec::cmd("sh")
+        .args(&["-c", "true"])
+        .env("SUBPROCESS_PLAN_TEST", "1")
+        .cwd("/")
+        .plan();
+    assert_eq!(plan.program, "sh");
+    let resolved = plan.resolved.clone().unwrap();
+    assert!(resolved.is_absolute() && resolved.ends_with("sh"));
+    assert_eq!(plan.argv, ["sh", "-c", "true"]);
+    assert!(plan
+        .env
+        .contains(&("SUBPROCESS_PLAN_TEST".into(), "1".into())));
+    assert_eq!(plan.cwd.as_deref(), Some("/".as_ref()));
+    let shown = plan.to_string();
+    assert!(shown.contains("argv: sh -c true\n"));
+    assert!(shown.contains("env: SUBPROCESS_PLAN_TEST=1\n"));
+    assert!(shown.contains("cwd: /\n"));
+}
+
+#[test]
+fn exec_plan_not_found() {
+    let plan = Exec::cmd("nosuchcommand").plan();
+    assert!(plan.resolved.is_none());
+    assert!(plan
+        .to_string()
+        .starts_with("program: nosuchcommand (not found)\n"));
+    let plans = (Exec::cmd("echo") | Exec::cmd("nosuchcommand")).plan();
+    assert_eq!(plans.len(), 2);
+    assert!(plans[0].resolved.is_some());
+}
@@ -4,9 +4,11 @@ use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::Write;
 use std::io::{self, Read};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::{ExitStatus, Popen, PopenConfig, PopenError, Redirection};
+use crate::{
+    ExecErrorKind, ExitStatus, Popen, PopenConfig, PopenError, Redirection, TerminateOnDrop,
+};
 
 pub fn read_whole_file<T: Read>(mut f: T) -> String {
     let mut content = String::new();
@@ -26,12 +28,26 @@ fn bad_cmd() {
     assert!(result.is_err());
 }
 
+#[test]
+fn bad_cmd_not_found() {
+    match Popen::create(&["nosuchcommand"], PopenConfig::default()) {
+        Err(PopenError::ExecError { kind, .. }) => assert_eq!(kind, ExecErrorKind::NotFound),
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+}
+
+#[test]
+fn exec_error_names_program() {
+    let err = Popen::create(&["nosuchcommand"], PopenConfig::default()).unwrap_err();
+    assert!(err.to_string().starts_with("nosuchcommand: "));
+}
+
 #[test]
 fn reject_empty_argv() {
     let test = Popen::create(&[""; 0], PopenConfig::default());
     if let Err(PopenError::LogicError(..)) = test {
     } else {
-        assert!(false, "didn't get LogicError for empty argv");
+        panic!("didn't get LogicError for empty argv");
     }
 }
 
@@ -154,6 +170,68 @@ fn write_to_subprocess() {
     assert_eq!(read_whole_file(File::open(tmpname).unwrap()), "foo\nbar\n");
 }
 
+#[test]
+fn close_stdin_half_close() {
+    let mut p = Popen::create(
+        &["sort"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.stdin.as_ref().unwrap().write_all(b"b\na\n").unwrap();
+    p.close_stdin();
+    assert!(p.stdin.is_none());
+    // closing again is a no-op
+    p.close_stdin();
+    assert_eq!(read_whole_file(p.stdout.as_ref().unwrap()), "a\nb\n");
+    p.close_stdout();
+    assert!(p.stdout.is_none());
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn feed_stdin_background() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let input = vec![b'x'; 1_000_000];
+    let feeder = p.feed_stdin_background(input.clone());
+    assert!(p.stdin.is_none());
+    let mut output = vec![];
+    p.stdout.as_ref().unwrap().read_to_end(&mut output).unwrap();
+    feeder.join().unwrap().unwrap();
+    assert!(output == input);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn has_pipes() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.has_stdin_pipe());
+    assert!(p.has_stdout_pipe());
+    assert!(!p.has_stderr_pipe());
+    p.close_stdin();
+    assert!(!p.has_stdin_pipe());
+    p.wait().unwrap();
+}
+
 #[test]
 fn communicate_input() {
     let tmpdir = TempDir::new("test").unwrap();
@@ -169,7 +247,7 @@ fn communicate_input() {
     .unwrap();
     if let (None, None) = p.communicate_bytes(Some(b"hello world")).unwrap() {
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
     assert_eq!(
@@ -193,7 +271,7 @@ fn communicate_output() {
         assert_eq!(out, b"foo\n");
         assert_eq!(err, b"bar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -214,11 +292,83 @@ fn communicate_input_output() {
         assert_eq!(out, b"hello world");
         assert_eq!(err, b"foo\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
 
+#[test]
+fn stdout_channel() {
+    let mut p = Popen::create(
+        &["sh", "-c", "echo foo; echo bar"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let rx = p.stdout_channel(1);
+    assert!(p.stdout.is_none());
+    let out: Vec<u8> = rx.iter().flatten().collect();
+    assert_eq!(out, b"foo\nbar\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn stdout_channel_backpressure() {
+    // with the receiver not drained, the child blocks instead of
+    // finishing
+    let mut p = Popen::create(
+        &["sh", "-c", "printf '%1000000s' ''"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let rx = p.stdout_channel(1);
+    assert!(p
+        .wait_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+    let total: usize = rx.iter().map(|chunk| chunk.len()).sum();
+    assert_eq!(total, 1_000_000);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn communicate_input_written() {
+    // the child reads only part of its input and exits
+    let mut p = Popen::create(
+        &["sh", "-c", "head -c 5 > /dev/null"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let input = vec![65u8; 1_000_000];
+    let mut comm = p.communicate_start(Some(input));
+    assert_eq!(comm.input_written(), 0);
+    assert_eq!(comm.read().unwrap(), (None, None));
+    let written = comm.input_written();
+    assert!((5..1_000_000).contains(&written));
+    assert!(p.wait().unwrap().success());
+
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p.communicate_start(Some(b"hello".to_vec()));
+    comm.read().unwrap();
+    assert_eq!(comm.input_written(), 5);
+}
+
 #[test]
 fn communicate_input_output_long() {
     let mut p = Popen::create(
@@ -236,7 +386,7 @@ fn communicate_input_output_long() {
         assert_eq!(&out[..], &input[..]);
         assert_eq!(&err[..], &[32u8; 100_000][..]);
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -266,6 +416,91 @@ fn communicate_timeout() {
     p.kill().unwrap();
 }
 
+#[test]
+fn communicate_invalid_utf8() {
+    let mut p = Popen::create(
+        &["printf", r"a\377b"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out, _err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "a\u{FFFD}b");
+    p.wait().unwrap();
+}
+
+#[test]
+fn communicate_timeout_retry() {
+    let mut p = Popen::create(
+        &["sh", "-c", "printf foo; sleep 0.3; printf bar"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p
+        .communicate_start(None)
+        .limit_time(Duration::from_millis(100));
+    let err = comm.read().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(err.capture, (Some(b"foo".to_vec()), None));
+    // after a timeout, reading can resume where it left off
+    let mut comm = comm.limit_time(Duration::from_secs(5));
+    assert_eq!(comm.read().unwrap(), (Some(b"bar".to_vec()), None));
+    p.wait().unwrap();
+}
+
+#[test]
+fn communicate_track_eof() {
+    let mut p = Popen::create(
+        &["sh", "-c", "exec 1>&-; sleep 0.2; echo err >&2"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p.communicate_start(None).track_eof();
+    assert_eq!(comm.eof_times(), (None, None));
+    let (out, err) = comm.read().unwrap();
+    assert_eq!(out.unwrap(), b"");
+    assert_eq!(err.unwrap(), b"err\n");
+    let (out_eof, err_eof) = comm.eof_times();
+    assert!(out_eof.unwrap() < err_eof.unwrap());
+    p.wait().unwrap();
+}
+
+#[test]
+fn communicate_deadline() {
+    let mut p = Popen::create(
+        &["sh", "-c", "while true; do echo x; sleep 0.05; done"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let start = Instant::now();
+    let mut comm = p
+        .communicate_start(None)
+        .limit_size(1)
+        .deadline(start + Duration::from_millis(300));
+    let err = loop {
+        // each read finishes quickly, but the total is bounded
+        if let Err(e) = comm.read() {
+            break e;
+        }
+    };
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // the process is killed once the deadline passes
+    assert!(p.wait_timeout(Duration::from_secs(2)).unwrap().is_some());
+}
+
 #[test]
 fn communicate_size_limit_small() {
     let mut p = Popen::create(
@@ -280,7 +515,7 @@ fn communicate_size_limit_small() {
     let mut comm = p.communicate_start(None).limit_size(2);
     assert_eq!(comm.read().unwrap(), (Some(vec![32; 2]), Some(vec![])));
     assert_eq!(comm.read().unwrap(), (Some(vec![32; 2]), Some(vec![])));
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     p.kill().unwrap();
 }
 
@@ -310,7 +545,7 @@ fn communicate_size_limit_large() {
     check_vec(out, 10_000, 32);
     assert_eq!(err, Some(vec![]));
 
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     p.kill().unwrap();
 }
 
@@ -347,7 +582,7 @@ fn communicate_size_limit_different_sizes() {
     check_vec(out, 8_900, 32);
     assert_eq!(err, Some(vec![]));
 
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     assert_eq!(comm.read().unwrap(), (Some(vec![]), Some(vec![])));
     p.kill().unwrap();
 }
@@ -372,11 +607,30 @@ fn merge_err_to_out_pipe() {
     if let (Some(out), None) = p.communicate_bytes(None).unwrap() {
         assert_eq!(out, b"foo\nbar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
 
+#[test]
+fn merge_err_to_out_pipe_reaches_eof() {
+    // The parent must not hold on to a write end of the merged pipe,
+    // or read_to_end() would never return.
+    let mut p = Popen::create(
+        &["sh", "-c", "echo foo; echo bar >&2"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Merge,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut out = vec![];
+    p.stdout.take().unwrap().read_to_end(&mut out).unwrap();
+    assert_eq!(out, b"foo\nbar\n");
+    assert!(p.wait().unwrap().success());
+}
+
 #[test]
 fn merge_out_to_err_pipe() {
     let mut p = Popen::create(
@@ -391,7 +645,7 @@ fn merge_out_to_err_pipe() {
     if let (None, Some(err)) = p.communicate_bytes(None).unwrap() {
         assert_eq!(err, b"foo\nbar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -413,6 +667,41 @@ fn merge_err_to_out_file() {
     assert_eq!(read_whole_file(File::open(&tmpname).unwrap()), "foobar");
 }
 
+#[test]
+fn merge_out_to_err_file_interleaved() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let tmpname = tmpdir.path().join("output");
+    let mut p = Popen::create(
+        &["sh", "-c", "printf 1; printf 2 >&2; printf 3; printf 4 >&2"],
+        PopenConfig {
+            stdout: Redirection::Merge,
+            stderr: Redirection::File(File::create(&tmpname).unwrap()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.wait().unwrap().success());
+    // both descriptors share the file offset, so nothing is overwritten
+    assert_eq!(read_whole_file(File::open(&tmpname).unwrap()), "1234");
+}
+
+#[test]
+fn merge_err_to_out_file_interleaved() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let tmpname = tmpdir.path().join("output");
+    let mut p = Popen::create(
+        &["sh", "-c", "printf 1; printf 2 >&2; printf 3; printf 4 >&2"],
+        PopenConfig {
+            stdout: Redirection::File(File::create(&tmpname).unwrap()),
+            stderr: Redirection::Merge,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.wait().unwrap().success());
+    assert_eq!(read_whole_file(File::open(&tmpname).unwrap()), "1234");
+}
+
 #[test]
 fn simple_pipe() {
     let mut c1 = Popen::create(
@@ -445,6 +734,73 @@ fn wait_timeout() {
     assert_eq!(ret, Some(ExitStatus::Exited(0)));
 }
 
+#[cfg(feature = "serde")]
+#[test]
+fn exit_status_serde() {
+    for &(status, json) in &[
+        (ExitStatus::Exited(0), r#"{"Exited":0}"#),
+        (ExitStatus::Signaled(9), r#"{"Signaled":9}"#),
+        (ExitStatus::Other(-1), r#"{"Other":-1}"#),
+        (ExitStatus::Undetermined, r#""Undetermined""#),
+    ] {
+        assert_eq!(serde_json::to_string(&status).unwrap(), json);
+        assert_eq!(serde_json::from_str::<ExitStatus>(json).unwrap(), status);
+    }
+}
+
+#[test]
+fn wait_timeout_short() {
+    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    assert_eq!(p.wait_timeout(Duration::from_millis(1)).unwrap(), None);
+    assert_eq!(p.wait_timeout(Duration::from_nanos(1)).unwrap(), None);
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn detached_drop() {
+    let p = Popen::create(
+        &["sleep", "5"],
+        PopenConfig {
+            detached: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let start = Instant::now();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn terminate_on_drop() {
+    let p = Popen::create(
+        &["sleep", "5"],
+        PopenConfig {
+            terminate_on_drop: Some(TerminateOnDrop::new(Duration::from_secs(5))),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let start = Instant::now();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn create_with_stdio() {
+    let mut p = Popen::create_with_stdio(
+        &["sh", "-c", "cat; echo err >&2"],
+        [Redirection::Pipe, Redirection::Pipe, Redirection::Merge],
+        PopenConfig::default(),
+    )
+    .unwrap();
+    let (out, err) = p.communicate(Some("foo\n")).unwrap();
+    assert_eq!(out.unwrap(), "foo\nerr\n");
+    assert!(err.is_none());
+    assert!(p.wait().unwrap().success());
+}
+
 #[test]
 fn setup_executable() {
     let mut p = Popen::create(
@@ -532,3 +888,220 @@ fn failed_cwd() {
     };
     assert_eq!(err_num, libc::ENOENT);
 }
+
+#[test]
+fn popen_read_write() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.write_all(b"foo\n").unwrap();
+    p.flush().unwrap();
+    p.stdin = None;
+    let mut out = String::new();
+    p.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "foo\n");
+    assert!(p.wait().unwrap().success());
+
+    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    let err = p.read(&mut [0u8; 10]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    let err = p.write(b"foo").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    p.wait().unwrap();
+}
+
+#[test]
+fn from_std() {
+    use std::process::{Command, Stdio};
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg("cat; exit 3")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut p = Popen::from_std(child);
+    assert!(p.pid().is_some());
+    assert!(p.stderr.is_none());
+    let (out, _err) = p.communicate(Some("foo")).unwrap();
+    assert_eq!(out.unwrap(), "foo");
+    assert_eq!(
+        p.wait_timeout(Duration::from_secs(5)).unwrap(),
+        Some(ExitStatus::Exited(3))
+    );
+}
+
+#[test]
+fn split_io() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (stdin, stdout, stderr) = p.split_io();
+    assert!(stderr.is_none());
+    let (mut stdin, mut stdout) = (stdin.unwrap(), stdout.unwrap());
+    let reader = std::thread::spawn(move || read_whole_file(&mut stdout));
+    stdin.write_all(b"foo\n").unwrap();
+    stdin.write_all(b"bar\n").unwrap();
+    // closing stdin lets cat finish
+    drop(stdin);
+    assert_eq!(reader.join().unwrap(), "foo\nbar\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn spawn_metrics() {
+    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    let metrics = p.spawn_metrics().unwrap();
+    assert!(metrics.exec >= metrics.spawn);
+    p.wait().unwrap();
+}
+
+#[test]
+fn early_exit_check() {
+    let config = || PopenConfig {
+        early_exit_check: Some(Duration::from_secs(5)),
+        ..Default::default()
+    };
+    match Popen::create(&["false"], config()) {
+        Err(PopenError::EarlyExit(status)) => assert_eq!(status, ExitStatus::Exited(1)),
+        other => panic!("unexpected result {:?}", other),
+    }
+    // a successful exit is not an error
+    let mut p = Popen::create(&["true"], config()).unwrap();
+    assert!(p.poll().unwrap().success());
+    // nor is a process that keeps running
+    let start = Instant::now();
+    let mut p = Popen::create(
+        &["sleep", "5"],
+        PopenConfig {
+            early_exit_check: Some(Duration::from_millis(100)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(p.poll().is_none());
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn resolved_path() {
+    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    let path = p.resolved_path().unwrap().to_owned();
+    assert!(path.is_absolute());
+    assert_eq!(path.file_stem().unwrap(), "true");
+    p.wait().unwrap();
+
+    let mut p = Popen::create(&[&path], PopenConfig::default()).unwrap();
+    assert_eq!(p.resolved_path(), Some(&*path));
+    p.wait().unwrap();
+}
+
+#[test]
+fn liveness_token() {
+    let mut p = Popen::create(&["sleep", "0.2"], PopenConfig::default()).unwrap();
+    let token = p.liveness_token();
+    assert!(token.is_alive());
+    let checker = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            while token.is_alive() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        })
+    };
+    checker.join().unwrap();
+    // the checker doesn't reap the process
+    assert!(p.wait().unwrap().success());
+    assert!(!token.is_alive());
+    assert!(!p.liveness_token().is_alive());
+}
+
+#[test]
+fn read_stdout_timeout() {
+    let mut p = Popen::create(
+        &["sh", "-c", "printf foo; sleep 1; printf bar"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut buf = [0u8; 10];
+    let n = p
+        .read_stdout_timeout(&mut buf, Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(&buf[..n.unwrap()], b"foo");
+    let n = p
+        .read_stdout_timeout(&mut buf, Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(n, None);
+    // the pipe is left in blocking mode
+    let mut rest = String::new();
+    p.stdout
+        .as_ref()
+        .unwrap()
+        .read_to_string(&mut rest)
+        .unwrap();
+    assert_eq!(rest, "bar");
+    p.wait().unwrap();
+}
+
+#[test]
+fn wait_for_output() {
+    let mut p = Popen::create(
+        &["sh", "-c", "printf star; sleep 0.1; echo ting; echo ready"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p
+        .wait_for_output(b"starting", Duration::from_secs(5))
+        .unwrap());
+    // the output read while waiting is still returned
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "starting\nready\n");
+
+    let mut p = Popen::create(
+        &["echo", "failed"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(!p.wait_for_output(b"ready", Duration::from_secs(5)).unwrap());
+}
+
+#[test]
+fn wait_for_output_timeout() {
+    let mut p = Popen::create(
+        &["sleep", "5"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let err = p
+        .wait_for_output(b"ready", Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    p.kill().unwrap();
+}
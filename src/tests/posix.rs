@@ -1,9 +1,20 @@
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::unix::PopenExt;
-use crate::{ExitStatus, Popen, PopenConfig, Redirection};
+use crate::unix::{ExecExt, PopenExt};
+use crate::{
+    CaptureData, Exec, ExecErrorKind, ExitStatus, Popen, PopenConfig, PopenError, Redirection,
+    TerminateOnDrop,
+};
 
-use libc;
+use lazy_static::lazy_static;
+use tempdir::TempDir;
 
 #[test]
 fn err_terminate() {
@@ -60,3 +71,1128 @@ fn env_set_all_2() {
     let (out, _err) = p.communicate(None).unwrap();
     assert_eq!(out.unwrap().trim_end(), "FOO=bar");
 }
+
+#[test]
+fn wait_with_usage() {
+    let mut p = Popen::create(&["sh", "-c", "exit 3"], PopenConfig::default()).unwrap();
+    let (status, usage) = p.wait_with_usage().unwrap();
+    assert_eq!(status, ExitStatus::Exited(3));
+    assert!(usage.max_rss > 0);
+    assert_eq!(p.poll(), Some(ExitStatus::Exited(3)));
+    assert!(p.wait_with_usage().is_err());
+}
+
+#[test]
+fn raw_wait_status() {
+    let mut p = Popen::create(&["sh", "-c", "exit 3"], PopenConfig::default()).unwrap();
+    assert_eq!(p.raw_wait_status(), None);
+    p.wait().unwrap();
+    let raw = p.raw_wait_status().unwrap();
+    assert!(libc::WIFEXITED(raw));
+    assert_eq!(libc::WEXITSTATUS(raw), 3);
+
+    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    p.kill().unwrap();
+    p.wait().unwrap();
+    let raw = p.raw_wait_status().unwrap();
+    assert!(libc::WIFSIGNALED(raw));
+    assert_eq!(libc::WTERMSIG(raw), libc::SIGKILL);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cpu_affinity() {
+    let mut p = Popen::create(
+        &["grep", "Cpus_allowed_list", "/proc/self/status"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            cpu_affinity: Some(vec![0]),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out, _err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap().split_whitespace().nth(1), Some("0"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cpu_affinity_invalid() {
+    let ret = Popen::create(
+        &["true"],
+        PopenConfig {
+            cpu_affinity: Some(vec![1 << 20]),
+            ..Default::default()
+        },
+    );
+    match ret {
+        Err(PopenError::IoError(e)) => assert_eq!(e.raw_os_error(), Some(libc::EINVAL)),
+        _ => panic!("expected error return"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn oom_score_adj() {
+    let mut p = Popen::create(
+        &["cat", "/proc/self/oom_score_adj"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            // raising the score requires no privilege
+            oom_score_adj: Some(1000),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out, _err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "1000\n");
+
+    let ret = Popen::create(
+        &["true"],
+        PopenConfig {
+            oom_score_adj: Some(-1001),
+            ..Default::default()
+        },
+    );
+    match ret {
+        Err(PopenError::IoError(e)) => assert_eq!(e.raw_os_error(), Some(libc::EINVAL)),
+        _ => panic!("expected error return"),
+    }
+}
+
+#[test]
+fn terminate_on_drop_kills_after_grace() {
+    // The shell ignores SIGTERM, so it has to be killed once the grace
+    // period expires.
+    let p = Popen::create(
+        &["sh", "-c", "trap '' TERM; sleep 5"],
+        PopenConfig {
+            terminate_on_drop: Some(TerminateOnDrop::new(Duration::from_millis(100))),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let start = Instant::now();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn terminate_on_drop_signal() {
+    // SIGTERM is ignored, so only the configured signal ends the
+    // process before the grace period expires.
+    let p = Popen::create(
+        &[
+            "sh",
+            "-c",
+            "trap '' TERM; trap 'exit 1' INT; sleep 5 & wait",
+        ],
+        PopenConfig {
+            terminate_on_drop: Some(TerminateOnDrop {
+                signal: libc::SIGINT,
+                grace: Duration::from_secs(10),
+            }),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let start = Instant::now();
+    drop(p);
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn suspend_resume() {
+    let mut p = Popen::create(&["sleep", "0.2"], PopenConfig::default()).unwrap();
+    p.suspend().unwrap();
+    // a stopped child is not reported as finished
+    assert_eq!(p.wait_timeout(Duration::from_millis(500)).unwrap(), None);
+    p.resume().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+}
+
+#[test]
+fn exec_error_errno() {
+    // the errno is passed from the child through the exec fail pipe
+    match Popen::create(&["/nonexistent/program"], PopenConfig::default()) {
+        Err(PopenError::ExecError { kind, error, .. }) => {
+            assert_eq!(kind, ExecErrorKind::NotFound);
+            assert_eq!(error.raw_os_error(), Some(libc::ENOENT));
+        }
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+}
+
+#[test]
+fn exec_error_permission_denied() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let script = tmpdir.path().join("script");
+    fs::write(&script, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+    match Popen::create(&[&script], PopenConfig::default()) {
+        Err(PopenError::ExecError { kind, .. }) => {
+            assert_eq!(kind, ExecErrorKind::PermissionDenied)
+        }
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+}
+
+#[test]
+fn exec_error_is_directory() {
+    let tmpdir = TempDir::new("test").unwrap();
+    match Popen::create(&[tmpdir.path()], PopenConfig::default()) {
+        Err(err @ PopenError::ExecError { .. }) => {
+            assert_eq!(
+                err.to_string(),
+                format!("{}: is a directory", tmpdir.path().display())
+            );
+            if let PopenError::ExecError { kind, .. } = err {
+                assert_eq!(kind, ExecErrorKind::IsDirectory);
+            }
+        }
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+}
+
+#[test]
+fn communicate_interleaved_streams() {
+    // stdout and stderr are written while stdin is still being read,
+    // so all three pipes must be serviced together
+    let mut p = Popen::create(
+        &["tee", "/dev/stderr"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let input: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+    let (out, err) = p.communicate_bytes(Some(&input)).unwrap();
+    assert!(out.unwrap() == input);
+    assert!(err.unwrap() == input);
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn feed_stdin_background_from_infinite_source() {
+    let mut p = Popen::create(
+        &["head", "-c", "1000000"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let feeder = p.feed_stdin_background_from(io::repeat(b'x'));
+    let mut output = vec![];
+    p.stdout.as_ref().unwrap().read_to_end(&mut output).unwrap();
+    assert_eq!(output.len(), 1_000_000);
+    assert!(p.wait().unwrap().success());
+    let err = feeder.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn exec_non_utf8_program_path() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let script = tmpdir.path().join(OsStr::from_bytes(b"script-\xff"));
+    fs::write(&script, "#!/bin/sh\necho ok\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let exec = Exec::cmd(&script);
+    assert!(format!("{:?}", exec).contains("script-\u{FFFD}"));
+    assert_eq!(exec.capture().unwrap().stdout_str(), "ok\n");
+}
+
+#[test]
+fn max_poll_interval() {
+    let mut p = Popen::create(
+        &["sleep", "0.3"],
+        PopenConfig {
+            max_poll_interval: Duration::from_millis(10),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let start = Instant::now();
+    let status = p.wait_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, Some(ExitStatus::Exited(0)));
+    // generous bound to tolerate loaded machines
+    assert!(start.elapsed() < Duration::from_millis(2 * 300));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn wait_timeout_prompt() {
+    let mut p = Popen::create(&["sleep", "0.3"], PopenConfig::default()).unwrap();
+    let start = Instant::now();
+    let status = p.wait_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, Some(ExitStatus::Exited(0)));
+    // returns once the process exits, well before the timeout
+    assert!(start.elapsed() < Duration::from_millis(2 * 300));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn block_signals() {
+    let mut p = Popen::create(
+        &["grep", "SigBlk", "/proc/self/status"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            block_signals: Some(vec![libc::SIGUSR1]),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out, _err) = p.communicate(None).unwrap();
+    let out = out.unwrap();
+    let mask = u64::from_str_radix(out.split_whitespace().nth(1).unwrap(), 16).unwrap();
+    assert_eq!(mask, 1 << (libc::SIGUSR1 - 1));
+}
+
+#[test]
+fn extra_fd_pipe() {
+    let mut p = Popen::create(
+        &["sh", "-c", "read x <&3; echo got $x >&3"],
+        PopenConfig {
+            extra_fds: vec![(3, Redirection::Pipe)],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let sock = p.extra_fd(3).unwrap();
+    sock.write_all(b"foo\n").unwrap();
+    let mut reply = String::new();
+    sock.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, "got foo\n");
+    assert!(p.extra_fd(4).is_none());
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+}
+
+#[test]
+fn redirect_raw_fd() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let tmpname = tmpdir.path().join("output");
+    let file = fs::File::create(&tmpname).unwrap();
+    let fd = file.as_raw_fd();
+    let mut p = Popen::create(
+        &["sh", "-c", "echo foo; echo bar >&3"],
+        PopenConfig {
+            stdout: Redirection::RawFd(fd),
+            extra_fds: vec![(3, Redirection::RawFd(fd))],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.wait().unwrap().success());
+    // the caller's descriptor is still open
+    (&file).write_all(b"baz\n").unwrap();
+    assert_eq!(fs::read_to_string(&tmpname).unwrap(), "foo\nbar\nbaz\n");
+}
+
+#[test]
+fn extra_fd_file() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let path = tmpdir.path().join("input");
+    fs::write(&path, "data").unwrap();
+    let c = Exec::cmd("sh")
+        .args(&["-c", "cat <&5"])
+        .redirect_fd(5, Redirection::File(fs::File::open(&path).unwrap()))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "data");
+}
+
+#[test]
+fn extra_fd_standard_stream() {
+    let config = PopenConfig {
+        extra_fds: vec![(1, Redirection::Pipe)],
+        ..Default::default()
+    };
+    assert!(matches!(
+        Popen::create(&["true"], config),
+        Err(PopenError::LogicError(_))
+    ));
+}
+
+#[test]
+fn bytes_available() {
+    let mut p = Popen::create(
+        &["printf", "foo"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.wait().unwrap();
+    let stdout = p.stdout.as_ref().unwrap();
+    assert_eq!(crate::posix::bytes_available(stdout).unwrap(), 3);
+    let mut buf = [0u8; 3];
+    (&*stdout).read_exact(&mut buf).unwrap();
+    assert_eq!(crate::posix::bytes_available(stdout).unwrap(), 0);
+}
+
+#[test]
+fn warn_unread_output() {
+    lazy_static! {
+        static ref WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    }
+    fn record(msg: &str) {
+        WARNINGS.lock().unwrap().push(msg.to_owned());
+    }
+    let config = || PopenConfig {
+        stdout: Redirection::Pipe,
+        warn_unread_output: Some(record),
+        ..Default::default()
+    };
+
+    // output that was read doesn't warn
+    let mut p = Popen::create(&["printf", "foo"], config()).unwrap();
+    p.communicate(None).unwrap();
+    p.wait().unwrap();
+    drop(p);
+    assert!(WARNINGS.lock().unwrap().is_empty());
+
+    let mut p = Popen::create(&["printf", "foo"], config()).unwrap();
+    p.wait().unwrap();
+    drop(p);
+    let warnings = WARNINGS.lock().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("3 unread bytes in its stdout pipe"));
+}
+
+#[test]
+fn warn_unread_output_default() {
+    // on by default in debug builds only
+    let config = PopenConfig::default();
+    assert_eq!(config.warn_unread_output.is_some(), cfg!(debug_assertions));
+}
+
+#[test]
+fn recv_fd() {
+    // send the write end of a pipe over a socket pair, as a child would
+    let (sender, receiver) = crate::posix::socketpair_cloexec().unwrap();
+    let (pipe_read, pipe_write) = crate::posix::pipe().unwrap();
+    unsafe {
+        let mut byte = [b'x'];
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+            iov_len: 1,
+        };
+        let mut control = [0u64; 8];
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = libc::CMSG_SPACE(4) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(4) as _;
+        *(libc::CMSG_DATA(cmsg) as *mut i32) = pipe_write.as_raw_fd();
+        assert_eq!(libc::sendmsg(sender.as_raw_fd(), &msg, 0), 1);
+    }
+    drop(pipe_write);
+    let mut received = crate::posix::recv_fd(&receiver).unwrap().unwrap();
+    received.write_all(b"hello").unwrap();
+    drop(received);
+    drop(sender);
+    assert!(crate::posix::recv_fd(&receiver).unwrap().is_none());
+    let mut content = String::new();
+    (&pipe_read).read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hello");
+}
+
+#[test]
+fn recv_fd_without_socket() {
+    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    assert!(matches!(p.recv_fd(3), Err(PopenError::LogicError(_))));
+    p.wait().unwrap();
+}
+
+#[test]
+fn exit_status_signal_name() {
+    assert_eq!(ExitStatus::Signaled(15).signal_name(), Some("SIGTERM"));
+    assert_eq!(ExitStatus::Signaled(9).signal_name(), Some("SIGKILL"));
+    assert_eq!(ExitStatus::Signaled(0).signal_name(), None);
+    assert_eq!(ExitStatus::Exited(15).signal_name(), None);
+    assert_eq!(ExitStatus::Signaled(15).to_string(), "signal 15 (SIGTERM)");
+    assert_eq!(ExitStatus::Exited(1).to_string(), "exit code 1");
+}
+
+#[test]
+fn exit_status_queries() {
+    const STATUS: ExitStatus = ExitStatus::Signaled(9);
+    const SIGNAL: Option<u8> = STATUS.signal();
+    assert_eq!(SIGNAL, Some(9));
+    assert_eq!(STATUS.code(), None);
+    assert!(!STATUS.success());
+    assert_eq!(ExitStatus::Exited(3).code(), Some(3));
+    assert_eq!(ExitStatus::Exited(3).signal(), None);
+    assert!(ExitStatus::Exited(0).success());
+    assert_eq!(ExitStatus::Undetermined.code(), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn namespaces() {
+    let config = PopenConfig {
+        stdout: Redirection::Pipe,
+        namespaces: libc::CLONE_NEWUSER | libc::CLONE_NEWPID,
+        ..Default::default()
+    };
+    let mut p = match Popen::create(&["sh", "-c", "echo $$"], config) {
+        Ok(p) => p,
+        // unprivileged user namespaces are disabled on this system
+        Err(PopenError::SpawnError(ref e)) if e.raw_os_error() == Some(libc::EPERM) => return,
+        Err(e) => panic!("{}", e),
+    };
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "1\n");
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+}
+
+#[test]
+fn spawn_retry() {
+    let is_eagain = |e: &io::Error| e.raw_os_error() == Some(libc::EAGAIN);
+    let retry = Some((3, Duration::from_millis(1)));
+    let mut calls = 0;
+    let result = crate::popen::retry_spawn(retry, is_eagain, || {
+        calls += 1;
+        if calls < 3 {
+            Err(io::Error::from_raw_os_error(libc::EAGAIN))
+        } else {
+            Ok(calls)
+        }
+    });
+    assert_eq!(result.unwrap(), 3);
+
+    // permanent errors are not retried
+    let mut calls = 0;
+    let result = crate::popen::retry_spawn(retry, is_eagain, || -> io::Result<()> {
+        calls += 1;
+        Err(io::Error::from_raw_os_error(libc::ENOENT))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+
+    // the number of retries is bounded
+    let mut calls = 0;
+    let result = crate::popen::retry_spawn(retry, is_eagain, || -> io::Result<()> {
+        calls += 1;
+        Err(io::Error::from_raw_os_error(libc::EAGAIN))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 4);
+}
+
+#[test]
+fn spawn_retry_config() {
+    let mut p = Popen::create(
+        &["true"],
+        PopenConfig {
+            spawn_retry: Some((5, Duration::from_millis(10))),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+}
+
+#[test]
+fn cwd_fd() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let dir = tmpdir.path().join("before");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("marker"), "found").unwrap();
+    let dir_file = fs::File::open(&dir).unwrap();
+    // The child must find the directory even after it has been renamed.
+    fs::rename(&dir, tmpdir.path().join("after")).unwrap();
+    let mut p = Popen::create(
+        &["cat", "marker"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            cwd_fd: Some(dir_file.as_raw_fd()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "found");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn cwd_fd_with_cwd() {
+    let dir_file = fs::File::open("/").unwrap();
+    match Popen::create(
+        &["true"],
+        PopenConfig {
+            cwd: Some("/".into()),
+            cwd_fd: Some(dir_file.as_raw_fd()),
+            ..Default::default()
+        },
+    ) {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn require_absolute() {
+    let config = || PopenConfig {
+        require_absolute: true,
+        ..Default::default()
+    };
+    match Popen::create(&["true"], config()) {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+    let mut p = Popen::create(&["/bin/sh", "-c", "true"], config()).unwrap();
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn fork_run() {
+    // the closures don't allocate or take locks
+    let mut p = unsafe { Popen::fork_run(|| 7, PopenConfig::default()) }.unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(7));
+
+    let mut p = unsafe {
+        Popen::fork_run(
+            || {
+                // the child's stdout is the pipe set up by the config
+                let msg = b"from child";
+                libc::write(1, msg.as_ptr() as *const libc::c_void, msg.len());
+                0
+            },
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                ..Default::default()
+            },
+        )
+    }
+    .unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "from child");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn exec_prefix_output() {
+    // the prefixed output goes to the stdout of the current process, so
+    // run the command in a test process whose stdout is a pipe
+    let c = isolated("exec_prefix_output_isolated").capture().unwrap();
+    assert_isolated_ok(&c);
+    assert!(c.stdout_str().contains("\n[x] a\n[x] b\n[x] c"));
+
+    // captured output is not prefixed
+    let c = Exec::cmd("echo")
+        .arg("foo")
+        .prefix_output("[x] ")
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\n");
+}
+
+#[test]
+#[ignore]
+fn exec_prefix_output_isolated() {
+    if !in_isolation() {
+        return;
+    }
+    let status = Exec::cmd("sh")
+        .args(&["-c", "printf 'a\\nb\\n'; printf c"])
+        .prefix_output("[x] ")
+        .join()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn exec_capture_and_tee() {
+    // the output is forwarded to the stdout of the current process, so
+    // run the command in a test process whose stdout is a pipe
+    let c = isolated("exec_capture_and_tee_isolated").capture().unwrap();
+    assert_isolated_ok(&c);
+    assert!(c.stdout_str().contains("\nfoo\nbar\n"));
+}
+
+#[test]
+#[ignore]
+fn exec_capture_and_tee_isolated() {
+    if !in_isolation() {
+        return;
+    }
+    let c = Exec::cmd("sh")
+        .args(&["-c", "echo foo; echo bar"])
+        .capture_and_tee()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo\nbar\n");
+}
+
+#[test]
+fn close_fds_list() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let file = fs::File::create(tmpdir.path().join("file")).unwrap();
+    // dup() creates descriptors that are inherited by default
+    let (kept, closed) = unsafe { (libc::dup(file.as_raw_fd()), libc::dup(file.as_raw_fd())) };
+    let script = format!("echo kept >&{} && echo closed >&{}", kept, closed);
+    let mut p = Popen::create(
+        &["sh", "-c", &script],
+        PopenConfig {
+            close_fds_list: vec![closed, 1000],
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let status = p.wait().unwrap();
+    unsafe {
+        libc::close(kept);
+        libc::close(closed);
+    }
+    assert!(!status.success());
+    assert_eq!(
+        fs::read_to_string(tmpdir.path().join("file")).unwrap(),
+        "kept\n"
+    );
+}
+
+#[test]
+fn close_fds() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let file = fs::File::create(tmpdir.path().join("file")).unwrap();
+    let extra = fs::File::create(tmpdir.path().join("extra")).unwrap();
+    let leaked = unsafe { libc::dup(file.as_raw_fd()) };
+    let script = format!("echo out; echo extra >&3; echo leaked >&{}", leaked);
+    let c = Exec::cmd("sh")
+        .args(&["-c", &script])
+        .close_fds(true)
+        .redirect_fd(3, Redirection::File(extra))
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    unsafe {
+        libc::close(leaked);
+    }
+    assert!(!c.success());
+    assert_eq!(c.stdout_str(), "out\n");
+    assert_eq!(
+        fs::read_to_string(tmpdir.path().join("extra")).unwrap(),
+        "extra\n"
+    );
+    assert_eq!(fs::read_to_string(tmpdir.path().join("file")).unwrap(), "");
+}
+
+#[test]
+fn exec_barrier() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let mut children: Vec<_> = (0..3)
+        .map(|i| {
+            let marker = tmpdir.path().join(format!("marker{}", i));
+            Popen::create(
+                &["touch".as_ref(), marker.as_os_str()],
+                PopenConfig {
+                    exec_barrier: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 0);
+    for p in &mut children {
+        p.release().unwrap();
+    }
+    for p in &mut children {
+        assert!(p.wait().unwrap().success());
+    }
+    assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 3);
+    match children[0].release() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn exec_barrier_errors() {
+    // exec errors are reported by release()
+    let mut p = Popen::create(
+        &["/nosuchprogram"],
+        PopenConfig {
+            exec_barrier: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    match p.release() {
+        Err(PopenError::ExecError { kind, .. }) => assert_eq!(kind, ExecErrorKind::NotFound),
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+
+    // a child dropped without release exits without running the program
+    let tmpdir = TempDir::new("test").unwrap();
+    let marker = tmpdir.path().join("marker");
+    let p = Popen::create(
+        &["touch".as_ref(), marker.as_os_str()],
+        PopenConfig {
+            exec_barrier: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    drop(p);
+    assert!(!marker.exists());
+}
+
+#[test]
+fn pgid() {
+    // children start in a group of their own, which they can't be moved
+    // out of after exec
+    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    assert_eq!(p.pgid().unwrap(), p.pid().unwrap());
+    let own_pgid = unsafe { libc::getpgrp() } as u32;
+    let err = p.set_pgid(own_pgid).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+    p.kill().unwrap();
+    p.wait().unwrap();
+    assert_eq!(p.pgid().unwrap_err().raw_os_error(), Some(libc::ESRCH));
+
+    // a child that doesn't exec can be moved
+    let mut p = unsafe {
+        Popen::fork_run(
+            || {
+                libc::sleep(5);
+                0
+            },
+            PopenConfig::default(),
+        )
+    }
+    .unwrap();
+    p.set_pgid(own_pgid).unwrap();
+    assert_eq!(p.pgid().unwrap(), own_pgid);
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn send_signal_group() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let marker = tmpdir.path().join("marker");
+    let script = format!("(sleep 0.5; touch '{}') & wait", marker.display());
+    let mut p = Popen::create(&["sh", "-c", &script], PopenConfig::default()).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    p.send_signal_group(libc::SIGTERM).unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+    // the background process was signaled as well
+    std::thread::sleep(Duration::from_millis(700));
+    assert!(!marker.exists());
+    p.send_signal_group(libc::SIGTERM).unwrap();
+}
+
+#[test]
+fn setpgid_disabled() {
+    let mut p = Popen::create(
+        &["sleep", "5"],
+        PopenConfig {
+            setpgid: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let own_pgid = unsafe { libc::getpgrp() } as u32;
+    assert_eq!(p.pgid().unwrap(), own_pgid);
+    let err = p.send_signal_group(libc::SIGTERM).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ESRCH));
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn fork_run_setup_error() {
+    match unsafe {
+        Popen::fork_run(
+            || 0,
+            PopenConfig {
+                cwd: Some("/nosuchdir".into()),
+                ..Default::default()
+            },
+        )
+    } {
+        Err(PopenError::IoError(e)) => assert_eq!(e.raw_os_error(), Some(libc::ENOENT)),
+        other => panic!("expected IoError, got {:?}", other),
+    }
+}
+
+#[test]
+fn null_closed_std() {
+    // closes the standard descriptors of the process running it
+    let c = isolated("null_closed_std_isolated").capture().unwrap();
+    assert_isolated_ok(&c);
+}
+
+#[test]
+#[ignore]
+fn null_closed_std_isolated() {
+    if !in_isolation() {
+        return;
+    }
+    unsafe {
+        libc::close(0);
+        libc::close(2);
+    }
+    // run a shell that checks whether it got valid descriptors 0 and 2
+    let run_with_closed_std = |null_closed_std| {
+        let mut p = Popen::create(
+            &["sh", "-c", "exec 3<&0 4>&2 && echo ok"],
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                null_closed_std,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (out, _) = p.communicate(None).unwrap();
+        out.unwrap() == "ok\n"
+    };
+    assert!(run_with_closed_std(true));
+    assert!(!run_with_closed_std(false));
+    // the pipe reporting exec failure must not be taken over by the
+    // descriptors reopened to /dev/null
+    match Popen::create(
+        &["/nonexistent/program"],
+        PopenConfig {
+            null_closed_std: true,
+            ..Default::default()
+        },
+    ) {
+        Err(PopenError::ExecError { kind, .. }) => assert_eq!(kind, ExecErrorKind::NotFound),
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+}
+
+// Build an Exec that runs the ignored test `name` of this module in a
+// new instance of the test binary.  Tests that change process-wide
+// state, such as the standard streams or which children get reaped,
+// run there rather than in the test process, where other tests run in
+// parallel.  Forking the multi-threaded test process without exec'ing
+// is not an option, since the child could block forever on a lock held
+// by another thread at the time of the fork.  The isolated tests only
+// run when requested through the environment, and print the harness
+// summary, which callers check to make sure the test actually ran.
+fn isolated(name: &str) -> Exec {
+    let module = module_path!().splitn(2, "::").nth(1).unwrap();
+    Exec::cmd(std::env::current_exe().unwrap())
+        .arg(format!("{}::{}", module, name))
+        .arg("--exact")
+        .arg("--ignored")
+        .arg("--nocapture")
+        .arg("--test-threads=1")
+        .arg("-q")
+        .env("SUBPROCESS_ISOLATED_TEST", "1")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+}
+
+fn in_isolation() -> bool {
+    std::env::var_os("SUBPROCESS_ISOLATED_TEST").is_some()
+}
+
+fn assert_isolated_ok(c: &CaptureData) {
+    assert!(
+        c.exit_status.success() && c.stdout_str().contains("1 passed"),
+        "isolated test failed: {}{}",
+        c.stdout_str(),
+        c.stderr_str()
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn wait_tree() {
+    // wait_tree() reaps all children of the process, and the process
+    // becomes a subreaper, so run it in a process of its own
+    let c = isolated("wait_tree_isolated").capture().unwrap();
+    assert_isolated_ok(&c);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+#[ignore]
+fn wait_tree_isolated() {
+    if !in_isolation() {
+        return;
+    }
+    let tmpdir = TempDir::new("test").unwrap();
+    let marker = tmpdir.path().join("marker");
+    let script = format!("(sleep 0.5; touch '{}') & exit 3", marker.display());
+    crate::unix::set_child_subreaper(true).unwrap();
+    let mut p = Popen::create(&["/bin/sh", "-c", &script], PopenConfig::default()).unwrap();
+    assert_eq!(p.wait_tree().unwrap(), ExitStatus::Exited(3));
+    // the orphaned background process has finished as well
+    assert!(marker.exists());
+}
+
+#[test]
+fn stdout_auto() {
+    let is_terminal = unsafe { libc::isatty(1) } == 1;
+    let mut p = Exec::cmd("true").stdout_auto().popen().unwrap();
+    assert_eq!(p.has_stdout_pipe(), !is_terminal);
+    p.wait().unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn create_waits_for_exec() {
+    // once create() returns, the child runs the program rather than a
+    // copy of the test binary
+    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    let comm = fs::read_to_string(format!("/proc/{}/comm", p.pid().unwrap())).unwrap();
+    assert_eq!(comm, "sleep\n");
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn temp_file_private() {
+    let tmp = crate::communicate::TempFile::create().unwrap();
+    let mode = fs::metadata(&tmp.path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn args_via_file_private() {
+    // the arguments may be secrets, so other users must not read them
+    let c = Exec::cmd("sh")
+        .args(&["-c", r#"ls -l "${0#@}" | cut -c1-10"#])
+        .args_via_file(&["secret"])
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "-rw-------\n");
+}
+
+#[test]
+fn resolved_path_follows_exec() {
+    // changes the PATH of the process running it
+    let c = isolated("resolved_path_follows_exec_isolated")
+        .capture()
+        .unwrap();
+    assert_isolated_ok(&c);
+}
+
+#[test]
+#[ignore]
+fn resolved_path_follows_exec_isolated() {
+    if !in_isolation() {
+        return;
+    }
+    // an executable file that exec() refuses to run, found in the PATH
+    // ahead of the one it does run
+    let tmpdir = TempDir::new("test").unwrap();
+    let bad = tmpdir.path().join("bad");
+    let good = tmpdir.path().join("good");
+    for &(dir, content) in &[(&bad, "not a program\n"), (&good, "#!/bin/sh\nexit 0\n")] {
+        fs::create_dir(dir).unwrap();
+        let program = dir.join("program");
+        fs::write(&program, content).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    std::env::set_var("PATH", format!("{}:{}", bad.display(), good.display()));
+    let mut p = Popen::create(&["program"], PopenConfig::default()).unwrap();
+    assert!(p.wait().unwrap().success());
+    assert_eq!(p.resolved_path(), Some(&*good.join("program")));
+}
+
+#[test]
+fn pty_controlling_terminal() {
+    // the child sees a terminal, and can open it as /dev/tty
+    let mut p = Popen::create(
+        &["sh", "-c", "test -t 1 && echo tty; echo direct > /dev/tty"],
+        PopenConfig {
+            stdout: Redirection::Pty,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out, _err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "tty\r\ndirect\r\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn pty_merges_output() {
+    let mut p = Popen::create(
+        &["sh", "-c", "echo out; echo err >&2"],
+        PopenConfig {
+            stdout: Redirection::Pty,
+            stderr: Redirection::Pty,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.stderr.is_none());
+    let (out, err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "out\r\nerr\r\n");
+    assert!(err.is_none());
+}
+
+#[test]
+fn pty_size() {
+    // set the size before the program gets to query it
+    let mut p = Popen::create(
+        &["stty", "size"],
+        PopenConfig {
+            stdin: Redirection::Pty,
+            stdout: Redirection::Pty,
+            exec_barrier: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.set_pty_size(24, 132).unwrap();
+    p.release().unwrap();
+    let (out, _err) = p.communicate(Some("")).unwrap();
+    assert_eq!(out.unwrap(), "24 132\r\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn pty_resize() {
+    // resizing notifies the running program with SIGWINCH
+    let mut p = Popen::create(
+        &[
+            "sh",
+            "-c",
+            "trap 'stty size; exit' WINCH; echo ready; while :; do sleep 0.1; done",
+        ],
+        PopenConfig {
+            stdin: Redirection::Pty,
+            stdout: Redirection::Pty,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut ready = [0u8; 7];
+    p.stdout.as_ref().unwrap().read_exact(&mut ready).unwrap();
+    assert_eq!(&ready, b"ready\r\n");
+    p.set_pty_size(40, 100).unwrap();
+    let (out, _err) = p.communicate(Some("")).unwrap();
+    assert_eq!(out.unwrap(), "40 100\r\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn pty_size_without_pty() {
+    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    match p.set_pty_size(24, 80) {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+    p.wait().unwrap();
+}
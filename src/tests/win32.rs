@@ -1,9 +1,338 @@
-use crate::{ExitStatus, Popen, PopenConfig};
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::windows::PopenExt;
+use crate::{ConsoleMode, ExecErrorKind, ExitStatus, Popen, PopenConfig, PopenError, Redirection};
+
+use lazy_static::lazy_static;
+use tempdir::TempDir;
 
 #[test]
 fn err_terminate() {
     let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
     assert!(p.poll().is_none());
     p.terminate().unwrap();
-    assert!(p.wait().unwrap() == ExitStatus::Exited(1));
+    assert!(p.wait().unwrap() == ExitStatus::Exited(u32::MAX));
+    assert!(p.terminated());
+}
+
+#[test]
+fn exit_with_terminated_code() {
+    // exit(-1) reports the same code as a terminated process
+    let mut p = Popen::create(&["cmd", "/c", "exit", "/b", "-1"], PopenConfig::default()).unwrap();
+    assert!(p.wait().unwrap() == ExitStatus::Exited(u32::MAX));
+    assert!(!p.terminated());
+}
+
+#[test]
+fn kill_after_exit() {
+    let mut p = Popen::create(&["cmd", "/c", "exit", "/b", "3"], PopenConfig::default()).unwrap();
+    // let the process exit without reaping it
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    p.kill().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+    assert!(!p.terminated());
+}
+
+#[test]
+fn exit_code_above_i32_max() {
+    // 0xDEADBEEF doesn't fit in i32; cmd's exit /b takes the signed
+    // representation of the code.
+    let mut p = Popen::create(
+        &["cmd", "/c", "exit", "/b", "-559038737"],
+        PopenConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0xDEADBEEF));
+}
+
+#[test]
+fn exec_error_bad_executable() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let exe = tmpdir.path().join("notanexe.exe");
+    fs::write(&exe, "not an executable").unwrap();
+    match Popen::create(&[&exe], PopenConfig::default()) {
+        Err(PopenError::ExecError { kind, .. }) => assert_eq!(kind, ExecErrorKind::BadExecutable),
+        other => panic!("expected ExecError, got {:?}", other),
+    }
+}
+
+#[test]
+fn console_mode_hidden() {
+    let mut p = Popen::create(
+        &["cmd", "/c", "exit", "/b", "3"],
+        PopenConfig {
+            console_mode: ConsoleMode::Hidden,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+}
+
+#[test]
+fn creation_flags() {
+    use crate::windows::ExecExt;
+    use crate::Exec;
+    use winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
+
+    let mut p = Popen::create(
+        &["cmd", "/c", "exit", "/b", "3"],
+        PopenConfig {
+            console_mode: ConsoleMode::Hidden,
+            creation_flags: CREATE_NEW_PROCESS_GROUP,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+
+    let status = Exec::cmd("cmd")
+        .args(&["/c", "exit", "/b", "4"])
+        .creation_flags(CREATE_NEW_PROCESS_GROUP)
+        .join()
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(4));
+}
+
+#[test]
+fn creation_flags_suspended() {
+    use winapi::um::winbase::CREATE_SUSPENDED;
+
+    let mut p = Popen::create(
+        &["cmd", "/c", "exit", "/b", "3"],
+        PopenConfig {
+            creation_flags: CREATE_SUSPENDED,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p
+        .wait_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+    p.release().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+    match p.release() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("expected LogicError, got {:?}", other),
+    }
+}
+
+#[test]
+fn exec_barrier() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let mut children: Vec<_> = (0..3)
+        .map(|i| {
+            Popen::create(
+                &["cmd", "/c", &format!("echo.> {}", i)],
+                PopenConfig {
+                    exec_barrier: true,
+                    cwd: Some(tmpdir.path().into()),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 0);
+    for p in &mut children {
+        p.release().unwrap();
+    }
+    for p in &mut children {
+        assert!(p.wait().unwrap().success());
+    }
+    assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 3);
+
+    // a child dropped without release is terminated without running
+    drop(
+        Popen::create(
+            &["cmd", "/c", "echo.> dropped"],
+            PopenConfig {
+                exec_barrier: true,
+                cwd: Some(tmpdir.path().into()),
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    );
+    assert!(!tmpdir.path().join("dropped").exists());
+}
+
+#[test]
+fn env_remove_case_insensitive() {
+    use crate::Exec;
+
+    // "set NAME" fails when no variable with that prefix is defined
+    let status = Exec::cmd("cmd")
+        .args(&["/c", "set TEST_ENV_REMOVE_WIN"])
+        .env("Test_Env_Remove_Win", "x")
+        .env_remove("TEST_ENV_REMOVE_WIN")
+        .stdout(crate::NullFile)
+        .join()
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(1));
+}
+
+#[test]
+fn env_remove_case_insensitive_unicode() {
+    use crate::Exec;
+
+    // case folding extends beyond ASCII
+    let status = Exec::cmd("cmd")
+        .args(&["/c", "set TEST_ÉNV_REMOVE_WIN"])
+        .env("Test_Énv_Remove_Win", "x")
+        .env_remove("TEST_éNV_REMOVE_WIN")
+        .stdout(crate::NullFile)
+        .join()
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(1));
+}
+
+#[test]
+fn env_dedup_case_insensitive_unicode() {
+    use crate::Exec;
+
+    // the environment block holds one entry per name, compared the same
+    // way as by env_remove
+    let out = Exec::cmd("cmd")
+        .args(&["/c", "set TEST_ÉNV_DEDUP_WIN"])
+        .env("Test_Énv_Dedup_Win", "x")
+        .env("TEST_éNV_DEDUP_WIN", "y")
+        .capture()
+        .unwrap()
+        .stdout_str();
+    // cmd prints the name in the console code page
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].ends_with("=y"));
+}
+
+// Run `f` with the process-wide limit on reader threads set to `max`,
+// restoring the default afterwards.  Tests changing the limit are
+// serialized, so that each sees its own limit.
+fn with_max_reader_threads<T>(max: usize, f: impl FnOnce() -> T) -> T {
+    lazy_static! {
+        static ref LIMIT_LOCK: Mutex<()> = Mutex::new(());
+    }
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            crate::set_max_reader_threads(0);
+        }
+    }
+    let _lock = LIMIT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    crate::set_max_reader_threads(max);
+    let _restore = Restore;
+    f()
+}
+
+fn popen_piped(cmd: &str, stderr: bool) -> Popen {
+    Popen::create(
+        &["cmd", "/c", cmd],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            stderr: if stderr {
+                Redirection::Pipe
+            } else {
+                Redirection::None
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+#[test]
+fn max_reader_threads() {
+    // communication needing more threads than the limit still proceeds
+    let (out, err) = with_max_reader_threads(1, || {
+        popen_piped("echo foo& echo bar 1>&2", true)
+            .communicate(None)
+            .unwrap()
+    });
+    assert_eq!(out.unwrap().trim(), "foo");
+    assert_eq!(err.unwrap().trim(), "bar");
+}
+
+#[test]
+fn max_reader_threads_limits() {
+    with_max_reader_threads(2, || {
+        // take up both threads for about two seconds
+        let mut busy = popen_piped("ping -n 3 127.0.0.1 >nul", true);
+        let mut busy_comm = busy.communicate_start(None);
+        let busy_reader = thread::spawn(move || busy_comm.read());
+        thread::sleep(Duration::from_millis(200));
+
+        // a communicator needing threads can't get them in time
+        let mut p = popen_piped("echo foo& echo bar 1>&2", true);
+        let mut comm = p
+            .communicate_start(None)
+            .limit_time(Duration::from_millis(100));
+        match comm.read() {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+
+        // one servicing a single stream without a time limit proceeds
+        // without a thread
+        let (out, _) = popen_piped("echo single", false).communicate(None).unwrap();
+        assert_eq!(out.unwrap().trim(), "single");
+
+        // once the threads are released, the first one proceeds too
+        busy_reader.join().unwrap().unwrap();
+        let (out, err) = comm.read_string().unwrap();
+        assert_eq!(out.unwrap().trim(), "foo");
+        assert_eq!(err.unwrap().trim(), "bar");
+    });
+}
+
+// CPU time consumed by the calling thread.
+fn thread_cpu_time() -> Duration {
+    use winapi::shared::minwindef::FILETIME;
+    use winapi::um::processthreadsapi::{GetCurrentThread, GetThreadTimes};
+
+    let mut t: [FILETIME; 4] = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        GetThreadTimes(
+            GetCurrentThread(),
+            &mut t[0],
+            &mut t[1],
+            &mut t[2],
+            &mut t[3],
+        )
+    };
+    assert!(ok != 0);
+    let ticks = |t: &FILETIME| (t.dwHighDateTime as u64) << 32 | t.dwLowDateTime as u64;
+    // kernel plus user time, in units of 100ns
+    Duration::from_nanos((ticks(&t[2]) + ticks(&t[3])) * 100)
+}
+
+#[test]
+fn read_stdout_timeout_doesnt_spin() {
+    // the child is silent for about two seconds, which the reader
+    // should spend asleep
+    let mut p = Popen::create(
+        &["cmd", "/c", "ping -n 3 127.0.0.1 >nul& echo done"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let cpu_before = thread_cpu_time();
+    let start = Instant::now();
+    let mut buf = [0u8; 16];
+    let n = p
+        .read_stdout_timeout(&mut buf, Duration::from_secs(30))
+        .unwrap()
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert!(thread_cpu_time() - cpu_before < Duration::from_millis(100));
+    assert_eq!(std::str::from_utf8(&buf[..n]).unwrap().trim(), "done");
+    p.wait().unwrap();
 }
@@ -1,27 +1,39 @@
 #![allow(non_snake_case, non_camel_case_types)]
 
+use std::cmp;
 use std::fs::File;
 use std::io::{Error, Result};
 
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::iter;
 use std::mem;
-use std::os::windows::ffi::OsStrExt;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
 use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::process;
 use std::ptr;
 use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 use winapi;
-use winapi::shared::minwindef::{BOOL, DWORD, LPVOID};
+use winapi::ctypes::c_int;
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPVOID, TRUE};
 use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
-use winapi::um::minwinbase::{LPSECURITY_ATTRIBUTES, SECURITY_ATTRIBUTES};
+use winapi::um::minwinbase::{LPSECURITY_ATTRIBUTES, OVERLAPPED, SECURITY_ATTRIBUTES};
 use winapi::um::processthreadsapi::{CreateProcessW, PROCESS_INFORMATION, STARTUPINFOW};
 use winapi::um::winbase::CREATE_UNICODE_ENVIRONMENT;
 use winapi::um::winnt::PHANDLE;
-use winapi::um::{handleapi, namedpipeapi, processenv, processthreadsapi, synchapi};
+use winapi::um::{
+    consoleapi, fileapi, handleapi, ioapiset, memoryapi, namedpipeapi, processenv,
+    processthreadsapi, stringapiset, synchapi, wincon,
+};
 
-pub use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_BAD_PATHNAME};
+pub use winapi::shared::winerror::{
+    ERROR_ACCESS_DENIED, ERROR_BAD_EXE_FORMAT, ERROR_BAD_PATHNAME, ERROR_BROKEN_PIPE,
+    ERROR_FILE_NOT_FOUND, ERROR_IO_PENDING, ERROR_NOT_ENOUGH_MEMORY, ERROR_NO_SYSTEM_RESOURCES,
+    ERROR_OPERATION_ABORTED, ERROR_PATH_NOT_FOUND,
+};
 pub const STILL_ACTIVE: u32 = 259;
 
 use crate::os_common::StandardStream;
@@ -54,6 +66,11 @@ impl FromRawHandle for Handle {
 
 pub const HANDLE_FLAG_INHERIT: u32 = 1;
 pub const STARTF_USESTDHANDLES: DWORD = winapi::um::winbase::STARTF_USESTDHANDLES;
+pub use winapi::um::winbase::{
+    CREATE_NEW_CONSOLE, CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW, CREATE_SUSPENDED,
+    DETACHED_PROCESS,
+};
+pub use winapi::um::wincon::CTRL_BREAK_EVENT;
 
 fn check(status: BOOL) -> Result<()> {
     if status != 0 {
@@ -94,6 +111,200 @@ pub fn CreatePipe(inherit_handle: bool) -> Result<(File, File)> {
     Ok(unsafe { (File::from_raw_handle(r), File::from_raw_handle(w)) })
 }
 
+pub fn PeekNamedPipe(handle: &File) -> Result<usize> {
+    let mut avail: DWORD = 0;
+    check(unsafe {
+        namedpipeapi::PeekNamedPipe(
+            handle.as_raw_handle(),
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+            &mut avail,
+            ptr::null_mut(),
+        )
+    })?;
+    Ok(avail as usize)
+}
+
+// Create a pipe whose read end supports overlapped IO, which CreatePipe
+// can't do.  Like CreatePipe, this creates a uniquely named pipe, but
+// opens its server end with FILE_FLAG_OVERLAPPED.  The write end, which
+// is inherited by the child, is an ordinary synchronous handle.
+pub fn CreateOverlappedPipe() -> Result<(File, File)> {
+    use winapi::um::fileapi::OPEN_EXISTING;
+    use winapi::um::winbase::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_INBOUND,
+        PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+    };
+    use winapi::um::winnt::GENERIC_WRITE;
+    static PIPE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut attempts = 0;
+    let (read, name) = loop {
+        let name = to_nullterm(OsStr::new(&format!(
+            r"\\.\pipe\__subprocess_pipe__.{}.{}",
+            process::id(),
+            PIPE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        )));
+        let raw = unsafe {
+            namedpipeapi::CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_INBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE | FILE_FLAG_OVERLAPPED,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                4096,
+                4096,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        match check_handle(raw) {
+            Ok(raw) => break (unsafe { File::from_raw_handle(raw) }, name),
+            // the name is taken, most likely by an unrelated process
+            // that happens to use the same naming scheme
+            Err(ref e) if e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) && attempts < 10 => {
+                attempts += 1
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: ptr::null_mut(),
+        bInheritHandle: TRUE,
+    };
+    let write = check_handle(unsafe {
+        fileapi::CreateFileW(
+            name.as_ptr(),
+            GENERIC_WRITE,
+            0,
+            &mut attributes as LPSECURITY_ATTRIBUTES,
+            OPEN_EXISTING,
+            0,
+            ptr::null_mut(),
+        )
+    })?;
+    Ok((read, unsafe { File::from_raw_handle(write) }))
+}
+
+fn CreateEvent() -> Result<Handle> {
+    let raw = unsafe { synchapi::CreateEventW(ptr::null_mut(), TRUE, FALSE, ptr::null()) };
+    if raw.is_null() {
+        Err(Error::last_os_error())
+    } else {
+        Ok(Handle(raw))
+    }
+}
+
+// Read from a pipe, giving up if no data arrives within timeout.
+//
+// The pipe must support overlapped IO, as does the read end of
+// CreateOverlappedPipe.  The read is started as an overlapped operation
+// and the thread sleeps on its event until the read completes or the
+// timeout expires.  A timed-out read is cancelled, and waited for
+// before returning because it might have completed in the meantime,
+// and because the kernel may write to buf and overlapped until then.
+pub fn ReadPipeTimeout(file: &File, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>> {
+    let handle = file.as_raw_handle();
+    let event = CreateEvent()?;
+    let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+    overlapped.hEvent = event.as_raw_handle();
+    let len = cmp::min(buf.len(), DWORD::MAX as usize) as DWORD;
+    let started = unsafe {
+        fileapi::ReadFile(
+            handle,
+            buf.as_mut_ptr() as LPVOID,
+            len,
+            ptr::null_mut(),
+            &mut overlapped,
+        )
+    };
+    if started == 0 {
+        let err = Error::last_os_error();
+        match err.raw_os_error() {
+            Some(code) if code == ERROR_IO_PENDING as i32 => (),
+            // the writer has closed the pipe
+            Some(code) if code == ERROR_BROKEN_PIPE as i32 => return Ok(Some(0)),
+            _ => return Err(err),
+        }
+    }
+    let waited = WaitForSingleObject(&event, Some(timeout));
+    if !matches!(waited, Ok(WaitEvent::OBJECT_0)) {
+        unsafe {
+            ioapiset::CancelIoEx(handle, &mut overlapped);
+        }
+    }
+    let mut nread: DWORD = 0;
+    let finished =
+        check(unsafe { ioapiset::GetOverlappedResult(handle, &mut overlapped, &mut nread, TRUE) });
+    waited?;
+    match finished {
+        Ok(()) => Ok(Some(nread as usize)),
+        Err(ref e) if e.raw_os_error() == Some(ERROR_OPERATION_ABORTED as i32) => Ok(None),
+        Err(ref e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(Some(0)),
+        Err(e) => Err(e),
+    }
+}
+
+// A read-only view of part of a file.
+pub struct FileMapping {
+    view: LPVOID,
+    len: usize,
+    _mapping: Handle,
+}
+
+impl FileMapping {
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.view as *const u8, self.len) }
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        unsafe {
+            memoryapi::UnmapViewOfFile(self.view);
+        }
+    }
+}
+
+// Map `len` bytes of `file` starting at `offset`, which must be a
+// multiple of the allocation granularity.
+pub fn MapViewOfFile(file: &File, offset: u64, len: usize) -> Result<FileMapping> {
+    use winapi::um::memoryapi::FILE_MAP_READ;
+    use winapi::um::winnt::PAGE_READONLY;
+    let raw = unsafe {
+        memoryapi::CreateFileMappingW(
+            file.as_raw_handle(),
+            ptr::null_mut(),
+            PAGE_READONLY,
+            0,
+            0,
+            ptr::null(),
+        )
+    };
+    if raw.is_null() {
+        return Err(Error::last_os_error());
+    }
+    let mapping = Handle(raw);
+    let view = unsafe {
+        memoryapi::MapViewOfFile(
+            raw,
+            FILE_MAP_READ,
+            (offset >> 32) as DWORD,
+            offset as DWORD,
+            len,
+        )
+    };
+    if view.is_null() {
+        return Err(Error::last_os_error());
+    }
+    Ok(FileMapping {
+        view,
+        len,
+        _mapping: mapping,
+    })
+}
+
 pub fn SetHandleInformation(handle: &File, dwMask: u32, dwFlags: u32) -> Result<()> {
     check(unsafe { handleapi::SetHandleInformation(handle.as_raw_handle(), dwMask, dwFlags) })?;
     Ok(())
@@ -110,7 +321,7 @@ pub fn CreateProcess(
     stdout: Option<RawHandle>,
     stderr: Option<RawHandle>,
     sinfo_flags: u32,
-) -> Result<(Handle, u64)> {
+) -> Result<(Handle, Handle, u64)> {
     let mut sinfo: STARTUPINFOW = unsafe { mem::zeroed() };
     sinfo.cb = mem::size_of::<STARTUPINFOW>() as DWORD;
     sinfo.hStdInput = stdin.unwrap_or(ptr::null_mut());
@@ -144,9 +355,9 @@ pub fn CreateProcess(
         )
     })?;
     unsafe {
-        drop(Handle::from_raw_handle(pinfo.hThread));
         Ok((
             Handle::from_raw_handle(pinfo.hProcess),
+            Handle::from_raw_handle(pinfo.hThread),
             pinfo.dwProcessId as u64,
         ))
     }
@@ -202,6 +413,29 @@ pub fn WaitForSingleObject(handle: &Handle, mut timeout: Option<Duration>) -> Re
     }
 }
 
+pub fn ResumeThread(handle: &Handle) -> Result<()> {
+    let prev_count = unsafe { processthreadsapi::ResumeThread(handle.as_raw_handle()) };
+    if prev_count == u32::MAX {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn QueryFullProcessImageName(handle: &Handle) -> Result<OsString> {
+    // the maximum length of an extended-length path
+    let mut buf = vec![0u16; 32768];
+    let mut size = buf.len() as DWORD;
+    check(unsafe {
+        winapi::um::winbase::QueryFullProcessImageNameW(
+            handle.as_raw_handle(),
+            0,
+            buf.as_mut_ptr(),
+            &mut size,
+        )
+    })?;
+    Ok(OsString::from_wide(&buf[..size as usize]))
+}
+
 pub fn GetExitCodeProcess(handle: &Handle) -> Result<u32> {
     let mut exit_code = 0u32;
     check(unsafe {
@@ -214,6 +448,42 @@ pub fn TerminateProcess(handle: &Handle, exit_code: u32) -> Result<()> {
     check(unsafe { processthreadsapi::TerminateProcess(handle.as_raw_handle(), exit_code) })
 }
 
+// Compare UTF-16 strings by code point, after converting them to upper
+// case with the operating system's tables if `ignore_case` is set.
+// This is how Windows compares environment variable names.
+pub fn CompareStringOrdinal(a: &[u16], b: &[u16], ignore_case: bool) -> Result<cmp::Ordering> {
+    const CSTR_LESS_THAN: c_int = 1;
+    const CSTR_EQUAL: c_int = 2;
+    const CSTR_GREATER_THAN: c_int = 3;
+    let result = unsafe {
+        stringapiset::CompareStringOrdinal(
+            a.as_ptr(),
+            a.len() as c_int,
+            b.as_ptr(),
+            b.len() as c_int,
+            if ignore_case { TRUE } else { FALSE },
+        )
+    };
+    match result {
+        CSTR_LESS_THAN => Ok(cmp::Ordering::Less),
+        CSTR_EQUAL => Ok(cmp::Ordering::Equal),
+        CSTR_GREATER_THAN => Ok(cmp::Ordering::Greater),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+// Compare environment variable names the way Windows does, falling
+// back to comparing them exactly if the system can't compare them.
+pub fn compare_env_keys(a: &OsStr, b: &OsStr) -> cmp::Ordering {
+    let a: Vec<u16> = a.encode_wide().collect();
+    let b: Vec<u16> = b.encode_wide().collect();
+    CompareStringOrdinal(&a, &b, true).unwrap_or_else(|_| a.cmp(&b))
+}
+
+pub fn GenerateConsoleCtrlEvent(ctrl_event: u32, process_group_id: u32) -> Result<()> {
+    check(unsafe { wincon::GenerateConsoleCtrlEvent(ctrl_event, process_group_id) })
+}
+
 unsafe fn GetStdHandle(which: StandardStream) -> Result<RawHandle> {
     // private/unsafe because the raw handle it returns must be
     // duplicated or leaked before converting to an owned Handle.
@@ -227,6 +497,17 @@ unsafe fn GetStdHandle(which: StandardStream) -> Result<RawHandle> {
     Ok(raw_handle)
 }
 
+pub fn IsConsole(which: StandardStream) -> bool {
+    unsafe {
+        let raw = match GetStdHandle(which) {
+            Ok(raw) => raw,
+            Err(_) => return false,
+        };
+        let mut mode: DWORD = 0;
+        consoleapi::GetConsoleMode(raw, &mut mode as *mut DWORD) != 0
+    }
+}
+
 pub fn make_standard_stream(which: StandardStream) -> Result<Rc<File>> {
     unsafe {
         let raw = GetStdHandle(which)?;